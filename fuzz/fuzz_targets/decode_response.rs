@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xdcc_request::Response;
+
+fuzz_target!(|data: &str| {
+    if let Some(response) = Response::decode(data) {
+        assert!(!response.filename.is_empty());
+    }
+});
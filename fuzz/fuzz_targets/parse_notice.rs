@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xdcc_request::parser::throttle_delay;
+use xdcc_request::{ChannelRules, QueueStatus};
+
+fuzz_target!(|data: &str| {
+    // the queue notices are never taken for throttle notices
+    if QueueStatus::parse(data).is_some() {
+        assert_eq!(throttle_delay(data), None);
+    }
+    let rules = ChannelRules::parse(data);
+    assert_eq!(rules.merge(rules), rules);
+});
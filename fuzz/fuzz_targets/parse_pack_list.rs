@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xdcc_request::PackAnnouncement;
+
+fuzz_target!(|data: &str| {
+    for line in data.lines() {
+        if let Some(pack) = PackAnnouncement::parse(line) {
+            assert!(!pack.filename.is_empty());
+        }
+    }
+});
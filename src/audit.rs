@@ -0,0 +1,305 @@
+//! History of the requests, persisted in a SQLite database.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::Connection;
+use rusqlite::types::{Value, ValueRef};
+
+use crate::{PackId, RequestInfo, Response};
+
+/// Outcome of a request, as stored in the audit log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Identifier of the request within its process.
+    pub request_id: u64,
+    /// Start of the request, since the Unix epoch.
+    pub started_at: Duration,
+    /// End of the request, since the Unix epoch.
+    pub finished_at: Duration,
+    /// IRC server address.
+    pub server: String,
+    /// IRC channel joined.
+    pub channel: String,
+    /// Bot nickname the request was sent to.
+    pub botname: String,
+    /// XDCC pack identifier, usually its number.
+    pub pack: PackId,
+    /// Name of the offered file, when the request succeeded.
+    pub filename: Option<String>,
+    /// Size of the offered file in bytes, when the request succeeded.
+    pub filesize: Option<u64>,
+    /// Description of the error, when the request failed.
+    pub error: Option<String>,
+    /// Number of bytes received by the transfer of the offered file, when complete.
+    pub transferred: Option<u64>,
+    /// Description of the error, when the transfer of the offered file failed.
+    pub transfer_error: Option<String>,
+}
+
+/// Audit log storing every request executed by an engine, with its outcome.
+///
+/// The database is written at the end of each request, on the blocking threads of the
+/// runtime, then updated with the outcome of the transfers created by the
+/// [`Presence`](crate::Presence) of the request, with the `transfer` feature.
+#[derive(Debug)]
+pub struct AuditLog {
+    connection: Mutex<Connection>,
+}
+
+impl AuditLog {
+    /// Opens the audit log stored at the given path, creating it when needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or initialized.
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Opens an audit log kept in memory, lost when dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be initialized.
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(connection: Connection) -> rusqlite::Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS requests (
+                request_id INTEGER NOT NULL,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER NOT NULL,
+                server TEXT NOT NULL,
+                channel TEXT NOT NULL,
+                botname TEXT NOT NULL,
+                pack INTEGER NOT NULL,
+                filename TEXT,
+                filesize INTEGER,
+                error TEXT,
+                transferred INTEGER,
+                transfer_error TEXT
+            )",
+        )?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Stores the outcome of a request.
+    pub(crate) async fn record(
+        self: &Arc<Self>,
+        info: &RequestInfo,
+        started_at: SystemTime,
+        result: Result<&Response, &crate::Error>,
+    ) -> rusqlite::Result<()> {
+        let millis = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as i64)
+        };
+        // the names share the column of the numbers, stored as text
+        let pack = match info.pack {
+            PackId::Number(number) => Value::Integer(number as i64),
+            PackId::Name(ref name) => Value::Text(name.clone()),
+        };
+        let (filename, filesize, error) = match result {
+            Ok(response) => (
+                Value::Text(response.filename.clone()),
+                Value::Integer(response.filesize as i64),
+                Value::Null,
+            ),
+            Err(err) => (Value::Null, Value::Null, Value::Text(err.to_string())),
+        };
+        self.execute(
+            "INSERT INTO requests VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, NULL, NULL)",
+            vec![
+                Value::Integer(info.id.get() as i64),
+                Value::Integer(millis(started_at)),
+                Value::Integer(millis(SystemTime::now())),
+                Value::Text(info.server.to_string()),
+                Value::Text(info.channel.to_string()),
+                Value::Text(info.botname.to_string()),
+                pack,
+                filename,
+                filesize,
+                error,
+            ],
+        )
+        .await
+    }
+
+    /// Stores the outcome of the transfer of the file offered to a request, in its
+    /// last entry.
+    #[cfg(feature = "transfer")]
+    pub(crate) async fn record_transfer(
+        self: &Arc<Self>,
+        info: &RequestInfo,
+        result: Result<&crate::TransferStats, &crate::Error>,
+    ) -> rusqlite::Result<()> {
+        let (transferred, error) = match result {
+            Ok(stats) => (Value::Integer(stats.bytes as i64), Value::Null),
+            Err(err) => (Value::Null, Value::Text(err.to_string())),
+        };
+        self.execute(
+            "UPDATE requests SET transferred = ?1, transfer_error = ?2
+            WHERE rowid = (
+                SELECT MAX(rowid) FROM requests
+                WHERE request_id = ?3 AND server = ?4 AND botname = ?5
+            )",
+            vec![
+                transferred,
+                error,
+                Value::Integer(info.id.get() as i64),
+                Value::Text(info.server.to_string()),
+                Value::Text(info.botname.to_string()),
+            ],
+        )
+        .await
+    }
+
+    /// Executes the given statement on the blocking threads of the runtime, so the
+    /// writes to the disk don't hold the other tasks.
+    async fn execute(
+        self: &Arc<Self>,
+        sql: &'static str,
+        params: Vec<Value>,
+    ) -> rusqlite::Result<()> {
+        let log = Arc::clone(self);
+        let write = tokio::task::spawn_blocking(move || {
+            log.connection
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .execute(sql, rusqlite::params_from_iter(params))
+                .map(drop)
+        });
+        match write.await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// Returns the stored requests, from the oldest to the most recent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be read.
+    pub fn entries(&self) -> rusqlite::Result<Vec<AuditEntry>> {
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut statement = connection.prepare(
+            "SELECT request_id, started_at, finished_at, server, channel, botname, pack,
+                filename, filesize, error, transferred, transfer_error
+            FROM requests ORDER BY started_at, rowid",
+        )?;
+        let millis = |value: i64| Duration::from_millis(value as u64);
+        let entries = statement.query_map([], |row| {
+            Ok(AuditEntry {
+                request_id: row.get::<_, i64>(0)? as u64,
+                started_at: millis(row.get(1)?),
+                finished_at: millis(row.get(2)?),
+                server: row.get(3)?,
+                channel: row.get(4)?,
+                botname: row.get(5)?,
+                pack: match row.get_ref(6)? {
+                    ValueRef::Integer(number) => PackId::Number(number as u64),
+                    ValueRef::Text(name) => PackId::Name(String::from_utf8_lossy(name).into()),
+                    other => {
+                        return Err(rusqlite::Error::InvalidColumnType(
+                            6,
+                            "pack".into(),
+                            other.data_type(),
+                        ));
+                    }
+                },
+                filename: row.get(7)?,
+                filesize: row.get::<_, Option<i64>>(8)?.map(|value| value as u64),
+                error: row.get(9)?,
+                transferred: row.get::<_, Option<i64>>(10)?.map(|value| value as u64),
+                transfer_error: row.get(11)?,
+            })
+        })?;
+        entries.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use super::AuditLog;
+    use crate::test_util::local_response;
+    use crate::{Engine, OfferError};
+
+    #[tokio::test]
+    async fn should_store_requests() {
+        let log = Arc::new(AuditLog::open_in_memory().unwrap());
+        let engine = Engine::default();
+        let first = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let second = engine.create_request("irc.example.net", "#channel", "bot", "ubuntu");
+        let response = local_response("ubuntu.iso", 5000, 1024);
+        log.record(first.info(), SystemTime::now(), Ok(&response))
+            .await
+            .unwrap();
+        log.record(
+            second.info(),
+            SystemTime::now(),
+            Err(&OfferError::Timeout.into()),
+        )
+        .await
+        .unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].request_id, first.info().id.get());
+        assert_eq!(entries[0].filename.as_deref(), Some("ubuntu.iso"));
+        assert_eq!(entries[0].filesize, Some(1024));
+        assert_eq!(entries[0].pack, 1.into());
+        assert_eq!(entries[1].pack, "ubuntu".into());
+        assert_eq!(
+            entries[1].error.as_deref(),
+            Some("the bot didn't offer the file in time")
+        );
+        assert_eq!(entries[0].transferred, None);
+    }
+
+    #[cfg(feature = "transfer")]
+    #[tokio::test]
+    async fn should_store_transfer_outcome() {
+        use std::time::Duration;
+
+        let log = Arc::new(AuditLog::open_in_memory().unwrap());
+        let engine = Engine::default();
+        let first = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let second = engine.create_request("irc.example.net", "#channel", "bot", 2);
+        let response = local_response("ubuntu.iso", 5000, 1024);
+        for request in [&first, &second] {
+            log.record(request.info(), SystemTime::now(), Ok(&response))
+                .await
+                .unwrap();
+        }
+        let stats = crate::TransferStats {
+            position: 1024,
+            bytes: 1000,
+            duration: Duration::from_secs(1),
+            average_speed: 1000.0,
+            peak_speed: 1000.0,
+            resumes: 1,
+        };
+        log.record_transfer(first.info(), Ok(&stats)).await.unwrap();
+        log.record_transfer(second.info(), Err(&crate::Error::DeadlineExceeded))
+            .await
+            .unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries[0].transferred, Some(1000));
+        assert_eq!(entries[0].transfer_error, None);
+        assert_eq!(entries[1].transferred, None);
+        assert!(entries[1].transfer_error.is_some());
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use crate::Dialect;
+
+/// Settings of a known bot, registered on the engine with
+/// [`EngineBuilder::bot`](crate::EngineBuilder::bot).
+///
+/// The requests to the bot, on any server, use these settings instead of the ones of
+/// the engine, so its quirks don't have to be repeated on every request.
+///
+/// ```
+/// use std::time::Duration;
+/// use xdcc_request::{Bot, Dialect, Engine};
+///
+/// let engine = Engine::builder()
+///     .bot(
+///         "Bot",
+///         Bot::default()
+///             .with_alias("Bot|away")
+///             .with_channel("#bot-chat")
+///             .dialect(Dialect::Ctcp)
+///             .max_requests(1)
+///             .request_delay(Duration::from_secs(5)),
+///     )
+///     .build();
+/// let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Bot {
+    pub(crate) aliases: Vec<String>,
+    pub(crate) channels: Vec<String>,
+    pub(crate) dialect: Option<Dialect>,
+    pub(crate) max_requests: Option<usize>,
+    pub(crate) request_delay: Option<Duration>,
+}
+
+impl Bot {
+    /// Adds another nickname the bot answers with, like the one it takes when away.
+    pub fn with_alias(mut self, nickname: impl Into<String>) -> Self {
+        self.aliases.push(nickname.into());
+        self
+    }
+
+    /// Joins the given channel along with the channel of the request, for the bots
+    /// only serving the users present in their channels.
+    pub fn with_channel(mut self, channel: impl Into<String>) -> Self {
+        self.channels.push(channel.into());
+        self
+    }
+
+    /// Dialect of the requests to the bot, unless one is given with
+    /// [`Request::with_dialect`](crate::Request::with_dialect).
+    pub fn dialect(mut self, value: Dialect) -> Self {
+        self.dialect = Some(value);
+        self
+    }
+
+    /// Maximum number of requests executed concurrently with the bot, usually the number
+    /// of its slots per user.
+    pub fn max_requests(mut self, value: usize) -> Self {
+        self.max_requests = Some(value);
+        self
+    }
+
+    /// Delay waited after joining the channels before requesting the bot, instead of the
+    /// delays of the channel, network or engine.
+    pub fn request_delay(mut self, value: Duration) -> Self {
+        self.request_delay = Some(value);
+        self
+    }
+}
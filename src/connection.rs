@@ -0,0 +1,639 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker, ready};
+use std::time::Duration;
+
+use crate::error::Result;
+use futures_util::stream::FuturesUnordered;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use irc::proto::{Command, IrcCodec, Message};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+/// Default port of the IRC servers, without TLS.
+pub(crate) const DEFAULT_PORT: u16 = 6667;
+
+/// Default port of the IRC servers over TLS.
+pub(crate) const TLS_PORT: u16 = 6697;
+
+/// Delay between two connection attempts, as recommended by RFC 8305.
+pub(crate) const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Byte stream carrying the IRC messages, like a TCP, TLS or proxied stream.
+///
+/// It's implemented for all the async streams.
+pub trait Transport: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> Transport for T {}
+
+/// Future returned by [`Connector::connect`], and by `TlsUpgrader::upgrade` with the
+/// `starttls` feature.
+///
+/// It must be `Sync`, like the futures of the requests.
+pub type ConnectFuture<'a> =
+    Pin<Box<dyn Future<Output = io::Result<Box<dyn Transport>>> + Send + Sync + 'a>>;
+
+/// Establishes the connections to the IRC servers.
+///
+/// Implementing this trait allows using alternative transports, like proxied sockets,
+/// custom TLS stacks or in-memory streams for tests. The engine then neither resolves
+/// the hostnames nor applies the [`SocketOptions`] itself.
+pub trait Connector: Debug + Send + Sync {
+    /// Connects to the given port of the given IRC server.
+    fn connect<'a>(&'a self, host: &'a str, port: u16) -> ConnectFuture<'a>;
+}
+
+/// TCP options applied to the sockets opened by the engine.
+///
+/// The options left to `None` keep the default value of the operating system.
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    /// Disables the Nagle algorithm when `true`.
+    pub nodelay: Option<bool>,
+    /// Enables the TCP keepalive, sending probes after the connection stayed idle for this duration.
+    pub keepalive: Option<Duration>,
+    /// Enables the TCP keepalive, with this duration between the probes.
+    pub keepalive_interval: Option<Duration>,
+    /// Enables the TCP keepalive, closing the connection after this number of unanswered
+    /// probes, ignored on the platforms not supporting it.
+    pub keepalive_retries: Option<u32>,
+    /// Size of the send buffer, in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// Size of the receive buffer, in bytes.
+    pub recv_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    /// Applies the options to the given socket.
+    pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket = socket2::SockRef::from(stream);
+        if let Some(value) = self.nodelay {
+            socket.set_tcp_nodelay(value)?;
+        }
+        if let Some(keepalive) = self.tcp_keepalive() {
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        if let Some(value) = self.send_buffer_size {
+            socket.set_send_buffer_size(value)?;
+        }
+        if let Some(value) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(value)?;
+        }
+        Ok(())
+    }
+
+    /// Keepalive parameters, when any of them is set.
+    fn tcp_keepalive(&self) -> Option<socket2::TcpKeepalive> {
+        if self.keepalive.is_none()
+            && self.keepalive_interval.is_none()
+            && self.keepalive_retries.is_none()
+        {
+            return None;
+        }
+        let mut keepalive = socket2::TcpKeepalive::new();
+        if let Some(value) = self.keepalive {
+            keepalive = keepalive.with_time(value);
+        }
+        if let Some(value) = self.keepalive_interval {
+            keepalive = keepalive.with_interval(value);
+        }
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "windows",
+        ))]
+        if let Some(value) = self.keepalive_retries {
+            keepalive = keepalive.with_retries(value);
+        }
+        Some(keepalive)
+    }
+}
+
+/// Address family of the connections opened by the engine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// Both IPv4 and IPv6, following the Happy Eyeballs algorithm.
+    #[default]
+    Any,
+    /// IPv4 only, for hosts whose IPv6 routing is broken.
+    V4Only,
+    /// IPv6 only, for hosts tunneling only IPv6.
+    V6Only,
+}
+
+impl AddressFamily {
+    /// Whether the given address belongs to the family.
+    pub fn allows(self, address: IpAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::V4Only => address.is_ipv4(),
+            Self::V6Only => address.is_ipv6(),
+        }
+    }
+}
+
+/// Sorts the addresses by alternating the address families, starting with IPv6,
+/// as described in RFC 8305.
+fn interleave(addresses: &[IpAddr]) -> Vec<IpAddr> {
+    let (mut ipv6, mut ipv4): (Vec<IpAddr>, Vec<IpAddr>) =
+        addresses.iter().partition(|address| address.is_ipv6());
+    ipv6.reverse();
+    ipv4.reverse();
+
+    let mut result = Vec::with_capacity(addresses.len());
+    while !ipv6.is_empty() || !ipv4.is_empty() {
+        result.extend(ipv6.pop());
+        result.extend(ipv4.pop());
+    }
+    result
+}
+
+/// Connects to the first responding address, following the Happy Eyeballs algorithm.
+///
+/// A new connection attempt is started every `delay`, or as soon as the previous attempt fails,
+/// alternating between IPv6 and IPv4 addresses. The first established connection is returned
+/// and the other attempts are dropped.
+///
+/// # Errors
+///
+/// Returns the last connection error if all the attempts fail.
+pub(crate) async fn happy_eyeballs(
+    addresses: &[IpAddr],
+    port: u16,
+    delay: Duration,
+) -> io::Result<TcpStream> {
+    let mut remaining = interleave(addresses).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        if let Some(address) = remaining.next() {
+            attempts.push(TcpStream::connect(SocketAddr::new(address, port)));
+        } else if attempts.is_empty() {
+            return Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "no address to connect to")
+            }));
+        }
+
+        let next = if remaining.len() > 0 {
+            match tokio::time::timeout(delay, attempts.next()).await {
+                Ok(next) => next,
+                // no attempt finished in time, starting the next one
+                Err(_) => continue,
+            }
+        } else {
+            attempts.next().await
+        };
+
+        match next {
+            Some(Ok(stream)) => {
+                if let Ok(address) = stream.peer_addr() {
+                    debug!("connected to {address}");
+                }
+                return Ok(stream);
+            }
+            Some(Err(err)) => {
+                warn!("connection attempt failed, trying the next address: {err}");
+                last_error = Some(err);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Shared record of the messages exchanged on a connection.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Transcript(Arc<Mutex<Vec<String>>>);
+
+impl Transcript {
+    /// Records a message, prefixed with its direction.
+    fn record(&self, direction: &str, message: &Message) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(format!("{direction} {}", message.to_string().trim_end()));
+    }
+
+    /// Takes the recorded messages.
+    pub(crate) fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// A connection to an IRC server.
+///
+/// Incoming `PING` messages are answered automatically while the connection is polled.
+/// No background task is involved: dropping the connection closes the socket, after
+/// trying to send a `QUIT` message.
+pub(crate) struct Connection {
+    /// Set once the `QUIT` message has been sent.
+    closed: bool,
+    framed: Framed<Box<dyn Transport>, IrcCodec>,
+    /// Nickname the client identified with, once identified.
+    nickname: Option<String>,
+    /// Address of the server, unknown when connected by a custom [`Connector`].
+    peer_addr: Option<SocketAddr>,
+    /// `PONG` message waiting to be sent to the server.
+    pong: Option<Message>,
+    /// Whether the connection is encrypted, unknown when TLS wasn't attempted.
+    tls: Option<bool>,
+    /// Records the exchanged messages, when enabled.
+    transcript: Option<Transcript>,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(stringify!(Connection))
+            .field("pong", &self.pong)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Connection {
+    /// Wraps the given stream, encoding and decoding the messages with the encoding
+    /// of the given WHATWG label, like `utf-8` or `windows-1252`.
+    ///
+    /// The label is ignored without the `encoding` feature, using UTF-8.
+    pub(crate) fn new(
+        stream: impl Transport + 'static,
+        peer_addr: Option<SocketAddr>,
+        encoding: &str,
+    ) -> Result<Self> {
+        let stream: Box<dyn Transport> = Box::new(stream);
+        Ok(Self {
+            closed: false,
+            framed: Framed::new(stream, IrcCodec::new(encoding)?),
+            nickname: None,
+            peer_addr,
+            pong: None,
+            tls: None,
+            transcript: None,
+        })
+    }
+
+    /// Nickname the client identified with, if identified.
+    pub(crate) fn nickname(&self) -> Option<&str> {
+        self.nickname.as_deref()
+    }
+
+    /// Address of the server, if known.
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Reads the messages already received without waiting, answering the pending pings,
+    /// and returns whether the connection is still open.
+    ///
+    /// An idle connection isn't read, so the server may have closed it meanwhile.
+    pub(crate) fn is_alive(&mut self) -> bool {
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            match Pin::new(&mut *self).poll_next(&mut cx) {
+                Poll::Ready(Some(Ok(_))) => continue,
+                Poll::Ready(_) => return false,
+                Poll::Pending => return true,
+            }
+        }
+    }
+
+    /// Whether the connection is encrypted, with the TLS connector of its network or
+    /// the `STARTTLS` command, unknown when TLS wasn't attempted.
+    pub(crate) fn tls(&self) -> Option<bool> {
+        self.tls
+    }
+
+    /// Sets whether the connection is encrypted.
+    pub(crate) fn set_tls(&mut self, tls: bool) {
+        self.tls = Some(tls);
+    }
+
+    /// Records the messages exchanged from now on in the given transcript.
+    pub(crate) fn set_transcript(&mut self, transcript: Transcript) {
+        self.transcript = Some(transcript);
+    }
+
+    /// Notes a message about to be sent.
+    fn sending(&self, message: &Message) {
+        debug!("sending {}", message.to_string().trim_end());
+        if let Some(ref transcript) = self.transcript {
+            transcript.record(">>", message);
+        }
+    }
+
+    /// Buffers a command, without waiting for it to be sent.
+    async fn feed(&mut self, command: Command) -> Result<()> {
+        let message = Message::from(command);
+        self.sending(&message);
+        self.framed.feed(message).await?;
+        Ok(())
+    }
+
+    /// Sends a command to the server.
+    pub(crate) async fn send(&mut self, command: Command) -> Result<()> {
+        self.feed(command).await?;
+        self.framed.flush().await?;
+        Ok(())
+    }
+
+    /// Sends a QUIT message and waits for it to be flushed.
+    pub(crate) async fn quit(mut self) -> Result<()> {
+        self.closed = true;
+        self.send(Command::QUIT(None)).await
+    }
+
+    /// Sends a CAP END, NICK and USER to identify.
+    ///
+    /// The `sasl` capability is requested instead of ending the negotiation when
+    /// authenticating, the registration then waits for the CAP END.
+    pub(crate) async fn identify(
+        &mut self,
+        nickname: &str,
+        username: &str,
+        sasl: bool,
+    ) -> Result<()> {
+        use irc::proto::CapSubCommand;
+
+        let capabilities = if sasl {
+            Command::CAP(None, CapSubCommand::REQ, None, Some("sasl".to_owned()))
+        } else {
+            Command::CAP(None, CapSubCommand::END, None, None)
+        };
+        self.feed(capabilities).await?;
+        self.feed(Command::NICK(nickname.to_owned())).await?;
+        self.nickname = Some(nickname.to_owned());
+        self.send(Command::USER(
+            username.to_owned(),
+            "0".to_owned(),
+            username.to_owned(),
+        ))
+        .await
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // a partially sent message would be corrupted by the QUIT message
+        if self.closed || !self.framed.write_buffer().is_empty() {
+            return;
+        }
+        // best effort without waiting, the socket gets closed anyway
+        let mut cx = Context::from_waker(Waker::noop());
+        let _ = Pin::new(self.framed.get_mut()).poll_write(&mut cx, b"QUIT\r\n");
+    }
+}
+
+impl Stream for Connection {
+    type Item = Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.pong.is_some() {
+            ready!(Pin::new(&mut this.framed).poll_ready(cx))?;
+            if let Some(pong) = this.pong.take() {
+                this.sending(&pong);
+                Pin::new(&mut this.framed).start_send(pong)?;
+            }
+        }
+        // the flush doesn't need to complete before receiving the next messages
+        if let Poll::Ready(Err(err)) = Pin::new(&mut this.framed).poll_flush(cx) {
+            return Poll::Ready(Some(Err(err.into())));
+        }
+
+        let message = ready!(Pin::new(&mut this.framed).poll_next(cx));
+        if let Some(Ok(ref message)) = message {
+            debug!("received {}", message.to_string().trim_end());
+            if let Some(ref transcript) = this.transcript {
+                transcript.record("<<", message);
+            }
+        }
+        if let Some(Ok(Message {
+            command: Command::PING(ref server1, ref server2),
+            ..
+        })) = message
+        {
+            this.pong = Some(Message::from(Command::PONG(
+                server1.clone(),
+                server2.clone(),
+            )));
+        }
+        Poll::Ready(message.map(|res| res.map_err(Into::into)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use irc::proto::Command;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::AddressFamily;
+
+    #[test]
+    fn should_filter_address_family() {
+        let v4 = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        assert!(AddressFamily::Any.allows(v4) && AddressFamily::Any.allows(v6));
+        assert!(AddressFamily::V4Only.allows(v4) && !AddressFamily::V4Only.allows(v6));
+        assert!(!AddressFamily::V6Only.allows(v4) && AddressFamily::V6Only.allows(v6));
+    }
+
+    #[test]
+    fn should_interleave_address_families() {
+        let v4a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let v4b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let v6a = IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1));
+        assert_eq!(super::interleave(&[v4a, v4b, v6a]), vec![v6a, v4a, v4b]);
+    }
+
+    #[tokio::test]
+    async fn should_connect_to_the_reachable_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let stream = super::happy_eyeballs(
+            &[
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+            ],
+            port,
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.peer_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn should_apply_socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let options = super::SocketOptions {
+            nodelay: Some(true),
+            keepalive: Some(Duration::from_secs(60)),
+            keepalive_interval: Some(Duration::from_secs(10)),
+            keepalive_retries: Some(3),
+            send_buffer_size: None,
+            recv_buffer_size: Some(65536),
+        };
+        options.apply(&stream).unwrap();
+
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.tcp_nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(
+            socket.tcp_keepalive_interval().unwrap(),
+            Duration::from_secs(10)
+        );
+        assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+        assert!(socket.recv_buffer_size().unwrap() >= 65536);
+    }
+
+    #[tokio::test]
+    async fn should_fail_without_address() {
+        super::happy_eyeballs(&[], 6667, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+    }
+
+    async fn accept_and_read(listener: TcpListener) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut result = String::new();
+        socket.read_to_string(&mut result).await.unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn should_quit_when_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(accept_and_read(listener));
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        drop(super::Connection::new(stream, None, "utf-8").unwrap());
+
+        assert_eq!(server.await.unwrap(), "QUIT\r\n");
+    }
+
+    #[tokio::test]
+    async fn should_quit_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(accept_and_read(listener));
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        super::Connection::new(stream, None, "utf-8")
+            .unwrap()
+            .quit()
+            .await
+            .unwrap();
+
+        assert_eq!(server.await.unwrap(), "QUIT\r\n");
+    }
+
+    #[cfg(feature = "encoding")]
+    #[tokio::test]
+    async fn should_encode_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut result = Vec::new();
+            socket.read_to_end(&mut result).await.unwrap();
+            result
+        });
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut connection = super::Connection::new(stream, None, "windows-1252").unwrap();
+        connection
+            .send(Command::PRIVMSG(
+                "#channel".into(),
+                "!files café.mkv".into(),
+            ))
+            .await
+            .unwrap();
+        connection.quit().await.unwrap();
+
+        assert_eq!(
+            server.await.unwrap(),
+            b"PRIVMSG #channel :!files caf\xe9.mkv\r\nQUIT\r\n"
+        );
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn should_reject_unknown_encoding() {
+        let (stream, _) = tokio::io::duplex(64);
+        super::Connection::new(stream, None, "unknown").unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn should_record_transcript() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(b":irc.example.net NOTICE * :hello world\r\n")
+                .await
+                .unwrap();
+        });
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut connection = super::Connection::new(stream, None, "utf-8").unwrap();
+        let transcript = super::Transcript::default();
+        connection.set_transcript(transcript.clone());
+        connection
+            .send(Command::JOIN("#channel".into(), None, None))
+            .await
+            .unwrap();
+        connection.next().await.unwrap().unwrap();
+
+        assert_eq!(
+            transcript.take(),
+            vec![
+                ">> JOIN #channel".to_string(),
+                "<< :irc.example.net NOTICE * :hello world".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_answer_ping() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(b"PING :irc.example.net\r\n")
+                .await
+                .unwrap();
+            let mut buffer = [0u8; 64];
+            let size = socket.read(&mut buffer).await.unwrap();
+            String::from_utf8_lossy(&buffer[..size]).to_string()
+        });
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut connection = super::Connection::new(stream, None, "utf-8").unwrap();
+        let message = connection.next().await.unwrap().unwrap();
+        assert!(matches!(message.command, Command::PING(_, _)));
+        // polling again sends the PONG
+        let _ = tokio::time::timeout(Duration::from_millis(100), connection.next()).await;
+
+        assert_eq!(server.await.unwrap(), "PONG irc.example.net\r\n");
+    }
+}
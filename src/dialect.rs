@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use irc::proto::Command;
+
+use crate::{RequestInfo, Response};
+
+/// Form of the command requesting a file from a bot.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// `xdcc send #N` sent privately to the bot, the most common form.
+    #[default]
+    Xdcc,
+    /// `xdcc get #N` sent privately to the bot.
+    XdccGet,
+    /// `XDCC SEND #N` sent privately to the bot as a CTCP, for the bots ignoring the
+    /// plain messages.
+    Ctcp,
+    /// FServe trigger sent to the channel, like `!trigger filename`.
+    ///
+    /// The pack of the request is ignored, and only the offers of the given
+    /// filename are accepted, ignoring the ASCII case.
+    Trigger {
+        /// Trigger of the file server, like `!trigger`.
+        trigger: String,
+        /// Name of the requested file.
+        filename: String,
+    },
+    /// Tries [`Dialect::Xdcc`], [`Dialect::XdccGet`] then [`Dialect::Ctcp`], each within the
+    /// [`EngineBuilder::dialect_probe_timeout`](crate::EngineBuilder::dialect_probe_timeout),
+    /// until the bot offers the file.
+    ///
+    /// The dialect the bot answered is remembered for the lifetime of the engine, so the
+    /// following requests to this bot send it right away.
+    Auto,
+}
+
+impl Dialect {
+    /// Dialects tried in order by [`Dialect::Auto`].
+    pub(crate) const PROBED: [Self; 3] = [Self::Xdcc, Self::XdccGet, Self::Ctcp];
+
+    /// Command requesting the file of the given request.
+    pub(crate) fn command(&self, info: &RequestInfo) -> Command {
+        match self {
+            // the automatic dialect is resolved before sending, default to the common form
+            Self::Xdcc | Self::Auto => {
+                Command::PRIVMSG(info.botname.to_string(), format!("xdcc send {}", info.pack))
+            }
+            Self::XdccGet => {
+                Command::PRIVMSG(info.botname.to_string(), format!("xdcc get {}", info.pack))
+            }
+            Self::Ctcp => Command::PRIVMSG(
+                info.botname.to_string(),
+                format!("\x01XDCC SEND {}\x01", info.pack),
+            ),
+            Self::Trigger { trigger, filename } => {
+                Command::PRIVMSG(info.channel.to_string(), format!("{trigger} {filename}"))
+            }
+        }
+    }
+
+    /// Whether the given offer answers the command.
+    pub(crate) fn accepts(&self, response: &Response) -> bool {
+        match self {
+            Self::Xdcc | Self::XdccGet | Self::Ctcp | Self::Auto => true,
+            Self::Trigger { filename, .. } => response.filename.eq_ignore_ascii_case(filename),
+        }
+    }
+}
+
+/// Dialects the bots answered, indexed by server and bot.
+#[derive(Debug, Default)]
+pub(crate) struct DialectCache {
+    dialects: Mutex<HashMap<(String, String), Dialect>>,
+}
+
+impl DialectCache {
+    /// Dialect the given bot answered on the given server, if known.
+    pub(crate) fn get(&self, server: &str, botname: &str) -> Option<Dialect> {
+        self.dialects
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&(server.to_owned(), botname.to_ascii_lowercase()))
+            .cloned()
+    }
+
+    /// Remembers the dialect the given bot answered on the given server.
+    pub(crate) fn insert(&self, server: &str, botname: &str, dialect: Dialect) {
+        self.dialects
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert((server.to_owned(), botname.to_ascii_lowercase()), dialect);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use irc::proto::Command;
+
+    use super::{Dialect, DialectCache};
+    use crate::test_util::local_response;
+    use crate::{Engine, Response};
+
+    fn response(filename: &str) -> Response {
+        local_response(filename, 5000, 1024)
+    }
+
+    #[test]
+    fn should_send_trigger_to_channel() {
+        let engine = Engine::default();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 0);
+        let dialect = Dialect::Trigger {
+            trigger: "!files".into(),
+            filename: "ubuntu.iso".into(),
+        };
+        assert_eq!(
+            dialect.command(request.info()),
+            Command::PRIVMSG("#channel".into(), "!files ubuntu.iso".into())
+        );
+        assert!(dialect.accepts(&response("Ubuntu.ISO")));
+        assert!(!dialect.accepts(&response("debian.iso")));
+    }
+
+    #[test]
+    fn should_send_xdcc_command_to_bot() {
+        let engine = Engine::default();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 42);
+        assert_eq!(
+            Dialect::Xdcc.command(request.info()),
+            Command::PRIVMSG("bot".into(), "xdcc send #42".into())
+        );
+        assert!(Dialect::Xdcc.accepts(&response("ubuntu.iso")));
+    }
+
+    #[test]
+    fn should_send_pack_name_to_bot() {
+        let engine = Engine::default();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", "ubuntu");
+        assert_eq!(
+            Dialect::Xdcc.command(request.info()),
+            Command::PRIVMSG("bot".into(), "xdcc send ubuntu".into())
+        );
+    }
+
+    #[test]
+    fn should_send_ctcp_command_to_bot() {
+        let engine = Engine::default();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 42);
+        assert_eq!(
+            Dialect::Ctcp.command(request.info()),
+            Command::PRIVMSG("bot".into(), "\x01XDCC SEND #42\x01".into())
+        );
+    }
+
+    #[test]
+    fn should_remember_dialect_per_bot() {
+        let cache = DialectCache::default();
+        cache.insert("irc.example.net", "Bot", Dialect::Ctcp);
+        assert_eq!(cache.get("irc.example.net", "bot"), Some(Dialect::Ctcp));
+        assert_eq!(cache.get("irc.example.net", "other"), None);
+        assert_eq!(cache.get("irc.other.net", "bot"), None);
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::{BuildHasher, RandomState};
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::ConnectError;
+
+/// Future returned by [`Resolver::resolve`].
+///
+/// It must be `Sync`, like the futures of the requests.
+pub type ResolveFuture<'a> =
+    Pin<Box<dyn Future<Output = io::Result<Vec<IpAddr>>> + Send + Sync + 'a>>;
+
+/// Future returned by [`Resolver::resolve_srv`].
+pub type SrvFuture<'a> =
+    Pin<Box<dyn Future<Output = io::Result<Vec<SrvRecord>>> + Send + Sync + 'a>>;
+
+/// DNS service record, advertising a server of a service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvRecord {
+    /// Priority of the server, the lowest being tried first.
+    pub priority: u16,
+    /// Weight of the server among the ones of the same priority, the highest being the most
+    /// likely to be tried first.
+    pub weight: u16,
+    /// Port of the service on the server.
+    pub port: u16,
+    /// Hostname of the server, `.` when the service is not available on the domain.
+    pub target: String,
+}
+
+/// Resolves the hostnames of the IRC servers.
+///
+/// Implementing this trait allows routing the lookups through DNS over HTTPS, a local
+/// cache or the resolver of a proxy. The engine caches the resolved addresses itself.
+pub trait Resolver: Debug + Send + Sync {
+    /// Resolves the given hostname into its addresses.
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] means the host doesn't exist, so the
+    /// requests to it are not retried, unlike the other errors.
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a>;
+
+    /// Resolves the service records of the given name, like `_ircs._tcp.example.net`.
+    ///
+    /// Returns no record by default.
+    fn resolve_srv<'a>(&'a self, name: &'a str) -> SrvFuture<'a> {
+        let _ = name;
+        Box::pin(async { Ok(Vec::new()) })
+    }
+}
+
+/// Resolver of the operating system, used by default.
+///
+/// The service records are only resolved with the `srv` feature, which reads the
+/// configuration of the system resolver on the first lookup.
+#[derive(Debug, Default)]
+pub struct SystemResolver {
+    /// Resolver of the service records, built on the first lookup.
+    #[cfg(feature = "srv")]
+    srv: Mutex<Option<hickory_resolver::TokioResolver>>,
+}
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let found = tokio::net::lookup_host((host, 0)).await.map_err(|err| {
+                if is_unknown_host(&err) {
+                    io::Error::new(io::ErrorKind::NotFound, err)
+                } else {
+                    err
+                }
+            })?;
+            Ok(found.map(|address| address.ip()).collect())
+        })
+    }
+
+    #[cfg(feature = "srv")]
+    fn resolve_srv<'a>(&'a self, name: &'a str) -> SrvFuture<'a> {
+        Box::pin(async move {
+            let resolver = {
+                let mut srv = self.srv.lock().unwrap_or_else(PoisonError::into_inner);
+                match *srv {
+                    Some(ref resolver) => resolver.clone(),
+                    None => srv
+                        .insert(
+                            hickory_resolver::TokioResolver::builder_tokio()
+                                .map_err(io::Error::other)?
+                                .build(),
+                        )
+                        .clone(),
+                }
+            };
+            // the lookup future is not `Sync`, while never being shared between threads
+            let found = match sync_wrapper::SyncFuture::new(resolver.srv_lookup(name)).await {
+                Ok(found) => found,
+                Err(err) if err.is_no_records_found() => return Ok(Vec::new()),
+                Err(err) => return Err(io::Error::other(err)),
+            };
+            Ok(found
+                .iter()
+                .map(|record| SrvRecord {
+                    priority: record.priority(),
+                    weight: record.weight(),
+                    port: record.port(),
+                    target: if record.target().is_root() {
+                        ".".to_owned()
+                    } else {
+                        record.target().to_utf8().trim_end_matches('.').to_owned()
+                    },
+                })
+                .collect())
+        })
+    }
+}
+
+/// Whether the given error of the system resolver means the host doesn't exist, rather
+/// than a transient failure like an unreachable name server.
+fn is_unknown_host(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        // WSAHOST_NOT_FOUND and WSANO_DATA
+        Some(code) => cfg!(windows) && matches!(code, 11001 | 11004),
+        // the failures of getaddrinfo are only described by their message
+        None => !err
+            .to_string()
+            .to_ascii_lowercase()
+            .contains("temporary failure"),
+    }
+}
+
+/// A resolved set of records with its expiration date.
+#[derive(Debug)]
+struct Entry<T> {
+    records: Vec<T>,
+    expires_at: Instant,
+}
+
+/// Returns the cached records of the given name, if they didn't expire.
+fn lookup<T: Clone>(entries: &Mutex<HashMap<String, Entry<T>>>, name: &str) -> Option<Vec<T>> {
+    let mut lock = entries.lock().unwrap_or_else(PoisonError::into_inner);
+    match lock.get(name) {
+        Some(entry) if entry.expires_at > Instant::now() => Some(entry.records.clone()),
+        Some(_) => {
+            lock.remove(name);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Orders the given service records by priority, then randomly within each priority,
+/// each record being picked first in proportion to its weight, as RFC 2782 describes.
+///
+/// The given source returns the random numbers.
+fn order_by_weight(mut records: Vec<SrvRecord>, mut random: impl FnMut() -> u64) -> Vec<SrvRecord> {
+    // the records without weight come first, to be picked only by a null random sum
+    records.sort_by_key(|record| (record.priority, record.weight != 0));
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].priority;
+        let count = records
+            .iter()
+            .take_while(|record| record.priority == priority)
+            .count();
+        let mut group: Vec<SrvRecord> = records.drain(..count).collect();
+        while !group.is_empty() {
+            let total: u64 = group.iter().map(|record| u64::from(record.weight)).sum();
+            let target = random() % (total + 1);
+            let mut sum = 0;
+            let index = group
+                .iter()
+                .position(|record| {
+                    sum += u64::from(record.weight);
+                    sum >= target
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    ordered
+}
+
+/// Caches resolved IRC server addresses and service records for a limited duration.
+#[derive(Debug)]
+pub(crate) struct DnsCache {
+    /// Resolved addresses, indexed by hostname.
+    entries: Mutex<HashMap<String, Entry<IpAddr>>>,
+    /// Resolves the hostnames missing from the cache.
+    resolver: Arc<dyn Resolver>,
+    /// Resolved service records, indexed by name, before their random ordering.
+    services: Mutex<HashMap<String, Entry<SrvRecord>>>,
+    /// Duration a resolved address is kept in the cache.
+    ttl: Duration,
+}
+
+impl DnsCache {
+    pub(crate) fn new(ttl: Duration, resolver: Arc<dyn Resolver>) -> Self {
+        Self {
+            entries: Default::default(),
+            resolver,
+            services: Default::default(),
+            ttl,
+        }
+    }
+
+    /// Returns the cached addresses of the given host, if they didn't expire.
+    fn lookup(&self, host: &str) -> Option<Vec<IpAddr>> {
+        lookup(&self.entries, host)
+    }
+
+    /// Stores the addresses of the given host.
+    fn insert(&self, host: &str, addresses: Vec<IpAddr>) {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                host.to_owned(),
+                Entry {
+                    records: addresses,
+                    expires_at: Instant::now() + self.ttl,
+                },
+            );
+    }
+
+    /// Resolves the service records of the given name, using the cached records when
+    /// available, ordered by priority then randomly by weight.
+    ///
+    /// The records of the `.` target, meaning the service is not available, are
+    /// skipped. A failed resolution is treated like a name without records, and not
+    /// cached.
+    pub(crate) async fn resolve_srv(&self, name: &str) -> Vec<SrvRecord> {
+        let records = match lookup(&self.services, name) {
+            Some(records) => {
+                debug!("using cached service records of {name}");
+                records
+            }
+            None => match self.resolver.resolve_srv(name).await {
+                Ok(mut records) => {
+                    records.retain(|record| !matches!(&*record.target, "" | "."));
+                    self.services
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(
+                            name.to_owned(),
+                            Entry {
+                                records: records.clone(),
+                                expires_at: Instant::now() + self.ttl,
+                            },
+                        );
+                    records
+                }
+                Err(err) => {
+                    debug!("unable to resolve the service records of {name}: {err}");
+                    Vec::new()
+                }
+            },
+        };
+        let seed = RandomState::new().hash_one(name);
+        let mut count = 0u64;
+        order_by_weight(records, || {
+            count += 1;
+            crate::identity::mix(seed.wrapping_add(count))
+        })
+    }
+
+    /// Resolves the given host, using the cached addresses when available.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConnectError::UnknownHost`] if the host doesn't exist or has no address,
+    /// or [`ConnectError::Resolve`] if the resolution fails otherwise.
+    pub(crate) async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ConnectError> {
+        if let Some(addresses) = self.lookup(host) {
+            debug!("using cached addresses of {host}");
+            return Ok(addresses);
+        }
+
+        let mut addresses: Vec<IpAddr> = Vec::new();
+        let found = self
+            .resolver
+            .resolve(host)
+            .await
+            .map_err(|source| match source.kind() {
+                io::ErrorKind::NotFound => ConnectError::UnknownHost {
+                    host: host.to_owned(),
+                },
+                _ => ConnectError::Resolve {
+                    host: host.to_owned(),
+                    source,
+                },
+            })?;
+        for address in found {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        if addresses.is_empty() {
+            return Err(ConnectError::UnknownHost {
+                host: host.to_owned(),
+            });
+        }
+
+        debug!("resolved {host} to {addresses:?}");
+        self.insert(host, addresses.clone());
+        Ok(addresses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::{DnsCache, ResolveFuture, Resolver, SrvFuture, SrvRecord, SystemResolver};
+
+    #[derive(Debug, Default)]
+    struct StaticResolver(AtomicUsize);
+
+    fn record(priority: u16, weight: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port: 6697,
+            target: target.into(),
+        }
+    }
+
+    impl Resolver for StaticResolver {
+        fn resolve<'a>(&'a self, _host: &'a str) -> ResolveFuture<'a> {
+            Box::pin(async { Ok(vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)); 2]) })
+        }
+
+        fn resolve_srv<'a>(&'a self, _name: &'a str) -> SrvFuture<'a> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Box::pin(async {
+                Ok(vec![
+                    record(20, 0, "backup.example.net"),
+                    record(10, 5, "light.example.net"),
+                    record(30, 0, "."),
+                    record(10, 50, "heavy.example.net"),
+                ])
+            })
+        }
+    }
+
+    #[test]
+    fn should_order_service_records_by_weight() {
+        let records = vec![
+            record(20, 0, "backup.example.net"),
+            record(10, 5, "light.example.net"),
+            record(10, 0, "idle.example.net"),
+            record(10, 50, "heavy.example.net"),
+        ];
+        let targets = |random: u64| -> Vec<String> {
+            super::order_by_weight(records.clone(), || random)
+                .into_iter()
+                .map(|record| record.target)
+                .collect()
+        };
+        // the random sums pick the record reaching them first
+        assert_eq!(
+            targets(0),
+            [
+                "idle.example.net",
+                "light.example.net",
+                "heavy.example.net",
+                "backup.example.net"
+            ]
+        );
+        assert_eq!(
+            targets(55),
+            [
+                "heavy.example.net",
+                "light.example.net",
+                "idle.example.net",
+                "backup.example.net"
+            ]
+        );
+        assert_eq!(
+            targets(3),
+            [
+                "light.example.net",
+                "heavy.example.net",
+                "idle.example.net",
+                "backup.example.net"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_cache_service_records() {
+        let resolver = Arc::new(StaticResolver::default());
+        let cache = DnsCache::new(Duration::from_secs(60), resolver.clone());
+        for _ in 0..2 {
+            let records = cache.resolve_srv("_ircs._tcp.example.net").await;
+            let mut targets: Vec<&str> = records.iter().map(|record| &*record.target).collect();
+            targets[..2].sort_unstable();
+            assert_eq!(
+                targets,
+                [
+                    "heavy.example.net",
+                    "light.example.net",
+                    "backup.example.net"
+                ]
+            );
+        }
+        assert_eq!(resolver.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn should_not_resolve_service_records_by_default() {
+        #[derive(Debug)]
+        struct AddressResolver;
+
+        impl Resolver for AddressResolver {
+            fn resolve<'a>(&'a self, _host: &'a str) -> ResolveFuture<'a> {
+                Box::pin(async { Ok(Vec::new()) })
+            }
+        }
+
+        let cache = DnsCache::new(Duration::from_secs(60), Arc::new(AddressResolver));
+        assert!(cache.resolve_srv("_ircs._tcp.example.net").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_resolve_with_custom_resolver() {
+        let cache = DnsCache::new(Duration::from_secs(60), Arc::new(StaticResolver::default()));
+        let res = cache.resolve("irc.example.net").await.unwrap();
+        assert_eq!(res, vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+    }
+
+    #[tokio::test]
+    async fn should_resolve_ip_literal() {
+        let cache = DnsCache::new(Duration::from_secs(60), Arc::new(SystemResolver::default()));
+        let res = cache.resolve("127.0.0.1").await.unwrap();
+        assert_eq!(res, vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        assert!(cache.lookup("127.0.0.1").is_some());
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_unknown_host() {
+        let cache = DnsCache::new(Duration::from_secs(60), Arc::new(SystemResolver::default()));
+        let err = cache.resolve("irc.example.invalid").await.unwrap_err();
+        assert!(
+            matches!(err, crate::ConnectError::UnknownHost { ref host } if host == "irc.example.invalid"),
+            "{err:?}"
+        );
+        assert!(!crate::Error::from(err).is_retryable());
+    }
+
+    #[test]
+    fn should_use_cached_addresses() {
+        let cache = DnsCache::new(Duration::from_secs(60), Arc::new(SystemResolver::default()));
+        cache.insert(
+            "irc.example.net",
+            vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))],
+        );
+        assert_eq!(
+            cache.lookup("irc.example.net"),
+            Some(vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))])
+        );
+    }
+
+    #[test]
+    fn should_expire_cached_addresses() {
+        let cache = DnsCache::new(Duration::ZERO, Arc::new(SystemResolver::default()));
+        cache.insert(
+            "irc.example.net",
+            vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))],
+        );
+        assert_eq!(cache.lookup("irc.example.net"), None);
+    }
+}
@@ -0,0 +1,3907 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures_util::Stream;
+use irc::proto::{Command, Message, Response as IrcResponse};
+
+#[cfg(feature = "ident")]
+use crate::ident;
+#[cfg(feature = "starttls")]
+use crate::starttls;
+use crate::{
+    AddressFamily, Bot, ChannelRules, ConnectError, Connector, Dialect, Error, Event, EventKind,
+    Network, OfferError, PackAnnouncement, PackId, PackSelector, ProtocolError, QueueStatus,
+    Resolver, Response, Result, ServerProbe, SocketOptions, SystemResolver, Timings, Transport,
+    Validation, connection, dialect, dns, identity, inflight, latency, limiter, notice, pool,
+    telemetry, validation, whois,
+};
+
+/// Internal engine state, shared across requests.
+struct InnerEngine {
+    /// Address family of the IRC and DCC connections.
+    address_family: AddressFamily,
+    /// Interval of the activity on the connections staying joined, disabled when `None`.
+    anti_idle: Option<Duration>,
+    /// Limits the concurrent requests per bot.
+    bot_limiter: limiter::ServerLimiter,
+    /// Settings of the known bots, indexed by lowercase nickname.
+    bots: HashMap<String, Bot>,
+    /// Stores the outcome of the requests, when set.
+    #[cfg(feature = "audit")]
+    audit_log: Option<Arc<crate::audit::AuditLog>>,
+    /// Records the messages exchanged during the requests when enabled.
+    capture_transcript: bool,
+    /// Whether the channels of the bot are joined when it doesn't answer the command.
+    channel_discovery: bool,
+    /// Delays waited after joining the channels before sending the command, indexed by
+    /// lowercase channel name, overriding the `request_delay`.
+    channel_request_delays: HashMap<String, Duration>,
+    /// Whether the presence of the bot is checked before joining the channels.
+    check_bot_presence: bool,
+    /// Establishes the connections instead of the engine, when set.
+    connector: Option<Arc<dyn Connector>>,
+    /// Timeout of each dialect tried by [`Dialect::Auto`].
+    dialect_probe_timeout: Duration,
+    /// Channels joined on every connection along with the channel of the request.
+    default_channels: Vec<String>,
+    /// Dialects the bots answered, used by [`Dialect::Auto`].
+    dialects: dialect::DialectCache,
+    /// Cache of the resolved IRC server addresses.
+    dns: dns::DnsCache,
+    /// WHATWG label of the encoding of the messages exchanged with the servers.
+    encoding: String,
+    /// Sends the events of all the requests.
+    events: tokio::sync::broadcast::Sender<Event>,
+    /// Maximum delay waited after joining a channel when its rules ask for one,
+    /// disabled when `None`.
+    honor_channel_rules: Option<Duration>,
+    /// Answers the ident queries during the registrations, when set.
+    #[cfg(feature = "ident")]
+    identd: Option<ident::Identd>,
+    /// Provides the nicknames and usernames.
+    identities: identity::IdentityProvider,
+    /// Requests being executed, shared by the identical requests, when deduplicated.
+    in_flight: Option<inflight::InFlight>,
+    /// Additional duration the offers are awaited after the timeout, disabled when `None`.
+    late_offer_window: Option<Duration>,
+    /// Measured latencies of the servers, used to compute the timeouts.
+    latencies: latency::LatencyTracker,
+    /// Limits the concurrent requests per server.
+    limiter: limiter::ServerLimiter,
+    /// Maximum duration of a request from its start, unlimited when `None`.
+    max_lifetime: Option<Duration>,
+    /// Profiles of the networks, indexed by name.
+    networks: HashMap<String, Network>,
+    /// Classifies the notices of the bots.
+    notice_patterns: notice::NoticePatterns,
+    /// Timeout of the reachability check of the offers, disabled when `None`.
+    offer_probe: Option<Duration>,
+    /// Registered connections waiting to be used by a request.
+    pool: pool::Pool<connection::Connection>,
+    /// Spaces the commands sent on the networks with a request interval.
+    pacer: limiter::RequestPacer,
+    /// Interval between the queries of the position in the queue of the bot, disabled when `None`.
+    queue_poll: Option<Duration>,
+    /// Delay waited after joining a channel before sending the command.
+    request_delay: Duration,
+    /// TCP options applied to the IRC connections.
+    socket_options: SocketOptions,
+    /// Upgrades the connections to TLS with the `STARTTLS` command, when set.
+    #[cfg(feature = "starttls")]
+    starttls: Option<Arc<dyn crate::TlsUpgrader>>,
+    /// Maximum number of requests sent again after a throttle notice, and maximum
+    /// delay waited before each of them.
+    throttle_retry: (u32, Duration),
+}
+
+impl Default for InnerEngine {
+    fn default() -> Self {
+        EngineBuilder::default().build_inner()
+    }
+}
+
+impl std::fmt::Debug for InnerEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(stringify!(InnerEngine))
+            .field("capture_transcript", &self.capture_transcript)
+            .field("connector", &self.connector)
+            .field("dns", &self.dns)
+            .field("latencies", &self.latencies)
+            .field("offer_probe", &self.offer_probe)
+            .field("socket_options", &self.socket_options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl InnerEngine {
+    /// Default dialect of the requests to the given bot.
+    fn dialect(&self, botname: &str) -> Dialect {
+        self.bots
+            .get(&botname.to_ascii_lowercase())
+            .and_then(|bot| bot.dialect.clone())
+            .unwrap_or_default()
+    }
+
+    /// Runs the given request within the maximum lifetime of the requests.
+    async fn within_lifetime<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.max_lifetime {
+            Some(lifetime) => tokio::time::timeout(lifetime, future)
+                .await
+                .unwrap_or(Err(Error::DeadlineExceeded)),
+            None => future.await,
+        }
+    }
+
+    /// Runs the given phase of a request within its timeout, and records its latency.
+    async fn run_phase<T>(
+        &self,
+        server: &str,
+        phase: latency::Phase,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let started_at = Instant::now();
+        let future = tokio::time::timeout(self.latencies.timeout(server, phase), future);
+        let result = instrument!(future, phase.span())
+            .await
+            .map_err(|_| phase.timeout_error())??;
+        self.latencies.record(server, phase, started_at.elapsed());
+        Ok(result)
+    }
+
+    /// Opens a connection to the given IRC server, or to the first reachable server when
+    /// it's the name of a network.
+    ///
+    /// Returns as well the local address of the connection, unknown with a custom connector.
+    async fn open(
+        &self,
+        server: &str,
+    ) -> Result<(connection::Connection, Option<std::net::SocketAddr>)> {
+        let Some(network) = self.networks.get(server) else {
+            return self.open_host(server, connection::DEFAULT_PORT, None).await;
+        };
+        // the TLS ports are advertised by a distinct service
+        let (port, service, tls) = match network.tls {
+            Some(ref connector) => (connection::TLS_PORT, "_ircs", Some(&**connector)),
+            None => (connection::DEFAULT_PORT, "_irc", None),
+        };
+        let mut last_error = None;
+        if let Some(ref domain) = network.srv_domain {
+            for record in self
+                .dns
+                .resolve_srv(&format!("{service}._tcp.{domain}"))
+                .await
+            {
+                match self.open_host(&record.target, record.port, tls).await {
+                    Ok(opened) => return Ok(opened),
+                    Err(err) => {
+                        warn!("unable to connect to {} of {server}: {err}", record.target);
+                        last_error = Some(err);
+                    }
+                }
+            }
+        }
+        for host in network.rotation() {
+            match self.open_host(host, port, tls).await {
+                Ok(opened) => return Ok(opened),
+                Err(err) => {
+                    warn!("unable to connect to {host} of {server}: {err}");
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.expect("a network has at least one server"))
+    }
+
+    /// Opens a connection to the given server address and port, with the given TLS
+    /// connector, or the custom connector if any.
+    ///
+    /// The connections opened over TLS are not upgraded with `STARTTLS`.
+    async fn open_host(
+        &self,
+        server: &str,
+        port: u16,
+        tls: Option<&dyn Connector>,
+    ) -> Result<(connection::Connection, Option<std::net::SocketAddr>)> {
+        let (stream, peer_addr, local_addr) = match tls.or(self.connector.as_deref()) {
+            Some(connector) => {
+                let stream = connector
+                    .connect(server, port)
+                    .await
+                    .map_err(ConnectError::Connect)?;
+                (stream, None, None)
+            }
+            None => {
+                let mut addresses = self.dns.resolve(server).await?;
+                addresses.retain(|address| self.address_family.allows(*address));
+                let stream = connection::happy_eyeballs(
+                    &addresses,
+                    port,
+                    connection::CONNECTION_ATTEMPT_DELAY,
+                )
+                .await
+                .map_err(ConnectError::Connect)?;
+                self.socket_options
+                    .apply(&stream)
+                    .map_err(ConnectError::Connect)?;
+                let peer_addr = stream.peer_addr().ok();
+                let local_addr = stream.local_addr().ok();
+                (
+                    Box::new(stream) as Box<dyn Transport>,
+                    peer_addr,
+                    local_addr,
+                )
+            }
+        };
+        let encrypted = tls.is_some().then_some(true);
+        #[cfg(feature = "starttls")]
+        let (stream, encrypted) = match self.starttls {
+            Some(ref upgrader) if tls.is_none() => {
+                let timeout = self.latencies.timeout(server, latency::Phase::Registration);
+                let (stream, upgraded) =
+                    tokio::time::timeout(timeout, starttls::negotiate(stream, server, &**upgrader))
+                        .await
+                        .map_err(|_| ConnectError::RegistrationTimeout)?
+                        .map_err(ConnectError::Connect)?;
+                (stream, Some(upgraded))
+            }
+            _ => (stream, encrypted),
+        };
+        let mut connection = connection::Connection::new(stream, peer_addr, &self.encoding)?;
+        if let Some(encrypted) = encrypted {
+            connection.set_tls(encrypted);
+        }
+        Ok((connection, local_addr))
+    }
+
+    /// Connects to the given IRC server and waits for the registration to complete.
+    ///
+    /// The durations of the steps are stored in the given timings, and the exchanged
+    /// messages in the given transcript.
+    async fn connect(
+        &self,
+        server: &str,
+        timings: &mut Timings,
+        transcript: Option<&connection::Transcript>,
+    ) -> Result<connection::Connection> {
+        let started_at = Instant::now();
+        let (mut connection, local_addr) =
+            instrument!(self.open(server), tracing::info_span!("connect", server)).await?;
+        timings.connect = started_at.elapsed();
+
+        let started_at = Instant::now();
+        if let Some(transcript) = transcript {
+            connection.set_transcript(transcript.clone());
+        }
+        let identity = self.identities.identity(server);
+        let sasl = self
+            .networks
+            .get(server)
+            .and_then(|network| network.sasl.as_ref());
+        connection
+            .identify(&identity.nickname, &identity.username, sasl.is_some())
+            .await?;
+
+        let registration = self.run_phase(server, latency::Phase::Registration, async {
+            if let Some(credentials) = sasl {
+                authenticate(&mut connection, credentials).await?;
+            }
+            wait_for_registration(&mut connection).await
+        });
+        #[cfg(feature = "ident")]
+        let registration = async {
+            match (&self.identd, local_addr) {
+                (Some(identd), Some(local_addr)) => {
+                    identd
+                        .serve_while(local_addr.port(), &identity.username, registration)
+                        .await
+                }
+                _ => registration.await,
+            }
+        };
+        #[cfg(not(feature = "ident"))]
+        let _ = local_addr;
+        registration.await?;
+        timings.register = started_at.elapsed();
+
+        Ok(connection)
+    }
+}
+
+/// Builder to configure an [`Engine`].
+#[derive(Clone, Debug)]
+pub struct EngineBuilder {
+    adaptive_timeout: Option<(Duration, Duration)>,
+    address_family: AddressFamily,
+    anti_idle: Option<Duration>,
+    #[cfg(feature = "audit")]
+    audit_log: Option<Arc<crate::audit::AuditLog>>,
+    bots: HashMap<String, Bot>,
+    capture_transcript: bool,
+    channel_discovery: bool,
+    channel_request_delays: HashMap<String, Duration>,
+    check_bot_presence: bool,
+    connector: Option<Arc<dyn Connector>>,
+    deduplicate_requests: bool,
+    default_channels: Vec<String>,
+    dialect_probe_timeout: Duration,
+    dns_ttl: Duration,
+    encoding: String,
+    event_capacity: usize,
+    honor_channel_rules: Option<Duration>,
+    #[cfg(feature = "ident")]
+    identd: Option<std::net::SocketAddr>,
+    late_offer_window: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    max_requests_per_server: Option<usize>,
+    name_seed: Option<u64>,
+    networks: HashMap<String, Network>,
+    notice_patterns: notice::NoticePatterns,
+    offer_probe: Option<Duration>,
+    persistent_identity: bool,
+    queue_poll: Option<Duration>,
+    request_delay: Duration,
+    resolver: Arc<dyn Resolver>,
+    socket_options: SocketOptions,
+    #[cfg(feature = "starttls")]
+    starttls: Option<Arc<dyn crate::TlsUpgrader>>,
+    throttle_retry: (u32, Duration),
+    timeout: Duration,
+    warm_ttl: Duration,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            adaptive_timeout: None,
+            address_family: AddressFamily::Any,
+            anti_idle: None,
+            #[cfg(feature = "audit")]
+            audit_log: None,
+            bots: HashMap::new(),
+            capture_transcript: false,
+            channel_discovery: false,
+            channel_request_delays: HashMap::new(),
+            check_bot_presence: false,
+            connector: None,
+            deduplicate_requests: false,
+            default_channels: Vec::new(),
+            dialect_probe_timeout: Duration::from_secs(10),
+            dns_ttl: Duration::from_secs(300),
+            encoding: "utf-8".into(),
+            event_capacity: 256,
+            honor_channel_rules: None,
+            #[cfg(feature = "ident")]
+            identd: None,
+            late_offer_window: None,
+            max_lifetime: None,
+            max_requests_per_server: None,
+            name_seed: None,
+            networks: HashMap::new(),
+            notice_patterns: Default::default(),
+            offer_probe: None,
+            persistent_identity: false,
+            queue_poll: None,
+            request_delay: Duration::ZERO,
+            resolver: Arc::new(SystemResolver::default()),
+            socket_options: SocketOptions::default(),
+            #[cfg(feature = "starttls")]
+            starttls: None,
+            throttle_retry: (3, Duration::from_secs(300)),
+            timeout: Duration::from_secs(30),
+            warm_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+impl EngineBuilder {
+    /// Adapts the timeouts to the latencies measured on each server.
+    ///
+    /// Once a server answered, the timeout of each step of a request is computed from
+    /// the previous latencies of that step on the server, bounded by `min` and `max`.
+    /// The [`EngineBuilder::timeout`] is used until a latency is measured.
+    pub fn adaptive_timeout(mut self, min: Duration, max: Duration) -> Self {
+        self.adaptive_timeout = Some((min, max));
+        self
+    }
+
+    /// Restricts the IRC connections and the checks of the DCC offers to the given
+    /// address family.
+    ///
+    /// The offers of another family fail the [`EngineBuilder::offer_probe`] with
+    /// [`OfferError::Unreachable`].
+    pub fn address_family(mut self, value: AddressFamily) -> Self {
+        self.address_family = value;
+        self
+    }
+
+    /// Sends a CTCP `PING` to the client itself at the given interval while
+    /// [`Presence::stay`] runs, for the networks disconnecting the idle clients during
+    /// the long transfers.
+    pub fn anti_idle(mut self, interval: Duration) -> Self {
+        self.anti_idle = Some(interval);
+        self
+    }
+
+    /// Stores the outcome of every executed request in the given audit log.
+    #[cfg(feature = "audit")]
+    pub fn audit_log(mut self, value: Arc<crate::audit::AuditLog>) -> Self {
+        self.audit_log = Some(value);
+        self
+    }
+
+    /// Registers the settings of the bot with the given nickname, used by the requests
+    /// to this bot on any server.
+    pub fn bot(mut self, nickname: impl AsRef<str>, bot: Bot) -> Self {
+        self.bots
+            .insert(nickname.as_ref().to_ascii_lowercase(), bot);
+        self
+    }
+
+    /// Records the messages exchanged with the server during each request, and attaches
+    /// them to the error when the request fails, available through [`Error::transcript`].
+    pub fn capture_transcript(mut self, value: bool) -> Self {
+        self.capture_transcript = value;
+        self
+    }
+
+    /// Looks for the bot when it doesn't offer the file in time, as many bots ignore the
+    /// users outside of their channels.
+    ///
+    /// The channels the bot is in are asked to the server with `WHOIS`, then the ones
+    /// not joined yet are joined and the command is sent again. The request fails with
+    /// [`OfferError::TimeoutAfterDiscovery`] listing them when the bot still doesn't answer.
+    pub fn channel_discovery(mut self, value: bool) -> Self {
+        self.channel_discovery = value;
+        self
+    }
+
+    /// Delay waited after joining the given channel before sending the command, instead
+    /// of the [`EngineBuilder::request_delay`].
+    pub fn channel_request_delay(mut self, channel: impl AsRef<str>, value: Duration) -> Self {
+        self.channel_request_delays
+            .insert(channel.as_ref().to_ascii_lowercase(), value);
+        self
+    }
+
+    /// Checks with `WHOIS` that the bot is connected before joining the channels, so the
+    /// requests to a gone bot fail right away with [`OfferError::BotOffline`] instead of
+    /// waiting for the offer until the timeout.
+    ///
+    /// The aliases of the [`Bot`] are checked as well.
+    pub fn check_bot_presence(mut self, value: bool) -> Self {
+        self.check_bot_presence = value;
+        self
+    }
+
+    /// Executes the requests of a pack once at a time: while a request is running, the
+    /// requests of the same pack to the same bot on the same server wait for its offer
+    /// instead of asking the bot again, which could get both requests banned.
+    ///
+    /// The waiting requests execute in turn when the running one fails.
+    pub fn deduplicate_requests(mut self, value: bool) -> Self {
+        self.deduplicate_requests = value;
+        self
+    }
+
+    /// Joins the given channel on every connection along with the channel of the request,
+    /// for the networks requiring their users to stay in a chat channel.
+    ///
+    /// The channels of a single network are set with [`Network::with_channel`].
+    pub fn default_channel(mut self, channel: impl Into<String>) -> Self {
+        self.default_channels.push(channel.into());
+        self
+    }
+
+    /// Establishes the connections to the IRC servers with the given connector, to use
+    /// proxied sockets, custom TLS stacks or in-memory streams.
+    ///
+    /// The [`EngineBuilder::resolver`] and [`EngineBuilder::socket_options`] are then
+    /// left to the connector.
+    pub fn connector(mut self, value: impl Connector + 'static) -> Self {
+        self.connector = Some(Arc::new(value));
+        self
+    }
+
+    /// Duration each dialect tried by [`Dialect::Auto`] waits for the offer of the bot.
+    pub fn dialect_probe_timeout(mut self, value: Duration) -> Self {
+        self.dialect_probe_timeout = value;
+        self
+    }
+
+    /// Duration resolved IRC server addresses are kept in cache.
+    pub fn dns_ttl(mut self, value: Duration) -> Self {
+        self.dns_ttl = value;
+        self
+    }
+
+    /// Encodes and decodes the messages exchanged with the servers with the encoding of
+    /// the given WHATWG label, like `windows-1252`, instead of UTF-8.
+    ///
+    /// Some bots of legacy networks mishandle the UTF-8 commands containing non-ASCII
+    /// characters, like the FServe triggers with a filename. The connections fail when
+    /// the label is unknown.
+    #[cfg(feature = "encoding")]
+    pub fn encoding(mut self, label: impl Into<String>) -> Self {
+        self.encoding = label.into();
+        self
+    }
+
+    /// Number of events kept for the slow receivers of [`Engine::events`].
+    ///
+    /// A receiver lagging behind more than this number of events misses the oldest ones.
+    pub fn event_capacity(mut self, value: usize) -> Self {
+        self.event_capacity = value.max(1);
+        self
+    }
+
+    /// Waits after joining a channel the delay its topic or entry notices ask for, like
+    /// `no requests for 60s after join`, bounded by the given maximum.
+    ///
+    /// The detected rules are emitted as [`EventKind::ChannelRules`] events either way.
+    pub fn honor_channel_rules(mut self, max_delay: Duration) -> Self {
+        self.honor_channel_rules = Some(max_delay);
+        self
+    }
+
+    /// Answers the ident (RFC 1413) queries of the servers with the username of the
+    /// connection being registered, listening on the given address, usually on port 113.
+    ///
+    /// The listener is bound on the first registration, and the queries are only answered
+    /// while a registration is in progress. The connections made by a custom
+    /// [`EngineBuilder::connector`] are not answered for.
+    #[cfg(feature = "ident")]
+    pub fn identd(mut self, address: std::net::SocketAddr) -> Self {
+        self.identd = Some(address);
+        self
+    }
+
+    /// Keeps waiting for the offer during the given window once the timeout elapsed,
+    /// for the slow bots answering long after the command.
+    ///
+    /// The slot of the request on the server is released during the window, so the
+    /// other requests to the server proceed, while the connection stays open to receive
+    /// the late offer.
+    pub fn late_offer_window(mut self, value: Duration) -> Self {
+        self.late_offer_window = Some(value);
+        self
+    }
+
+    /// Maximum duration of each request, from its start to the offer, including the
+    /// waits for a slot and in the queue of the bot.
+    ///
+    /// The requests still running at the deadline are cancelled, leaving the server, and
+    /// fail with [`Error::DeadlineExceeded`]. With the `transfer` feature, the transfers
+    /// created by the [`Presence`] of a request end at the same deadline, given by
+    /// [`Presence::deadline`], while the transfers created from the offer alone are only
+    /// bounded by their own `Transfer::deadline`.
+    pub fn max_lifetime(mut self, value: Duration) -> Self {
+        self.max_lifetime = Some(value);
+        self
+    }
+
+    /// Maximum number of requests executed concurrently on a same server.
+    ///
+    /// The requests exceeding the limit wait for a running one to complete. Unlimited by default.
+    pub fn max_requests_per_server(mut self, value: usize) -> Self {
+        self.max_requests_per_server = Some(value);
+        self
+    }
+
+    /// Generates the nicknames and usernames from the given seed instead of a random one.
+    ///
+    /// The engines built with the same seed use the same sequence of names, which makes
+    /// integration tests and recorded sessions reproducible.
+    pub fn name_seed(mut self, value: u64) -> Self {
+        self.name_seed = Some(value);
+        self
+    }
+
+    /// Registers the profile of a network, to reference by name as the server of the
+    /// requests, instead of its address.
+    ///
+    /// The connections, limits and identities of the requests are tracked per network.
+    pub fn network(mut self, name: impl Into<String>, network: Network) -> Self {
+        self.networks.insert(name.into(), network);
+        self
+    }
+
+    /// Classifies the notices of the bots with the given patterns as well, like the
+    /// ones of the bots not speaking English.
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// use xdcc_request::{Engine, NoticePatterns};
+    ///
+    /// let patterns = NoticePatterns::default()
+    ///     .with_queue(Regex::new(r"en position (?<position>\d+)(?: sur (?<total>\d+))?").unwrap())
+    ///     .with_throttle(Regex::new(r"(?i)attendez (?<delay>\d+) secondes").unwrap())
+    ///     .with_error(Regex::new(r"(?i)num[ée]ro de pack invalide").unwrap());
+    /// let engine = Engine::builder().notice_patterns(patterns).build();
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn notice_patterns(mut self, value: crate::NoticePatterns) -> Self {
+        self.notice_patterns = value;
+        self
+    }
+
+    /// Checks that the endpoint of each DCC offer accepts connections before returning it.
+    ///
+    /// A TCP connection is attempted with the given timeout, and the request fails with
+    /// [`OfferError::Unreachable`] when it cannot be established. Some bots consider the
+    /// transfer as started on the first connection, so this should only be enabled for
+    /// bots accepting several connections per offer.
+    pub fn offer_probe(mut self, timeout: Duration) -> Self {
+        self.offer_probe = Some(timeout);
+        self
+    }
+
+    /// Keeps the same nickname and username on a server for the lifetime of the engine.
+    ///
+    /// Some bots track the requesters by nickname and throttle the new ones. As two
+    /// connections cannot use the same nickname, this is best combined with
+    /// [`EngineBuilder::max_requests_per_server`] set to `1`.
+    pub fn persistent_identity(mut self, value: bool) -> Self {
+        self.persistent_identity = value;
+        self
+    }
+
+    /// Asks the bot the position of the request in its queue with `xdcc queue`, each time
+    /// the given interval elapses without an offer.
+    ///
+    /// The positions announced by the bot are emitted as [`EventKind::Queued`] events,
+    /// whether they answer a query or not. Bots tend to ban the users flooding them,
+    /// so the interval should be of a few minutes. The offer still has to arrive within
+    /// the [`EngineBuilder::timeout`], which should be raised when long queues are expected.
+    pub fn queue_poll(mut self, interval: Duration) -> Self {
+        self.queue_poll = Some(interval);
+        self
+    }
+
+    /// Delay waited after joining a channel before sending the command, as many channels
+    /// ban the clients messaging a bot right after joining. No delay by default.
+    ///
+    /// The delay of a channel can be overridden with [`EngineBuilder::channel_request_delay`].
+    pub fn request_delay(mut self, value: Duration) -> Self {
+        self.request_delay = value;
+        self
+    }
+
+    /// Resolves the hostnames of the IRC servers with the given resolver, instead of
+    /// the [`SystemResolver`].
+    pub fn resolver(mut self, value: impl Resolver + 'static) -> Self {
+        self.resolver = Arc::new(value);
+        self
+    }
+
+    /// TCP options applied to the IRC connections, and to the DCC connections of the
+    /// transfers created by a [`Presence`].
+    pub fn socket_options(mut self, value: SocketOptions) -> Self {
+        self.socket_options = value;
+        self
+    }
+
+    /// Asks the servers to upgrade the plaintext connections to TLS with the `STARTTLS`
+    /// command, performing the handshake with the given upgrader.
+    ///
+    /// The connection stays in plaintext when the server refuses the upgrade, so this
+    /// only protects against passive eavesdropping.
+    #[cfg(feature = "starttls")]
+    pub fn starttls(mut self, upgrader: impl crate::TlsUpgrader + 'static) -> Self {
+        self.starttls = Some(Arc::new(upgrader));
+        self
+    }
+
+    /// Requests again at most `max_retries` times when the bot answers with a throttle
+    /// notice, like `Please wait 30 seconds`, after waiting the delay it asks for.
+    ///
+    /// The request fails with [`OfferError::Throttled`] when the bot asks to wait longer
+    /// than `max_delay`, or once the retries are exhausted. Defaults to 3 retries of at
+    /// most 5 minutes.
+    pub fn throttle_retry(mut self, max_retries: u32, max_delay: Duration) -> Self {
+        self.throttle_retry = (max_retries, max_delay);
+        self
+    }
+
+    /// Timeout duration for IRC responses.
+    pub fn timeout(mut self, value: Duration) -> Self {
+        self.timeout = value;
+        self
+    }
+
+    /// Duration a connection created with [`Engine::warm`] is kept before being discarded.
+    pub fn warm_ttl(mut self, value: Duration) -> Self {
+        self.warm_ttl = value;
+        self
+    }
+
+    fn build_inner(self) -> InnerEngine {
+        let mut identities =
+            identity::IdentityProvider::new(self.persistent_identity, self.name_seed);
+        let mut limiter = limiter::ServerLimiter::new(self.max_requests_per_server);
+        let mut bot_limiter = limiter::ServerLimiter::new(None);
+        let mut pacer = limiter::RequestPacer::default();
+        for (name, bot) in &self.bots {
+            if let Some(limit) = bot.max_requests {
+                bot_limiter = bot_limiter.with_limit(name.clone(), limit);
+            }
+        }
+        for (name, network) in &self.networks {
+            if let Some(persistent) = network.persistent_identity {
+                identities = identities.with_persistent(name.clone(), persistent);
+            }
+            if let Some(limit) = network.max_requests {
+                limiter = limiter.with_limit(name.clone(), limit);
+            }
+            if let Some(interval) = network.request_interval {
+                pacer = pacer.with_interval(name.clone(), interval);
+            }
+        }
+        InnerEngine {
+            address_family: self.address_family,
+            anti_idle: self.anti_idle,
+            #[cfg(feature = "audit")]
+            audit_log: self.audit_log,
+            bot_limiter,
+            bots: self.bots,
+            capture_transcript: self.capture_transcript,
+            channel_discovery: self.channel_discovery,
+            channel_request_delays: self.channel_request_delays,
+            check_bot_presence: self.check_bot_presence,
+            connector: self.connector,
+            default_channels: self.default_channels,
+            dialect_probe_timeout: self.dialect_probe_timeout,
+            dialects: Default::default(),
+            dns: dns::DnsCache::new(self.dns_ttl, self.resolver),
+            encoding: self.encoding,
+            events: tokio::sync::broadcast::Sender::new(self.event_capacity),
+            honor_channel_rules: self.honor_channel_rules,
+            #[cfg(feature = "ident")]
+            identd: self.identd.map(ident::Identd::new),
+            identities,
+            in_flight: self.deduplicate_requests.then(Default::default),
+            late_offer_window: self.late_offer_window,
+            latencies: latency::LatencyTracker::new(self.timeout, self.adaptive_timeout),
+            limiter,
+            max_lifetime: self.max_lifetime,
+            networks: self.networks,
+            notice_patterns: self.notice_patterns,
+            offer_probe: self.offer_probe,
+            pacer,
+            pool: pool::Pool::new(self.warm_ttl),
+            queue_poll: self.queue_poll,
+            request_delay: self.request_delay,
+            socket_options: self.socket_options,
+            #[cfg(feature = "starttls")]
+            starttls: self.starttls,
+            throttle_retry: self.throttle_retry,
+        }
+    }
+
+    /// Builds the configured [`Engine`].
+    pub fn build(self) -> Engine {
+        Engine(Arc::new(self.build_inner()))
+    }
+}
+
+/// A clonable interface to create and manage IRC XDCC requests.
+#[derive(Clone, Debug, Default)]
+pub struct Engine(Arc<InnerEngine>);
+
+impl Engine {
+    /// Creates a builder to configure a new `Engine`.
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    /// Creates an engine with the default settings, establishing the connections with
+    /// the given connector, like the fake servers of the tests.
+    ///
+    /// See [`EngineBuilder::connector`] to combine it with other settings.
+    pub fn with_transport(connector: impl Connector + 'static) -> Self {
+        Self::builder().connector(connector).build()
+    }
+
+    /// Changes the timeout of the phases of the requests, like [`EngineBuilder::timeout`],
+    /// for the phases starting afterwards, including the ones of the running requests.
+    ///
+    /// With the [`EngineBuilder::adaptive_timeout`], it's the timeout of the servers
+    /// whose latency is not measured yet.
+    pub fn set_timeout(&self, value: Duration) {
+        self.0.latencies.set_fallback(value);
+    }
+
+    /// Changes the maximum number of requests executed concurrently on a same server,
+    /// like [`EngineBuilder::max_requests_per_server`], `None` removing the limit.
+    ///
+    /// The running requests count against the new limit, so the waiting ones start
+    /// right away when it's raised or removed, or once enough requests complete when
+    /// it's lowered. The requests started while the servers were unlimited don't count,
+    /// as they hold no slot. The limits of the networks are not affected.
+    pub fn set_max_requests_per_server(&self, value: Option<usize>) {
+        self.0.limiter.set_limit(value);
+    }
+
+    /// Changes the maximum number of requests executed concurrently on the given server
+    /// or network, like [`Network::max_requests`], `None` falling back to the limit per
+    /// server.
+    ///
+    /// The running requests count against the new limit, like with
+    /// [`Engine::set_max_requests_per_server`].
+    pub fn set_server_max_requests(&self, server: impl Into<String>, value: Option<usize>) {
+        self.0.limiter.set_server_limit(server.into(), value);
+    }
+
+    /// Changes the maximum number of requests executed concurrently with the given bot,
+    /// like [`Bot::max_requests`], `None` removing the limit.
+    ///
+    /// The running requests count against the new limit, like with
+    /// [`Engine::set_max_requests_per_server`].
+    pub fn set_bot_max_requests(&self, botname: impl AsRef<str>, value: Option<usize>) {
+        let botname = botname.as_ref().to_ascii_lowercase();
+        self.0.bot_limiter.set_server_limit(botname, value);
+    }
+
+    /// Changes the minimum interval between two commands sent on the given server or
+    /// network, like [`Network::request_interval`], `None` removing it.
+    ///
+    /// The requests already waiting for their turn keep it.
+    pub fn set_request_interval(&self, server: impl Into<String>, value: Option<Duration>) {
+        self.0.pacer.set_interval(server.into(), value);
+    }
+
+    /// Subscribes to the events of all the requests executed by this engine, and of the
+    /// transfers created by their [`Presence`] with the `transfer` feature.
+    ///
+    /// Only the events emitted after the subscription are received.
+    pub fn events(&self) -> tokio::sync::broadcast::Receiver<Event> {
+        self.0.events.subscribe()
+    }
+
+    /// Resolves the given IRC server address ahead of time and keeps it in cache,
+    /// so the following requests on this server don't wait for the DNS resolution.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address cannot be resolved.
+    pub async fn prefetch(&self, server: impl AsRef<str>) -> Result<()> {
+        let server = server.as_ref();
+        match self.0.networks.get(server) {
+            Some(network) => {
+                for host in network.servers() {
+                    self.0.dns.resolve(host).await?;
+                }
+            }
+            None => {
+                self.0.dns.resolve(server).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the health of the given IRC server by connecting, registering and disconnecting,
+    /// measuring the latency of each step, and reporting whether the connection is encrypted.
+    ///
+    /// This lets orchestrators pick among candidate servers before queueing requests.
+    /// The measured latencies are used by the [`EngineBuilder::adaptive_timeout`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection or the registration fails.
+    pub async fn probe(&self, server: impl AsRef<str>) -> Result<ServerProbe> {
+        let mut timings = Timings::default();
+        let connection = self.0.connect(server.as_ref(), &mut timings, None).await?;
+        let address = connection.peer_addr();
+        let tls = connection.tls();
+        // the server is healthy, failing to leave properly doesn't matter
+        let _ = connection.quit().await;
+        Ok(ServerProbe {
+            address,
+            connect: timings.connect,
+            register: timings.register,
+            tls,
+        })
+    }
+
+    /// Closes the idle connections kept by [`Engine::warm`], sending them a `QUIT`
+    /// message, so the application can shut down without leaving them to time out.
+    ///
+    /// The requests being executed are not affected. Dropping the engine closes the
+    /// idle connections as well, without waiting for the `QUIT` messages to be sent.
+    pub async fn close(&self) {
+        for connection in self.0.pool.drain() {
+            // the connection is discarded anyway, failing to leave properly doesn't matter
+            let _ = connection.quit().await;
+        }
+    }
+
+    /// Connects and registers to the given IRC server ahead of time.
+    ///
+    /// The next request on this server reuses the connection and only has to join
+    /// the channel and send the XDCC command. The connection is discarded if it's
+    /// not used before the configured [`EngineBuilder::warm_ttl`], and replaced by a
+    /// new one if the server closed it meanwhile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection or the registration fails.
+    pub async fn warm(&self, server: impl AsRef<str>) -> Result<()> {
+        let server = server.as_ref();
+        let connection = self
+            .0
+            .connect(server, &mut Timings::default(), None)
+            .await?;
+        self.0.pool.put(server, connection);
+        Ok(())
+    }
+
+    /// Executes the given requests, with at most `max_concurrent` of them running at the same time.
+    ///
+    /// The returned stream yields the information and the result of each request as they complete,
+    /// which can be in a different order than the given one. The limit of
+    /// [`EngineBuilder::max_requests_per_server`] still applies.
+    pub fn execute_all(
+        &self,
+        requests: impl IntoIterator<Item = Request>,
+        max_concurrent: usize,
+    ) -> impl Stream<Item = (RequestInfo, Result<Response>)> {
+        use futures_util::StreamExt;
+
+        futures_util::stream::iter(requests)
+            .map(|request| async move {
+                let result = request.execute().await;
+                (request.info, result)
+            })
+            .buffer_unordered(max_concurrent.max(1))
+    }
+
+    /// Create a new XDCC `Request` using the given parameters.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - IRC server address.
+    /// * `channel` - IRC channel to join.
+    /// * `botname` - Bot's nickname to send the XDCC request to.
+    /// * `pack` - XDCC pack number, or name for the bots addressing their packs by name.
+    pub fn create_request(
+        &self,
+        server: impl Into<String>,
+        channel: impl Into<String>,
+        botname: impl Into<String>,
+        pack: impl Into<PackId>,
+    ) -> Request {
+        let botname = botname.into();
+        Request {
+            dialect: self.0.dialect(&botname),
+            inner: self.0.clone(),
+            info: RequestInfo {
+                id: RequestId::next(),
+                server: Arc::from(server.into()),
+                channel: Arc::from(channel.into()),
+                botname: Arc::from(botname),
+                pack: pack.into(),
+            },
+        }
+    }
+
+    /// Create a XDCC [`Batch`] requesting the given range of packs with a single command,
+    /// for the bots supporting `xdcc batch`.
+    pub fn create_batch(
+        &self,
+        server: impl Into<String>,
+        channel: impl Into<String>,
+        botname: impl Into<String>,
+        packnums: RangeInclusive<u64>,
+    ) -> Batch {
+        Batch {
+            request: self.create_request(server, channel, botname, *packnums.start()),
+            packnums,
+        }
+    }
+
+    /// Create a XDCC `Request` for each of the given pack numbers, like a range.
+    ///
+    /// The requests share the server, channel and bot names. They can be executed
+    /// together with [`Engine::execute_all`].
+    ///
+    /// ```no_run
+    /// # async fn run() {
+    /// use futures_util::StreamExt;
+    /// use xdcc_request::Engine;
+    ///
+    /// let engine = Engine::default();
+    /// let requests = engine.create_requests("irc.example.net", "#channel", "bot", 1..=25);
+    /// let mut results = engine.execute_all(requests, 4);
+    /// while let Some((info, result)) = results.next().await {
+    ///     println!("pack {}: {:?}", info.pack, result);
+    /// }
+    /// # }
+    /// ```
+    pub fn create_requests(
+        &self,
+        server: impl Into<String>,
+        channel: impl Into<String>,
+        botname: impl Into<String>,
+        packnums: impl IntoIterator<Item = u64>,
+    ) -> Vec<Request> {
+        let server: Arc<str> = Arc::from(server.into());
+        let channel: Arc<str> = Arc::from(channel.into());
+        let botname: Arc<str> = Arc::from(botname.into());
+        let dialect = self.0.dialect(&botname);
+        packnums
+            .into_iter()
+            .map(|packnum| Request {
+                dialect: dialect.clone(),
+                inner: self.0.clone(),
+                info: RequestInfo {
+                    id: RequestId::next(),
+                    server: server.clone(),
+                    channel: channel.clone(),
+                    botname: botname.clone(),
+                    pack: PackId::Number(packnum),
+                },
+            })
+            .collect()
+    }
+
+    /// Create a XDCC `Request` for each pack of the bot selected by the given selector.
+    ///
+    /// The numbers and ranges select their packs directly, while the filename patterns
+    /// are resolved against the given listing of the bot, like the announcements parsed
+    /// with [`PackAnnouncement::parse`](crate::PackAnnouncement::parse).
+    ///
+    /// ```
+    /// use xdcc_request::{Engine, PackAnnouncement, PackSelector};
+    ///
+    /// let listing = [
+    ///     PackAnnouncement::parse("#1 12x [700M] Show.S01E01.mkv").unwrap(),
+    ///     PackAnnouncement::parse("#2 3x [1K] notes.txt").unwrap(),
+    /// ];
+    /// let engine = Engine::default();
+    /// let selector = PackSelector::Glob("*.mkv".into());
+    /// let requests =
+    ///     engine.create_selected_requests("irc.example.net", "#channel", "bot", selector, &listing);
+    /// assert_eq!(requests.len(), 1);
+    /// ```
+    pub fn create_selected_requests<'a>(
+        &self,
+        server: impl Into<String>,
+        channel: impl Into<String>,
+        botname: impl Into<String>,
+        selector: impl Into<PackSelector>,
+        listing: impl IntoIterator<Item = &'a PackAnnouncement>,
+    ) -> Vec<Request> {
+        let selector = selector.into();
+        let packnums = match selector.packnums() {
+            Some(packnums) => packnums.collect(),
+            None => selector.select(
+                listing
+                    .into_iter()
+                    .map(|pack| (pack.packnum, pack.filename.as_str())),
+            ),
+        };
+        self.create_requests(server, channel, botname, packnums)
+    }
+
+    /// Downloads to the given directory each pack of the bot selected by the given
+    /// selector, resolved against the given listing like with
+    /// [`Engine::create_selected_requests`].
+    ///
+    /// The packs are downloaded one after the other, as most bots allow a single
+    /// transfer per user. Each file is saved under its offered filename, made safe like
+    /// with the default [`FilenameTemplate`](crate::FilenameTemplate), and left partial
+    /// when its transfer fails. The returned stream yields the information of each
+    /// request with the statistics of its download.
+    ///
+    /// ```no_run
+    /// # async fn run(listing: Vec<xdcc_request::PackAnnouncement>) {
+    /// use futures_util::StreamExt;
+    /// use xdcc_request::{Engine, PackSelector};
+    ///
+    /// let engine = Engine::default();
+    /// let selector = PackSelector::Glob("*.mkv".into());
+    /// let downloads =
+    ///     engine.download_matching("irc.example.net", "#channel", "bot", selector, &listing, "downloads");
+    /// let mut downloads = std::pin::pin!(downloads);
+    /// while let Some((info, result)) = downloads.next().await {
+    ///     println!("pack {}: {:?}", info.pack, result);
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "transfer")]
+    pub fn download_matching<'a>(
+        &self,
+        server: impl Into<String>,
+        channel: impl Into<String>,
+        botname: impl Into<String>,
+        selector: impl Into<PackSelector>,
+        listing: impl IntoIterator<Item = &'a PackAnnouncement>,
+        directory: impl Into<std::path::PathBuf>,
+    ) -> impl Stream<Item = (RequestInfo, Result<crate::TransferStats>)> {
+        use futures_util::StreamExt;
+
+        let requests = self.create_selected_requests(server, channel, botname, selector, listing);
+        let directory = directory.into();
+        futures_util::stream::iter(requests).then(move |request| {
+            let directory = directory.clone();
+            async move {
+                let result = request.download_to(&directory).await;
+                (request.info, result)
+            }
+        })
+    }
+}
+
+/// Unique identifier of a request, to correlate its events, logs and errors.
+///
+/// The identifiers are unique across all the engines of the process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    /// Generates a new identifier.
+    pub(crate) fn next() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(1);
+        Self(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Numeric value of the identifier.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Information needed to perform a XDCC request.
+///
+/// The strings are shared, so cloning the information doesn't allocate.
+#[derive(Clone, Debug)]
+pub struct RequestInfo {
+    /// Unique identifier of the request.
+    pub id: RequestId,
+    /// IRC server address.
+    pub server: Arc<str>,
+    /// IRC channel to join.
+    pub channel: Arc<str>,
+    /// Bot nickname to send request to.
+    pub botname: Arc<str>,
+    /// XDCC pack identifier, usually its number.
+    pub pack: PackId,
+}
+
+/// A single XDCC request created from an `Engine`.
+#[derive(Debug)]
+pub struct Request {
+    dialect: Dialect,
+    inner: Arc<InnerEngine>,
+    info: RequestInfo,
+}
+
+/// Slots of a request with its bot and on its server, released when dropped.
+type Permits = (Option<limiter::Permit>, Option<limiter::Permit>);
+
+/// Connection of a request staying joined to the channels after the offer, created by
+/// [`Request::execute_and_stay`] or [`Batch::execute_and_stay`].
+///
+/// The slots of the request with its bot and server are held until the presence ends.
+/// The pings of the server are only answered while [`Presence::stay`] or
+/// [`Presence::stay_during`] runs, and the connection is closed when the presence is
+/// dropped.
+#[derive(Debug)]
+pub struct Presence {
+    /// Interval of the activity sent while staying, disabled when `None`.
+    anti_idle: Option<Duration>,
+    /// Offers of the batch still expected, when created by a batch.
+    batch: Option<BatchOffers>,
+    /// Nickname of the bot of the request.
+    botname: Arc<str>,
+    /// Comma-separated channels joined by the request, until parted.
+    channels: Option<String>,
+    connection: connection::Connection,
+    /// End of the lifetime of the request, unlimited when `None`.
+    deadline: Option<Instant>,
+    _permits: Permits,
+    /// Audit log the outcome of the transfers is stored in.
+    #[cfg(all(feature = "audit", feature = "transfer"))]
+    audit_log: Option<Arc<crate::audit::AuditLog>>,
+    /// Bus the events of the transfers are sent to, with the request.
+    #[cfg(feature = "transfer")]
+    events: (tokio::sync::broadcast::Sender<Event>, RequestInfo),
+    /// Number of times the bot accepted to resume a transfer.
+    #[cfg(feature = "transfer")]
+    resumes: u32,
+    /// Duration the bot has to accept to resume a transfer.
+    #[cfg(feature = "transfer")]
+    resume_timeout: Duration,
+    /// TCP options applied to the DCC connections.
+    #[cfg(feature = "transfer")]
+    socket_options: SocketOptions,
+}
+
+/// Request of a range of packs with a single `xdcc batch #a-#b` command, created from
+/// an `Engine`.
+///
+/// The bot sends the offers one after the other, each once the transfer of the previous
+/// one is complete, so the connection stays open while the offers are consumed. The
+/// pings of the server are only answered while the stream of [`Batch::execute`] is
+/// polled, so the transfers are run with the [`Presence`] of [`Batch::execute_and_stay`]
+/// instead, when they outlast the ping timeout of the server.
+///
+/// ```no_run
+/// # async fn run() {
+/// use futures_util::StreamExt;
+/// use xdcc_request::Engine;
+///
+/// let engine = Engine::default();
+/// let batch = engine.create_batch("irc.example.net", "#channel", "bot", 1..=5);
+/// let offers = batch.execute();
+/// futures_util::pin_mut!(offers);
+/// while let Some(offer) = offers.next().await {
+///     // the next offer is sent by the bot once this file is received
+///     println!("{:?}", offer);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Batch {
+    /// Packs of the batch.
+    packnums: RangeInclusive<u64>,
+    /// Request of the first pack of the batch.
+    request: Request,
+}
+
+/// Progress of the execution of a batch.
+enum BatchState {
+    /// The command is not sent yet.
+    Pending,
+    /// The offers are received on the connection of the presence of the batch.
+    Running(Box<Presence>),
+    /// All the offers are received, or the batch failed.
+    Done,
+}
+
+/// Offers of a batch still expected on the connection of its presence.
+#[derive(Debug)]
+struct BatchOffers {
+    /// Offers received while staying, not returned yet.
+    received: VecDeque<Response>,
+    /// Number of offers not returned yet.
+    remaining: u64,
+    /// Request of the first pack of the batch.
+    request: Request,
+}
+
+/// Waits for the end of the registration on the IRC server.
+///
+/// Returns `Ok(())` once the message of the day has been received, or an error if the stream ends or fails.
+async fn wait_for_registration(
+    mut stream: impl Stream<Item = Result<Message>> + Unpin,
+) -> Result<()> {
+    use futures_util::StreamExt;
+
+    while let Some(message) = stream.next().await.transpose()? {
+        match message.command {
+            Command::Response(IrcResponse::RPL_ENDOFMOTD | IrcResponse::ERR_NOMOTD, _) => {
+                return Ok(());
+            }
+            Command::Response(IrcResponse::ERR_NICKNAMEINUSE, _) => {
+                return Err(ConnectError::NicknameInUse.into());
+            }
+            _ => {}
+        }
+    }
+
+    Err(ProtocolError::ConnectionClosed.into())
+}
+
+/// Authenticates with the `PLAIN` SASL mechanism once the server acknowledged the
+/// `sasl` capability, then ends the capability negotiation.
+///
+/// Returns [`ConnectError::Authentication`] if the server refuses the capability or
+/// the credentials, or an error if the stream ends or fails.
+async fn authenticate(
+    connection: &mut connection::Connection,
+    credentials: &crate::network::SaslCredentials,
+) -> Result<()> {
+    use futures_util::StreamExt;
+    use irc::proto::CapSubCommand;
+
+    /// Maximum length of the payload of an `AUTHENTICATE` message.
+    const CHUNK_LENGTH: usize = 400;
+
+    while let Some(message) = connection.next().await.transpose()? {
+        match message.command {
+            Command::CAP(_, CapSubCommand::ACK, _, _) => {
+                connection
+                    .send(Command::AUTHENTICATE("PLAIN".to_owned()))
+                    .await?;
+            }
+            Command::AUTHENTICATE(ref data) if data == "+" => {
+                let payload = credentials.plain();
+                for chunk in payload.as_bytes().chunks(CHUNK_LENGTH) {
+                    let chunk = String::from_utf8_lossy(chunk).into_owned();
+                    connection.send(Command::AUTHENTICATE(chunk)).await?;
+                }
+                // a payload filling the last message is terminated by an empty one
+                if payload.len().is_multiple_of(CHUNK_LENGTH) {
+                    connection
+                        .send(Command::AUTHENTICATE("+".to_owned()))
+                        .await?;
+                }
+            }
+            Command::Response(IrcResponse::RPL_SASLSUCCESS, _) => {
+                return connection
+                    .send(Command::CAP(None, CapSubCommand::END, None, None))
+                    .await;
+            }
+            Command::CAP(_, CapSubCommand::NAK, _, _)
+            | Command::Response(
+                IrcResponse::ERR_SASLFAIL
+                | IrcResponse::ERR_SASLTOOLONG
+                | IrcResponse::ERR_SASLABORT,
+                _,
+            ) => return Err(ConnectError::Authentication.into()),
+            Command::Response(IrcResponse::ERR_NICKNAMEINUSE, _) => {
+                return Err(ConnectError::NicknameInUse.into());
+            }
+            _ => {}
+        }
+    }
+
+    Err(ProtocolError::ConnectionClosed.into())
+}
+
+/// Waits for the first private message from the IRC server.
+///
+/// Returns the rules detected in the topic and the notices received meanwhile once a
+/// `PRIVMSG` is received, or an error if the stream ends or fails.
+async fn wait_for_first_private_message(
+    mut stream: impl Stream<Item = Result<Message>> + Unpin,
+) -> Result<ChannelRules> {
+    use futures_util::StreamExt;
+
+    let mut rules = ChannelRules::default();
+    while let Some(message) = stream.next().await.transpose()? {
+        match message.command {
+            Command::PRIVMSG(_, _) => return Ok(rules),
+            Command::NOTICE(_, ref text) | Command::TOPIC(_, Some(ref text)) => {
+                rules = rules.merge(ChannelRules::parse(text));
+            }
+            Command::Response(IrcResponse::RPL_TOPIC, ref args) => {
+                if let Some(topic) = args.last() {
+                    rules = rules.merge(ChannelRules::parse(topic));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Err(ProtocolError::ConnectionClosed.into())
+}
+
+/// Waits for a DCC SEND response from the IRC bot.
+///
+/// The offers not answering the command of the given dialect are ignored, and the
+/// positions in the queue announced by the bot are given to `on_queued`.
+///
+/// Returns a parsed [`Response`], [`OfferError::Throttled`] if the bot asks to wait
+/// before requesting again, [`OfferError::Refused`] if it refuses the request, or an
+/// error if the stream ends or times out.
+async fn wait_for_dcc_response(
+    mut stream: impl Stream<Item = Result<Message>> + Unpin,
+    botnames: &[&str],
+    dialect: &Dialect,
+    notices: &notice::NoticePatterns,
+    mut on_queued: impl FnMut(QueueStatus),
+) -> Result<Response> {
+    use futures_util::StreamExt;
+
+    while let Some(message) = stream.next().await.transpose()? {
+        if let irc::proto::Command::PRIVMSG(_, ref cmd) = message.command
+            && let Some(mut res) = Response::decode(cmd)
+            && dialect.accepts(&res)
+        {
+            res.sender = message.prefix.as_ref().map(ToString::to_string);
+            return Ok(res);
+        }
+        let Some(line) = validation::bot_line(message, botnames) else {
+            continue;
+        };
+        match notices.classify(&line) {
+            Some(notice::Notice::Queued(status)) => on_queued(status),
+            Some(notice::Notice::Throttled(delay)) => {
+                return Err(OfferError::Throttled(delay).into());
+            }
+            #[cfg(feature = "regex")]
+            Some(notice::Notice::Refused) => return Err(OfferError::Refused(line).into()),
+            None => {}
+        }
+    }
+
+    Err(ProtocolError::ConnectionClosed.into())
+}
+
+/// Waits for the `DCC ACCEPT` message of the transfer offered on the given port.
+///
+/// Returns the position the transfer resumes at, or an error if the stream ends or fails.
+#[cfg(feature = "transfer")]
+async fn wait_for_resume_accept(
+    mut stream: impl Stream<Item = Result<Message>> + Unpin,
+    port: u16,
+) -> Result<u64> {
+    use futures_util::StreamExt;
+
+    while let Some(message) = stream.next().await.transpose()? {
+        if let Command::PRIVMSG(_, ref text) = message.command
+            && let Some(accept) = crate::ResumeAccept::decode(text)
+            && accept.port == port
+        {
+            return Ok(accept.position);
+        }
+    }
+
+    Err(ProtocolError::ConnectionClosed.into())
+}
+
+impl Request {
+    /// Information identifying the request.
+    pub fn info(&self) -> &RequestInfo {
+        &self.info
+    }
+
+    /// Sends the request in the given dialect, instead of `xdcc send #N`.
+    ///
+    /// ```
+    /// use xdcc_request::{Dialect, Engine};
+    ///
+    /// let engine = Engine::default();
+    /// let request = engine
+    ///     .create_request("irc.example.net", "#channel", "bot", 0)
+    ///     .with_dialect(Dialect::Trigger {
+    ///         trigger: "!files".into(),
+    ///         filename: "ubuntu.iso".into(),
+    ///     });
+    /// ```
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Executes the XDCC request by connecting to the IRC server,
+    /// identifying, joining the channel, sending the XDCC command,
+    /// and awaiting the DCC SEND response.
+    ///
+    /// A connection prepared with [`Engine::warm`] is used when available.
+    /// The request waits first for a slot when the server reached the
+    /// [`EngineBuilder::max_requests_per_server`] limit.
+    ///
+    /// No background task is spawned: dropping the returned future closes the
+    /// connection, sending a `QUIT` message when possible, which makes it safe to
+    /// use within `select!` or timeout wrappers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Error::Request`] wrapping the connection, protocol or offer error,
+    /// with the information of the request.
+    pub async fn execute(&self) -> Result<Response> {
+        self.execute_with_timings()
+            .await
+            .map(|(response, _timings)| response)
+    }
+
+    /// Executes the XDCC request like [`Request::execute`], returning as well the time
+    /// spent in each step of the request.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Error::Request`] wrapping the connection, protocol or offer error.
+    pub async fn execute_with_timings(&self) -> Result<(Response, Timings)> {
+        self.execute_inner(false)
+            .await
+            .map(|(response, timings, _presence)| (response, timings))
+    }
+
+    /// Executes the XDCC request like [`Request::execute`], staying connected and joined
+    /// to the channels with the returned [`Presence`], as some bots cancel or deprioritize
+    /// the transfers of the users leaving right away.
+    ///
+    /// The identical requests are not deduplicated, as each one needs its own connection.
+    ///
+    /// ```no_run
+    /// # async fn run() -> xdcc_request::Result<()> {
+    /// use std::time::Duration;
+    /// use xdcc_request::Engine;
+    ///
+    /// let engine = Engine::default();
+    /// let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+    /// let (response, presence) = request.execute_and_stay().await?;
+    /// let transfer = async {
+    ///     // receive the file from response.socket_addr()
+    /// };
+    /// presence.stay(transfer, Duration::from_secs(60)).await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Error::Request`] wrapping the connection, protocol or offer error.
+    pub async fn execute_and_stay(&self) -> Result<(Response, Presence)> {
+        let (response, _timings, presence) = self.execute_inner(true).await?;
+        let presence = presence.ok_or(ProtocolError::ConnectionClosed)?;
+        Ok((response, presence))
+    }
+
+    /// Executes the XDCC request, keeping the connection when `stay` is set.
+    async fn execute_inner(&self, stay: bool) -> Result<(Response, Timings, Option<Presence>)> {
+        self.emit(EventKind::Started);
+        #[cfg(feature = "audit")]
+        let started_time = std::time::SystemTime::now();
+        let started_at = Instant::now();
+        let deadline = self
+            .inner
+            .max_lifetime
+            .map(|lifetime| started_at + lifetime);
+        let mut timings = Timings::default();
+        let transcript = self
+            .inner
+            .capture_transcript
+            .then(connection::Transcript::default);
+        let run = self.run(&mut timings, transcript.as_ref(), stay);
+        #[cfg(feature = "tracing")]
+        let span = self.span("xdcc_request");
+        let result = instrument!(self.inner.within_lifetime(run), span.clone()).await;
+        #[cfg(feature = "tracing")]
+        telemetry::record_span_outcome(&span, &result);
+        telemetry::record_request(started_at.elapsed(), &result);
+        #[cfg(feature = "audit")]
+        if let Some(ref audit_log) = self.inner.audit_log
+            && let Err(err) = audit_log
+                .record(
+                    &self.info,
+                    started_time,
+                    result.as_ref().map(|(response, _)| response),
+                )
+                .await
+        {
+            warn!(
+                "unable to store request {} in the audit log: {err}",
+                self.info.id
+            );
+        }
+        match result {
+            Ok((ref response, _)) => self.emit(EventKind::OfferReceived(response.clone())),
+            Err(ref err) => self.emit(EventKind::Failed(err.to_string())),
+        }
+        result
+            .map(|(response, presence)| {
+                let presence = presence.map(|presence| Presence {
+                    deadline,
+                    ..presence
+                });
+                (response, timings, presence)
+            })
+            .map_err(|err| Error::Request {
+                info: self.info.clone(),
+                source: Box::new(err),
+                transcript: transcript.map(|inner| inner.take()),
+            })
+    }
+
+    /// Span covering the request, identified by its information.
+    #[cfg(feature = "tracing")]
+    fn span(&self, name: &'static str) -> tracing::Span {
+        tracing::info_span!(
+            "request",
+            otel.name = name,
+            otel.kind = "client",
+            otel.status_code = tracing::field::Empty,
+            otel.status_description = tracing::field::Empty,
+            id = self.info.id.get(),
+            server = &*self.info.server,
+            channel = &*self.info.channel,
+            bot = &*self.info.botname,
+            pack = %self.info.pack,
+        )
+    }
+
+    /// Sends an event about this request to the subscribers of the engine.
+    fn emit(&self, kind: EventKind) {
+        match kind {
+            EventKind::Failed(ref reason) => warn!(
+                "request {} of pack {} to {} on {} failed: {reason}",
+                self.info.id, self.info.pack, self.info.botname, self.info.server
+            ),
+            EventKind::Throttled(delay) => warn!(
+                "request {} of pack {} to {} on {} throttled, requesting again in {delay:?}",
+                self.info.id, self.info.pack, self.info.botname, self.info.server
+            ),
+            ref other => info!(
+                "request {} of pack {} to {} on {}: {other:?}",
+                self.info.id, self.info.pack, self.info.botname, self.info.server
+            ),
+        }
+        // no subscriber is not an error
+        let _ = self.inner.events.send(Event {
+            request: self.info.clone(),
+            kind,
+        });
+    }
+
+    /// Reports the given error of the offer of a batch, and wraps it with the
+    /// information of the request.
+    fn batch_failed(&self, err: Error) -> Error {
+        self.emit(EventKind::Failed(err.to_string()));
+        Error::Request {
+            info: self.info.clone(),
+            source: Box::new(err),
+            transcript: None,
+        }
+    }
+
+    /// Executes the request and downloads the offered file to the given directory,
+    /// staying connected during the transfer.
+    #[cfg(feature = "transfer")]
+    async fn download_to(&self, directory: &std::path::Path) -> Result<crate::TransferStats> {
+        let (response, presence) = self.execute_and_stay().await?;
+        let path = directory.join(crate::FilenameTemplate::default().render(&self.info, &response));
+        let file = tokio::fs::File::create(&path)
+            .await
+            .map_err(crate::TransferError::Io)?;
+        let transfer = presence.transfer(&response);
+        presence
+            .stay(transfer.download_with_stats(file), Duration::ZERO)
+            .await
+    }
+
+    /// Checks that the pack appears to exist and be requestable, without ever accepting
+    /// a DCC offer.
+    ///
+    /// The request connects, joins the channel and asks the bot the details of the pack
+    /// with the `xdcc info` command, which makes it a cheap pre-flight check.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Error::Request`] wrapping the connection or protocol error, or
+    /// [`OfferError::Timeout`] when the bot doesn't answer in time.
+    pub async fn validate(&self) -> Result<Validation> {
+        let mut timings = Timings::default();
+        let transcript = self
+            .inner
+            .capture_transcript
+            .then(connection::Transcript::default);
+        let run = self.run_validation(&mut timings, transcript.as_ref());
+        #[cfg(feature = "tracing")]
+        let span = self.span("xdcc_validation");
+        let result = instrument!(self.inner.within_lifetime(run), span.clone()).await;
+        #[cfg(feature = "tracing")]
+        telemetry::record_span_outcome(&span, &result);
+        result.map_err(|err| Error::Request {
+            info: self.info.clone(),
+            source: Box::new(err),
+            transcript: transcript.map(|inner| inner.take()),
+        })
+    }
+
+    /// Connects to the IRC server, reusing a warm connection when available,
+    /// and joins the channel.
+    ///
+    /// A warm connection closed by the server is replaced by a new one, as the idle
+    /// connections don't answer the pings of the server.
+    async fn join(
+        &self,
+        timings: &mut Timings,
+        transcript: Option<&connection::Transcript>,
+    ) -> Result<connection::Connection> {
+        let warm = std::iter::from_fn(|| self.inner.pool.take(&self.info.server))
+            .find_map(|mut connection| connection.is_alive().then_some(connection));
+        if let Some(mut connection) = warm {
+            if let Some(transcript) = transcript {
+                connection.set_transcript(transcript.clone());
+            }
+            self.emit(EventKind::Connected);
+            match self.enter(connection, timings).await {
+                Err(Error::Protocol(ProtocolError::ConnectionClosed | ProtocolError::Irc(_))) => {
+                    warn!(
+                        "request {}: the warm connection was lost, connecting again",
+                        self.info.id
+                    );
+                }
+                result => return result,
+            }
+        }
+        let connection = self
+            .inner
+            .connect(&self.info.server, timings, transcript)
+            .await?;
+        self.emit(EventKind::Connected);
+        self.enter(connection, timings).await
+    }
+
+    /// Joins the channels on the given connection, and waits the delay before requesting.
+    async fn enter(
+        &self,
+        mut connection: connection::Connection,
+        timings: &mut Timings,
+    ) -> Result<connection::Connection> {
+        let started_at = Instant::now();
+        if self.inner.check_bot_presence {
+            let Some(whois) = self.whois_bot(&mut connection).await? else {
+                return Err(OfferError::BotOffline(self.info.botname.to_string()).into());
+            };
+            debug!(
+                "request {}: {} is in {:?}, idle for {:?}",
+                self.info.id, self.info.botname, whois.channels, whois.idle
+            );
+        }
+        connection
+            .send(Command::JOIN(self.channels(), None, None))
+            .await?;
+
+        let rules = self
+            .inner
+            .run_phase(
+                &self.info.server,
+                latency::Phase::Join,
+                wait_for_first_private_message(&mut connection),
+            )
+            .await?;
+        timings.join = started_at.elapsed();
+        self.emit(EventKind::Joined);
+
+        if !rules.is_empty() {
+            self.emit(EventKind::ChannelRules(rules));
+        }
+        let mut delay = self
+            .bot()
+            .and_then(|bot| bot.request_delay)
+            .or_else(|| {
+                let channel = self.info.channel.to_ascii_lowercase();
+                self.inner.channel_request_delays.get(&channel).copied()
+            })
+            .or_else(|| {
+                let network = self.inner.networks.get(&*self.info.server)?;
+                network.request_delay
+            })
+            .unwrap_or(self.inner.request_delay);
+        if let Some(max_delay) = self.inner.honor_channel_rules
+            && let Some(detected) = rules.request_delay
+        {
+            delay = delay.max(detected.min(max_delay));
+        }
+        if !delay.is_zero() {
+            debug!(
+                "request {}: waiting {delay:?} before requesting",
+                self.info.id
+            );
+            tokio::time::sleep(delay).await;
+        }
+
+        Ok(connection)
+    }
+
+    /// Settings of the bot of the request, when registered.
+    fn bot(&self) -> Option<&Bot> {
+        self.inner.bots.get(&self.info.botname.to_ascii_lowercase())
+    }
+
+    /// Nicknames of the bot of the request, starting with the requested one.
+    fn botnames(&self) -> Vec<&str> {
+        let aliases = self.bot().into_iter().flat_map(|bot| &bot.aliases);
+        std::iter::once(&*self.info.botname)
+            .chain(aliases.map(String::as_str))
+            .collect()
+    }
+
+    /// Details of the bot, under the first of its nicknames connected to the server.
+    async fn whois_bot(
+        &self,
+        connection: &mut connection::Connection,
+    ) -> Result<Option<whois::Whois>> {
+        for botname in self.botnames() {
+            connection
+                .send(Command::WHOIS(None, botname.to_string()))
+                .await?;
+            let whois = self
+                .inner
+                .run_phase(
+                    &self.info.server,
+                    latency::Phase::Join,
+                    whois::wait_for_whois(&mut *connection, botname),
+                )
+                .await?;
+            if whois.is_some() {
+                return Ok(whois);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for a slot with the bot, then on the server.
+    ///
+    /// The bot is waited first, so the requests waiting for a busy bot don't hold the
+    /// slots of the server needed by the requests to other bots.
+    async fn acquire(&self) -> Permits {
+        let bot = self
+            .inner
+            .bot_limiter
+            .acquire(&self.info.botname.to_ascii_lowercase())
+            .await;
+        (bot, self.inner.limiter.acquire(&self.info.server).await)
+    }
+
+    /// Presence of the request on the given connection, holding the given slots.
+    fn presence(&self, connection: connection::Connection, permits: Permits) -> Presence {
+        Presence {
+            anti_idle: self.inner.anti_idle,
+            batch: None,
+            botname: self.info.botname.clone(),
+            channels: Some(self.channels()),
+            connection,
+            deadline: None,
+            _permits: permits,
+            #[cfg(all(feature = "audit", feature = "transfer"))]
+            audit_log: self.inner.audit_log.clone(),
+            #[cfg(feature = "transfer")]
+            events: (self.inner.events.clone(), self.info.clone()),
+            #[cfg(feature = "transfer")]
+            resumes: 0,
+            #[cfg(feature = "transfer")]
+            resume_timeout: self
+                .inner
+                .latencies
+                .timeout(&self.info.server, latency::Phase::Offer),
+            #[cfg(feature = "transfer")]
+            socket_options: self.inner.socket_options.clone(),
+        }
+    }
+
+    /// Comma-separated channels to join: the channel of the request, then the default
+    /// channels of the engine, of the network and of the bot, without duplicates.
+    fn channels(&self) -> String {
+        let network = self.inner.networks.get(&*self.info.server);
+        let defaults = self
+            .inner
+            .default_channels
+            .iter()
+            .chain(network.into_iter().flat_map(|network| &network.channels))
+            .chain(self.bot().into_iter().flat_map(|bot| &bot.channels));
+        let mut channels = vec![&*self.info.channel];
+        for channel in defaults {
+            if !channels.iter().any(|c| c.eq_ignore_ascii_case(channel)) {
+                channels.push(channel);
+            }
+        }
+        channels.join(",")
+    }
+
+    /// Performs the steps of the validation.
+    async fn run_validation(
+        &self,
+        timings: &mut Timings,
+        transcript: Option<&connection::Transcript>,
+    ) -> Result<Validation> {
+        let _permits = self.acquire().await;
+
+        let mut connection = self.join(timings, transcript).await?;
+        connection
+            .send(Command::PRIVMSG(
+                self.info.botname.to_string(),
+                format!("xdcc info {}", self.info.pack),
+            ))
+            .await?;
+
+        let first = self
+            .inner
+            .run_phase(
+                &self.info.server,
+                latency::Phase::Offer,
+                validation::wait_for_reply(&mut connection, &self.botnames()),
+            )
+            .await?;
+        let mut info = vec![first];
+        info.extend(
+            validation::collect_reply(
+                &mut connection,
+                &self.botnames(),
+                validation::REPLY_QUIET_PERIOD,
+            )
+            .await?,
+        );
+
+        // the reply is already received, failing to leave properly doesn't matter
+        let _ = connection.quit().await;
+
+        Ok(Validation::from_reply(info))
+    }
+
+    /// Waits for the offer of the bot answering the command of the given dialect,
+    /// querying the position in its queue at the configured interval.
+    async fn wait_for_offer(
+        &self,
+        connection: &mut connection::Connection,
+        dialect: &Dialect,
+    ) -> Result<Response> {
+        let on_queued = |status| self.emit(EventKind::Queued(status));
+        let botnames = self.botnames();
+        let notices = &self.inner.notice_patterns;
+        let Some(interval) = self.inner.queue_poll else {
+            return wait_for_dcc_response(connection, &botnames, dialect, notices, on_queued).await;
+        };
+        loop {
+            let wait =
+                wait_for_dcc_response(&mut *connection, &botnames, dialect, notices, on_queued);
+            if let Ok(result) = tokio::time::timeout(interval, wait).await {
+                return result;
+            }
+            connection
+                .send(Command::PRIVMSG(
+                    self.info.botname.to_string(),
+                    "xdcc queue".into(),
+                ))
+                .await?;
+        }
+    }
+
+    /// Sends the command in the given dialect and waits for the offer, requesting again
+    /// after the delays the bot asks for, within the configured bounds.
+    async fn request_offer(
+        &self,
+        connection: &mut connection::Connection,
+        dialect: &Dialect,
+    ) -> Result<Response> {
+        let (max_retries, max_delay) = self.inner.throttle_retry;
+        let mut retries = 0;
+        loop {
+            self.inner.pacer.wait(&self.info.server).await;
+            connection.send(dialect.command(&self.info)).await?;
+            self.emit(EventKind::RequestSent);
+            let result = self
+                .inner
+                .run_phase(
+                    &self.info.server,
+                    latency::Phase::Offer,
+                    self.wait_for_offer(connection, dialect),
+                )
+                .await;
+            match result {
+                Err(Error::Offer(OfferError::Throttled(delay)))
+                    if retries < max_retries && delay <= max_delay =>
+                {
+                    retries += 1;
+                    self.emit(EventKind::Throttled(delay));
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends the command in the given dialect like [`Request::request_offer`], then again
+    /// from the channels of the bot not joined yet when it doesn't answer and the channel
+    /// discovery is enabled.
+    async fn discover_offer(
+        &self,
+        connection: &mut connection::Connection,
+        dialect: &Dialect,
+    ) -> Result<Response> {
+        let result = self.request_offer(connection, dialect).await;
+        if !self.inner.channel_discovery
+            || !matches!(result, Err(Error::Offer(OfferError::Timeout)))
+        {
+            return result;
+        }
+
+        let whois = self.whois_bot(connection).await?;
+        let joined = self.channels();
+        let channels: Vec<String> = whois
+            .into_iter()
+            .flat_map(|whois| whois.channels)
+            .filter(|channel| {
+                !joined
+                    .split(',')
+                    .any(|joined| joined.eq_ignore_ascii_case(channel))
+            })
+            .collect();
+        if channels.is_empty() {
+            return result;
+        }
+
+        debug!(
+            "request {}: joining {channels:?} where {} is",
+            self.info.id, self.info.botname
+        );
+        connection
+            .send(Command::JOIN(channels.join(","), None, None))
+            .await?;
+        match self.request_offer(connection, dialect).await {
+            Err(Error::Offer(OfferError::Timeout)) => {
+                Err(OfferError::TimeoutAfterDiscovery { channels }.into())
+            }
+            result => result,
+        }
+    }
+
+    /// Sends the command in each of the [`Dialect::PROBED`] until the bot offers the file,
+    /// and remembers the dialect it answered.
+    async fn probe_dialect(&self, connection: &mut connection::Connection) -> Result<Response> {
+        for dialect in Dialect::PROBED {
+            self.inner.pacer.wait(&self.info.server).await;
+            connection.send(dialect.command(&self.info)).await?;
+            self.emit(EventKind::RequestSent);
+            let wait = self.wait_for_offer(connection, &dialect);
+            let Ok(response) = tokio::time::timeout(self.inner.dialect_probe_timeout, wait).await
+            else {
+                debug!(
+                    "request {}: {} didn't answer the {dialect:?} dialect",
+                    self.info.id, self.info.botname
+                );
+                continue;
+            };
+            let response = response?;
+            self.inner
+                .dialects
+                .insert(&self.info.server, &self.info.botname, dialect);
+            return Ok(response);
+        }
+        Err(OfferError::Timeout.into())
+    }
+
+    /// Performs the steps of the request like [`Request::run_request`], unless an
+    /// identical request is running and receives the offer.
+    async fn run(
+        &self,
+        timings: &mut Timings,
+        transcript: Option<&connection::Transcript>,
+        stay: bool,
+    ) -> Result<(Response, Option<Presence>)> {
+        let in_flight = match self.inner.in_flight {
+            Some(ref in_flight) if !stay => in_flight,
+            _ => return self.run_request(timings, transcript, stay).await,
+        };
+        let joined = in_flight
+            .join(&self.info.server, &self.info.botname, &self.info.pack)
+            .await;
+        let leader = match joined {
+            inflight::Joined::Done(response) => {
+                debug!(
+                    "request {}: offer received by an identical request",
+                    self.info.id
+                );
+                return Ok((response, None));
+            }
+            inflight::Joined::Leader(leader) => leader,
+        };
+        let result = self.run_request(timings, transcript, stay).await;
+        if let Ok((ref response, _)) = result {
+            leader.complete(response.clone());
+        }
+        result
+    }
+
+    /// Performs the steps of the request, storing their durations in the given timings,
+    /// and the exchanged messages in the given transcript.
+    ///
+    /// The connection is returned as a [`Presence`] when `stay` is set, and closed otherwise.
+    async fn run_request(
+        &self,
+        timings: &mut Timings,
+        transcript: Option<&connection::Transcript>,
+        stay: bool,
+    ) -> Result<(Response, Option<Presence>)> {
+        let mut permits = self.acquire().await;
+
+        let mut connection = self.join(timings, transcript).await?;
+
+        let dialect = match self.dialect {
+            Dialect::Auto => self
+                .inner
+                .dialects
+                .get(&self.info.server, &self.info.botname),
+            ref dialect => Some(dialect.clone()),
+        };
+        let sent_at = Instant::now();
+        let response = match dialect {
+            Some(dialect) => {
+                let result = self.discover_offer(&mut connection, &dialect).await;
+                match (result, self.inner.late_offer_window) {
+                    (Err(Error::Offer(OfferError::Timeout)), Some(window)) => {
+                        debug!(
+                            "request {}: waiting {window:?} more for a late offer",
+                            self.info.id
+                        );
+                        // the other requests to the server don't have to wait for a slow bot
+                        permits.1 = None;
+                        let wait = self.wait_for_offer(&mut connection, &dialect);
+                        tokio::time::timeout(window, wait)
+                            .await
+                            .map_err(|_| OfferError::Timeout)??
+                    }
+                    (result, _) => result?,
+                }
+            }
+            None => self.probe_dialect(&mut connection).await?,
+        };
+        timings.offer_wait = sent_at.elapsed();
+        telemetry::record_offer_wait(timings.offer_wait);
+
+        let presence = if stay {
+            Some(self.presence(connection, permits))
+        } else {
+            // the offer is already received, failing to leave properly doesn't matter
+            let _ = connection.quit().await;
+            None
+        };
+
+        // the passive offers have no endpoint to probe
+        if let Some(timeout) = self.inner.offer_probe
+            && !response.is_passive()
+        {
+            if !self.inner.address_family.allows(response.address) {
+                return Err(OfferError::Unreachable {
+                    address: response.socket_addr(),
+                    source: std::io::ErrorKind::AddrNotAvailable.into(),
+                }
+                .into());
+            }
+            response.probe(timeout).await?;
+        }
+
+        Ok((response, presence))
+    }
+}
+
+impl Presence {
+    /// End of the lifetime of the request, set by the
+    /// [`EngineBuilder::max_lifetime`], unlimited when `None`.
+    ///
+    /// The transfers returned by [`Presence::transfer`], [`Presence::listen`] and
+    /// [`Presence::resume`] are cancelled at this instant.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Leaves the channels joined by the request, staying connected to the server,
+    /// to be less visible once the data connection is open.
+    ///
+    /// The server pings are still answered while [`Presence::stay`] runs, so the bots
+    /// cancelling the transfers of the users leaving the network keep sending the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be sent.
+    pub async fn part(&mut self) -> Result<()> {
+        if let Some(channels) = self.channels.take() {
+            self.connection.send(Command::PART(channels, None)).await?;
+        }
+        Ok(())
+    }
+
+    /// Stays connected while the given transfer runs, and for the given duration after
+    /// it, then leaves the server.
+    ///
+    /// Returns the output of the transfer once left, or as soon as the transfer ends
+    /// when the server closes the connection. A duration of [`Duration::MAX`] stays
+    /// until the server closes the connection.
+    ///
+    /// The transfer doesn't depend on the IRC connection: if it drops, the transfer keeps
+    /// running until it ends, without reconnecting to the server.
+    pub async fn stay<T>(mut self, transfer: impl Future<Output = T>, linger: Duration) -> T {
+        let output = self.stay_during(transfer).await;
+        if tokio::time::timeout(linger, self.keep_alive())
+            .await
+            .is_err()
+        {
+            // leaving once the transfer is complete, failing to leave properly doesn't matter
+            let _ = self.connection.quit().await;
+        }
+        output
+    }
+
+    /// Stays connected while the given transfer runs, then returns its output, keeping
+    /// the presence for the next offers of its batch.
+    ///
+    /// The offers of the batch received meanwhile are kept for [`Presence::next_offer`].
+    /// Like with [`Presence::stay`], the transfer keeps running if the connection drops.
+    pub async fn stay_during<T>(&mut self, transfer: impl Future<Output = T>) -> T {
+        use futures_util::future::Either;
+
+        let transfer = std::pin::pin!(transfer);
+        match futures_util::future::select(transfer, std::pin::pin!(self.keep_alive())).await {
+            Either::Left((output, _)) => output,
+            Either::Right(((), transfer)) => transfer.await,
+        }
+    }
+
+    /// Waits for the next offer of the batch of the presence, within the timeout of the
+    /// offers and the lifetime of the batch.
+    ///
+    /// Returns `None` once an offer per pack has been returned, or for the presence of
+    /// a single request. The offers received while staying are returned first.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Error::Request`] wrapping the protocol or offer error, ending the
+    /// batch.
+    pub async fn next_offer(&mut self) -> Option<Result<Response>> {
+        let batch = self.batch.as_mut().filter(|batch| batch.remaining > 0)?;
+        let request = &batch.request;
+        let result = match batch.received.pop_front() {
+            Some(response) => Ok(response),
+            None => {
+                let botnames = request.botnames();
+                let on_queued = |status| request.emit(EventKind::Queued(status));
+                let notices = &request.inner.notice_patterns;
+                let wait = wait_for_dcc_response(
+                    &mut self.connection,
+                    &botnames,
+                    &Dialect::Xdcc,
+                    notices,
+                    on_queued,
+                );
+                let phase = latency::Phase::Offer;
+                let timeout = request.inner.latencies.timeout(&request.info.server, phase);
+                let wait = async {
+                    tokio::time::timeout(timeout, wait)
+                        .await
+                        .unwrap_or_else(|_| Err(phase.timeout_error()))
+                };
+                match self.deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline.into(), wait)
+                        .await
+                        .unwrap_or(Err(Error::DeadlineExceeded)),
+                    None => wait.await,
+                }
+            }
+        };
+        match result {
+            Ok(response) => {
+                request.emit(EventKind::OfferReceived(response.clone()));
+                batch.remaining -= 1;
+                Some(Ok(response))
+            }
+            Err(err) => {
+                let err = request.batch_failed(err);
+                batch.remaining = 0;
+                Some(Err(err))
+            }
+        }
+    }
+
+    /// Keeps the offer of the batch carried by the given message, if any, to be returned
+    /// by [`Presence::next_offer`].
+    fn keep_offer(&mut self, message: Message) {
+        if let Some(ref mut batch) = self.batch
+            && let Command::PRIVMSG(_, ref text) = message.command
+            && let Some(mut response) = Response::decode(text)
+            && Dialect::Xdcc.accepts(&response)
+        {
+            response.sender = message.prefix.as_ref().map(ToString::to_string);
+            batch.received.push_back(response);
+        }
+    }
+
+    /// Declines the given offer with a `DCC REJECT` message, so the bot frees the slot
+    /// right away instead of waiting for a connection that never comes.
+    ///
+    /// The message is sent to the sender of the offer when known, and to the bot of
+    /// the request otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn reject(&mut self, response: &Response) -> Result<()> {
+        let target = self.sender_nickname(response);
+        let text = format!("\x01{}\x01", response.reject());
+        self.connection
+            .send(Command::NOTICE(target.to_owned(), text))
+            .await
+    }
+
+    /// Leaves the server right away, sending the `QUIT` message and waiting for it to
+    /// be flushed.
+    ///
+    /// Dropping the presence closes the connection as well, only trying to send the
+    /// `QUIT` message without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn close(self) -> Result<()> {
+        self.connection.quit().await
+    }
+
+    /// Creates the transfer of the given offer, to run within [`Presence::stay`].
+    ///
+    /// Unlike [`Transfer::new`](crate::Transfer::new), the transfer uses the socket
+    /// options of the engine, reports its events, and is cancelled at the
+    /// [`Presence::deadline`].
+    #[cfg(feature = "transfer")]
+    pub fn transfer(&self, response: &Response) -> crate::Transfer {
+        self.configure(crate::Transfer::new(response))
+    }
+
+    /// Accepts the given passive offer, made by a bot unable to accept connections.
+    ///
+    /// Listens on the given address, on the first free port of the given range, then
+    /// answers the offer with the given address, reachable by the bot, and the port
+    /// listened on, so the bot connects to the returned transfer, to run within
+    /// [`Presence::stay`]. The range `0..=0` listens on any free port, while a range
+    /// forwarded by the router allows the bots to reach a client behind a NAT, with
+    /// the public address of the router advertised.
+    ///
+    /// Only the connections from the address of the offer, or from the host of its
+    /// sender, are accepted by the transfer, the others are closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no port of the range can be listened on, or if the answer
+    /// cannot be sent.
+    #[cfg(feature = "transfer")]
+    pub async fn listen(
+        &mut self,
+        response: &Response,
+        bind: std::net::IpAddr,
+        ports: std::ops::RangeInclusive<u16>,
+        advertised: std::net::IpAddr,
+    ) -> Result<crate::Transfer> {
+        let mut last_error = std::io::ErrorKind::AddrNotAvailable.into();
+        let mut bound = None;
+        for port in ports {
+            match tokio::net::TcpListener::bind((bind, port)).await {
+                Ok(listener) => {
+                    bound = Some(listener);
+                    break;
+                }
+                // trying the next port of the range
+                Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => last_error = err,
+                Err(err) => return Err(crate::TransferError::Listen(err).into()),
+            }
+        }
+        let listener = bound.ok_or(crate::TransferError::Listen(last_error))?;
+        let port = listener
+            .local_addr()
+            .map_err(crate::TransferError::Listen)?
+            .port();
+        let answer = Response {
+            address: advertised,
+            port,
+            ..response.clone()
+        };
+        let target = self.sender_nickname(response).to_owned();
+        let text = format!("\x01{}\x01", answer.encode());
+        self.connection.send(Command::PRIVMSG(target, text)).await?;
+        Ok(self.configure(crate::Transfer::accept(response, listener)))
+    }
+
+    /// Asks the bot to resume the transfer of the given offer at the given position,
+    /// like the size of the partial file of a previous transfer.
+    ///
+    /// Returns the transfer starting at the position accepted by the bot, within the
+    /// timeout of the offers, to run within [`Presence::stay`] with the partial file
+    /// opened in append mode. A passive offer is then accepted with [`Presence::listen`]
+    /// instead, at the same position with [`Transfer::offset`](crate::Transfer::offset).
+    ///
+    /// The resumes accepted by the bot are counted in the
+    /// [`TransferStats`](crate::TransferStats) of the transfer. Resuming before the end
+    /// of the partial file, the data sent again is compared with it using
+    /// [`Transfer::verify`](crate::Transfer::verify).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransferError::ResumeTimeout`](crate::TransferError::ResumeTimeout) if
+    /// the bot doesn't accept in time, or an error if the messages cannot be exchanged.
+    #[cfg(feature = "transfer")]
+    pub async fn resume(&mut self, response: &Response, position: u64) -> Result<crate::Transfer> {
+        let target = self.sender_nickname(response).to_owned();
+        let text = format!("\x01{}\x01", response.resume(position));
+        self.connection.send(Command::PRIVMSG(target, text)).await?;
+        let accepted = async {
+            let accepted = wait_for_resume_accept(&mut self.connection, response.port);
+            tokio::time::timeout(self.resume_timeout, accepted)
+                .await
+                .map_err(|_| crate::TransferError::ResumeTimeout)?
+        };
+        let position = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), accepted)
+                .await
+                .unwrap_or(Err(Error::DeadlineExceeded))?,
+            None => accepted.await?,
+        };
+        self.resumes += 1;
+        let mut transfer = crate::Transfer::new(response).offset(position);
+        transfer.resumes = self.resumes;
+        Ok(self.configure(transfer))
+    }
+
+    /// Applies the socket options of the engine to the connection of the given transfer,
+    /// reports its events and its outcome in the audit log, and cancels it at the end of
+    /// the lifetime of the request, if any.
+    #[cfg(feature = "transfer")]
+    fn configure(&self, transfer: crate::Transfer) -> crate::Transfer {
+        let mut transfer = transfer.socket_options(self.socket_options.clone());
+        transfer.events = Some(self.events.clone());
+        #[cfg(feature = "audit")]
+        {
+            transfer.audit_log = self.audit_log.clone();
+        }
+        match self.deadline {
+            Some(deadline) => transfer.deadline(deadline),
+            None => transfer,
+        }
+    }
+
+    /// Nickname of the sender of the given offer when known, or of the bot of the
+    /// request otherwise.
+    fn sender_nickname<'a>(&'a self, response: &'a Response) -> &'a str {
+        response.sender.as_deref().map_or(&*self.botname, |sender| {
+            sender
+                .split_once('!')
+                .map_or(sender, |(nickname, _)| nickname)
+        })
+    }
+
+    /// Reads the messages of the server, answering its pings and pinging the client
+    /// itself at the anti-idle interval, until the connection closes or fails.
+    async fn keep_alive(&mut self) {
+        use futures_util::StreamExt;
+
+        let Some(interval) = self.anti_idle else {
+            while let Some(Ok(message)) = self.connection.next().await {
+                self.keep_offer(message);
+            }
+            return;
+        };
+        let mut deadline = tokio::time::Instant::now() + interval;
+        loop {
+            match tokio::time::timeout_at(deadline, self.connection.next()).await {
+                Ok(Some(Ok(message))) => {
+                    self.keep_offer(message);
+                    continue;
+                }
+                Ok(_) => return,
+                Err(_) => deadline += interval,
+            }
+            let Some(nickname) = self.connection.nickname().map(str::to_owned) else {
+                continue;
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let ping = Command::PRIVMSG(nickname, format!("\x01PING {timestamp}\x01"));
+            if self.connection.send(ping).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Batch {
+    /// Information identifying the batch, with the first pack of the range.
+    pub fn info(&self) -> &RequestInfo {
+        &self.request.info
+    }
+
+    /// Packs requested by the batch.
+    pub fn packnums(&self) -> RangeInclusive<u64> {
+        self.packnums.clone()
+    }
+
+    /// Number of packs requested by the batch.
+    fn len(&self) -> u64 {
+        self.packnums
+            .end()
+            .checked_sub(*self.packnums.start())
+            .map_or(0, |count| count.saturating_add(1))
+    }
+
+    /// Sends the batch command, and returns the offers of the bot in the order received.
+    ///
+    /// Each offer is awaited within the timeout of the engine, and the maximum lifetime
+    /// of the requests bounds the whole batch. The stream ends after an offer per pack,
+    /// or the first error.
+    pub fn execute(&self) -> impl Stream<Item = Result<Response>> + Send + '_ {
+        let state = if self.len() == 0 {
+            BatchState::Done
+        } else {
+            BatchState::Pending
+        };
+        futures_util::stream::unfold(state, move |state| async move {
+            let result = match state {
+                BatchState::Pending => self
+                    .execute_and_stay()
+                    .await
+                    .map(|(response, presence)| (response, Box::new(presence))),
+                BatchState::Running(mut presence) => match presence.next_offer().await {
+                    Some(result) => result.map(|response| (response, presence)),
+                    None => {
+                        // all the offers are received, failing to leave properly doesn't matter
+                        let _ = presence.close().await;
+                        return None;
+                    }
+                },
+                BatchState::Done => return None,
+            };
+            Some(match result {
+                Ok((response, presence)) => (Ok(response), BatchState::Running(presence)),
+                Err(err) => (Err(err), BatchState::Done),
+            })
+        })
+    }
+
+    /// Sends the batch command, and returns the first offer of the bot with the presence
+    /// of the batch on the server, returning the next offers with
+    /// [`Presence::next_offer`].
+    ///
+    /// The transfers are run with [`Presence::stay_during`], so the pings of the server
+    /// are answered meanwhile. The first offer is awaited within the timeout of the
+    /// engine, like the next ones, and the maximum lifetime of the requests bounds the
+    /// whole batch, given by [`Presence::deadline`].
+    ///
+    /// ```no_run
+    /// # async fn run() -> xdcc_request::Result<()> {
+    /// use xdcc_request::Engine;
+    ///
+    /// let engine = Engine::default();
+    /// let batch = engine.create_batch("irc.example.net", "#channel", "bot", 1..=5);
+    /// let (mut response, mut presence) = batch.execute_and_stay().await?;
+    /// loop {
+    ///     let transfer = async {
+    ///         // receive the file from response.socket_addr()
+    ///     };
+    ///     presence.stay_during(transfer).await;
+    ///     match presence.next_offer().await {
+    ///         Some(offer) => response = offer?,
+    ///         None => break,
+    ///     }
+    /// }
+    /// presence.close().await
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Error::Request`] wrapping the connection, protocol or offer error.
+    pub async fn execute_and_stay(&self) -> Result<(Response, Presence)> {
+        self.request.emit(EventKind::Started);
+        let deadline = self
+            .request
+            .inner
+            .max_lifetime
+            .map(|lifetime| Instant::now() + lifetime);
+        let first = async {
+            let (mut connection, permits) = self.start().await?;
+            let botnames = self.request.botnames();
+            let on_queued = |status| self.request.emit(EventKind::Queued(status));
+            let notices = &self.request.inner.notice_patterns;
+            let wait = wait_for_dcc_response(
+                &mut connection,
+                &botnames,
+                &Dialect::Xdcc,
+                notices,
+                on_queued,
+            );
+            let server = &self.request.info.server;
+            let phase = latency::Phase::Offer;
+            let response = self.request.inner.run_phase(server, phase, wait).await?;
+            Ok((response, connection, permits))
+        };
+        let (response, connection, permits) = self
+            .request
+            .inner
+            .within_lifetime(first)
+            .await
+            .map_err(|err| self.request.batch_failed(err))?;
+        self.request
+            .emit(EventKind::OfferReceived(response.clone()));
+        let presence = Presence {
+            batch: Some(BatchOffers {
+                received: VecDeque::new(),
+                remaining: self.len().saturating_sub(1),
+                request: Request {
+                    dialect: Dialect::Xdcc,
+                    inner: self.request.inner.clone(),
+                    info: self.request.info.clone(),
+                },
+            }),
+            deadline,
+            ..self.request.presence(connection, permits)
+        };
+        Ok((response, presence))
+    }
+
+    /// Connects, joins the channels and sends the batch command.
+    async fn start(&self) -> Result<(connection::Connection, Permits)> {
+        let permits = self.request.acquire().await;
+        let mut connection = self.request.join(&mut Timings::default(), None).await?;
+        connection
+            .send(Command::PRIVMSG(
+                self.request.info.botname.to_string(),
+                format!(
+                    "xdcc batch #{}-#{}",
+                    self.packnums.start(),
+                    self.packnums.end()
+                ),
+            ))
+            .await?;
+        self.request.emit(EventKind::RequestSent);
+        Ok((connection, permits))
+    }
+}
+
+impl Response {
+    /// Checks that the offered endpoint accepts TCP connections within the given timeout.
+    ///
+    /// The connection is closed right after being established.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`OfferError::Unreachable`] if the connection cannot be established in time.
+    pub async fn probe(&self, timeout: Duration) -> Result<()> {
+        let address = self.socket_addr();
+        match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(address)).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(source)) => Err(OfferError::Unreachable { address, source }.into()),
+            Err(_) => Err(OfferError::Unreachable {
+                address,
+                source: std::io::ErrorKind::TimedOut.into(),
+            }
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures_util::stream;
+    use irc::proto::{Command, Message, Response as IrcResponse};
+
+    use crate::test_util::FakeIrcBot;
+
+    /// Offer of the first pack by the fake bots.
+    const OFFER: &str = "DCC SEND ubuntu.iso 2130706433 5000 1024";
+
+    /// Private message of the fake bots to the client.
+    fn reply(text: &str) -> String {
+        format!(":bot!bot@example.net PRIVMSG nickname :{text}")
+    }
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    #[test]
+    fn should_execute_from_any_thread() {
+        let engine = super::Engine::default();
+        assert_send_sync(&engine);
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        assert_send_sync(&request.execute());
+        assert_send_sync(&request.execute_and_stay());
+        let batch = engine.create_batch("irc.example.net", "#channel", "bot", 1..=2);
+        assert_send_sync(&batch.execute_and_stay());
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_dcc_message() {
+        let mut stream = stream::iter(vec![Ok(Message {
+            tags: None,
+            prefix: Some("botname!bot@example.net".into()),
+            command: Command::PRIVMSG(
+                "nickname".into(),
+                "DCC SEND \"ubuntu.iso\" 3232235777 5000 1048576".into(),
+            ),
+        })]);
+        let res = super::wait_for_dcc_response(
+            &mut stream,
+            &["botname"],
+            &Default::default(),
+            &Default::default(),
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.filename, "ubuntu.iso");
+        assert_eq!(res.sender.as_deref(), Some("botname!bot@example.net"));
+    }
+
+    #[tokio::test]
+    async fn should_report_queue_position() {
+        let mut stream = stream::iter(vec![
+            Ok(Message {
+                tags: None,
+                prefix: Some("botname!bot@example.net".into()),
+                command: Command::NOTICE(
+                    "nickname".into(),
+                    "Queued 0h1m for \"ubuntu.iso\", in position 2 of 3. 0h5m or more remaining."
+                        .into(),
+                ),
+            }),
+            Ok(Message {
+                tags: None,
+                prefix: Some("other!user@example.net".into()),
+                command: Command::PRIVMSG("nickname".into(), "in position 1".into()),
+            }),
+            Ok(Message {
+                tags: None,
+                prefix: Some("botname!bot@example.net".into()),
+                command: Command::PRIVMSG(
+                    "nickname".into(),
+                    "DCC SEND \"ubuntu.iso\" 3232235777 5000 1048576".into(),
+                ),
+            }),
+        ]);
+        let mut positions = Vec::new();
+        let res = super::wait_for_dcc_response(
+            &mut stream,
+            &["botname"],
+            &Default::default(),
+            &Default::default(),
+            |status| positions.push(status.position),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.filename, "ubuntu.iso");
+        assert_eq!(positions, [2]);
+    }
+
+    #[tokio::test]
+    async fn should_report_throttle() {
+        let mut stream = stream::iter(vec![Ok(Message {
+            tags: None,
+            prefix: Some("botname!bot@example.net".into()),
+            command: Command::NOTICE(
+                "nickname".into(),
+                "** Please wait 30 seconds before requesting again".into(),
+            ),
+        })]);
+        let err = super::wait_for_dcc_response(
+            &mut stream,
+            &["botname"],
+            &Default::default(),
+            &Default::default(),
+            |_| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            super::Error::Offer(super::OfferError::Throttled(delay)) if delay == Duration::from_secs(30)
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_registration() {
+        let mut stream = stream::iter(vec![
+            Ok(Message {
+                tags: None,
+                prefix: None,
+                command: Command::PING(Default::default(), Default::default()),
+            }),
+            Ok(Message {
+                tags: None,
+                prefix: None,
+                command: Command::Response(
+                    irc::proto::Response::RPL_ENDOFMOTD,
+                    vec!["End of /MOTD command.".into()],
+                ),
+            }),
+        ]);
+        super::wait_for_registration(&mut stream).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_fail_registration_if_nickname_in_use() {
+        let mut stream = stream::iter(vec![Ok(Message {
+            tags: None,
+            prefix: None,
+            command: Command::Response(
+                irc::proto::Response::ERR_NICKNAMEINUSE,
+                vec![
+                    "*".into(),
+                    "nickname".into(),
+                    "Nickname is already in use".into(),
+                ],
+            ),
+        })]);
+        let err = super::wait_for_registration(&mut stream).await.unwrap_err();
+        assert!(matches!(
+            err,
+            super::Error::Connect(super::ConnectError::NicknameInUse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_private_message() {
+        let mut stream = stream::iter(vec![
+            Ok(Message {
+                tags: None,
+                prefix: None,
+                command: Command::PING(Default::default(), Default::default()),
+            }),
+            Ok(Message {
+                tags: None,
+                prefix: None,
+                command: Command::PRIVMSG("botname".into(), "hello world".into()),
+            }),
+        ]);
+        super::wait_for_first_private_message(&mut stream)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_detect_channel_rules() {
+        let mut stream = stream::iter(vec![
+            Ok(Message {
+                tags: None,
+                prefix: None,
+                command: Command::Response(
+                    IrcResponse::RPL_TOPIC,
+                    vec![
+                        "nickname".into(),
+                        "#channel".into(),
+                        "No requests for 60s after join | max 2 downloads".into(),
+                    ],
+                ),
+            }),
+            Ok(Message {
+                tags: None,
+                prefix: None,
+                command: Command::NOTICE(
+                    "nickname".into(),
+                    "Please wait 2 minutes before requesting".into(),
+                ),
+            }),
+            Ok(Message {
+                tags: None,
+                prefix: None,
+                command: Command::PRIVMSG("#channel".into(), "hello world".into()),
+            }),
+        ]);
+        let rules = super::wait_for_first_private_message(&mut stream)
+            .await
+            .unwrap();
+        assert_eq!(rules.request_delay, Some(Duration::from_secs(120)));
+        assert_eq!(rules.max_downloads, Some(2));
+    }
+
+    #[tokio::test]
+    async fn should_fail_if_no_private_message() {
+        let mut stream = stream::iter(vec![Ok(Message {
+            tags: None,
+            prefix: None,
+            command: Command::PING(Default::default(), Default::default()),
+        })]);
+        super::wait_for_first_private_message(&mut stream)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn should_connect_with_custom_connector() {
+        let engine = super::Engine::builder()
+            .connector(FakeIrcBot::new("bot"))
+            .build();
+        let probe = engine.probe("irc.example.net").await.unwrap();
+        assert_eq!(probe.address, None);
+        assert_eq!(probe.tls, None);
+    }
+
+    #[tokio::test]
+    async fn should_connect_to_network_server() {
+        #[derive(Debug)]
+        struct ExpectHost(FakeIrcBot);
+
+        impl super::Connector for ExpectHost {
+            fn connect<'a>(&'a self, host: &'a str, port: u16) -> crate::ConnectFuture<'a> {
+                if host != "irc.example.net" {
+                    return Box::pin(async { Err(std::io::ErrorKind::NotFound.into()) });
+                }
+                self.0.connect(host, port)
+            }
+        }
+
+        let engine = super::Engine::builder()
+            .connector(ExpectHost(FakeIrcBot::new("bot")))
+            .network("example", crate::Network::new("irc.example.net"))
+            .build();
+        engine.probe("example").await.unwrap();
+        engine.probe("irc.other.net").await.unwrap_err();
+
+        let engine = super::Engine::builder()
+            .connector(ExpectHost(FakeIrcBot::new("bot")))
+            .network(
+                "example",
+                crate::Network::new("irc.down.net").with_server("irc.example.net"),
+            )
+            .build();
+        engine.probe("example").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_connect_to_service_record() {
+        #[derive(Debug)]
+        struct SrvResolver;
+
+        impl crate::Resolver for SrvResolver {
+            fn resolve<'a>(&'a self, _host: &'a str) -> crate::ResolveFuture<'a> {
+                Box::pin(async { Ok(Vec::new()) })
+            }
+
+            fn resolve_srv<'a>(&'a self, name: &'a str) -> crate::SrvFuture<'a> {
+                let port = match name {
+                    "_irc._tcp.example.net" => 6668,
+                    "_ircs._tcp.example.net" => 6698,
+                    _ => panic!("unexpected service {name}"),
+                };
+                Box::pin(async move {
+                    Ok(vec![crate::SrvRecord {
+                        priority: 10,
+                        weight: 0,
+                        port,
+                        target: "irc.example.net".into(),
+                    }])
+                })
+            }
+        }
+
+        let engine = super::Engine::builder()
+            .resolver(SrvResolver)
+            .connector(ExpectPort(6668, FakeIrcBot::new("bot")))
+            .network("example", crate::Network::srv("example.net"))
+            .build();
+        engine.probe("example").await.unwrap();
+
+        // the TLS ports are only used with a TLS connector
+        let engine = super::Engine::builder()
+            .resolver(SrvResolver)
+            .network(
+                "example",
+                crate::Network::srv("example.net").tls(ExpectPort(6698, FakeIrcBot::new("bot"))),
+            )
+            .build();
+        engine.probe("example").await.unwrap();
+    }
+
+    /// Connector accepting only the given port of `irc.example.net`.
+    #[derive(Debug)]
+    struct ExpectPort(u16, FakeIrcBot);
+
+    impl super::Connector for ExpectPort {
+        fn connect<'a>(&'a self, host: &'a str, port: u16) -> crate::ConnectFuture<'a> {
+            if (host, port) != ("irc.example.net", self.0) {
+                return Box::pin(async { Err(std::io::ErrorKind::NotFound.into()) });
+            }
+            self.1.connect(host, port)
+        }
+    }
+
+    #[tokio::test]
+    async fn should_connect_to_network_over_tls() {
+        let engine = super::Engine::builder()
+            .connector(FakeIrcBot::new("bot").with_handler(|line| {
+                assert!(!line.starts_with("USER"), "connected without TLS");
+                Vec::new()
+            }))
+            .network(
+                "example",
+                crate::Network::new("irc.example.net")
+                    .tls(ExpectPort(6697, FakeIrcBot::new("bot"))),
+            )
+            .build();
+        let probe = engine.probe("example").await.unwrap();
+        assert_eq!(probe.tls, Some(true));
+    }
+
+    #[cfg(feature = "starttls")]
+    #[tokio::test]
+    async fn should_report_starttls_upgrade() {
+        /// Upgrader keeping the plaintext stream.
+        #[derive(Debug)]
+        struct NoopUpgrader;
+
+        impl crate::TlsUpgrader for NoopUpgrader {
+            fn upgrade<'a>(
+                &'a self,
+                _host: &'a str,
+                stream: Box<dyn crate::Transport>,
+            ) -> crate::ConnectFuture<'a> {
+                Box::pin(async { Ok(stream) })
+            }
+        }
+
+        for (reply, upgraded) in [
+            ("670 * :STARTTLS successful", true),
+            ("691 * :failed", false),
+        ] {
+            let engine = super::Engine::builder()
+                .connector(FakeIrcBot::new("bot").with_handler(move |line| {
+                    if line != "STARTTLS" {
+                        return Vec::new();
+                    }
+                    // the end of the MOTD sent on connection is read by the negotiation
+                    vec![
+                        format!(":irc.example.net {reply}"),
+                        ":irc.example.net 376 * :End of /MOTD command.".into(),
+                    ]
+                }))
+                .starttls(NoopUpgrader)
+                .build();
+            let probe = engine.probe("irc.example.net").await.unwrap();
+            assert_eq!(probe.tls, Some(upgraded));
+        }
+    }
+
+    /// Bot of a network accepting the given base64 `PLAIN` SASL payload.
+    fn sasl_bot(payload: &'static str) -> FakeIrcBot {
+        FakeIrcBot::new("bot").with_handler(move |line| {
+            let reply = match line {
+                "CAP REQ sasl" | "CAP REQ :sasl" => ":irc.example.net CAP * ACK :sasl",
+                "AUTHENTICATE PLAIN" => "AUTHENTICATE +",
+                "CAP END" => ":irc.example.net 376 * :End of /MOTD command.",
+                _ => match line.strip_prefix("AUTHENTICATE ") {
+                    Some(data) if data == payload => {
+                        ":irc.example.net 903 * :SASL authentication successful"
+                    }
+                    Some(_) => ":irc.example.net 904 * :SASL authentication failed",
+                    None => return Vec::new(),
+                },
+            };
+            vec![reply.to_owned()]
+        })
+    }
+
+    #[tokio::test]
+    async fn should_authenticate_with_sasl() {
+        let network = crate::Network::new("irc.example.net").sasl("jilles", "sesame");
+        let engine = super::Engine::builder()
+            .connector(sasl_bot("amlsbGVzAGppbGxlcwBzZXNhbWU="))
+            .network("example", network.clone())
+            .build();
+        engine.probe("example").await.unwrap();
+
+        let engine = super::Engine::builder()
+            .connector(sasl_bot("bm9ib2R5AG5vYm9keQBub3RoaW5n"))
+            .network("example", network)
+            .build();
+        let err = engine.probe("example").await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                crate::Error::Connect(crate::ConnectError::Authentication)
+            ),
+            "{err:?}"
+        );
+        assert!(!err.is_retryable());
+    }
+
+    /// Bot answering only the CTCP requests and the batches.
+    fn ctcp_bot() -> FakeIrcBot {
+        FakeIrcBot::new("bot").with_handler(|line| {
+            let offers: &[&str] = if line.contains("\x01XDCC SEND #1\x01") {
+                &["DCC SEND ubuntu.iso 2130706433 5000 1024"]
+            } else if line.contains("xdcc batch #1-#2") {
+                &[
+                    "DCC SEND ubuntu.iso 2130706433 5000 1024",
+                    "DCC SEND debian.iso 2130706433 5001 2048",
+                ]
+            } else {
+                &[]
+            };
+            offers.iter().map(|offer| reply(offer)).collect()
+        })
+    }
+
+    /// Bot offering the first pack a while after the command.
+    fn slow_bot(delay: Duration) -> FakeIrcBot {
+        FakeIrcBot::new("bot")
+            .with_pack(1, OFFER)
+            .offer_delay(delay)
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_late_offer() {
+        let engine = super::Engine::builder()
+            .connector(slow_bot(Duration::from_millis(300)))
+            .timeout(Duration::from_millis(100))
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let err = request.execute().await.unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::OfferTimeout);
+
+        let engine = super::Engine::builder()
+            .connector(slow_bot(Duration::from_millis(400)))
+            .timeout(Duration::from_millis(100))
+            .late_offer_window(Duration::from_secs(5))
+            .max_requests_per_server(1)
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let execution = request.execute();
+        tokio::pin!(execution);
+        tokio::select! {
+            _ = &mut execution => panic!("the offer should be late"),
+            _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+        }
+        // the slot on the server is released during the window
+        let other = engine.create_request("irc.example.net", "#channel", "other", 1);
+        tokio::select! {
+            _ = &mut execution => panic!("the offer should be late"),
+            _ = other.acquire() => {}
+        }
+        assert_eq!(execution.await.unwrap().filename, "ubuntu.iso");
+    }
+
+    /// Bot only answering the users in the given channel, and in `#files` according to
+    /// the server, which knows no `ghost`.
+    fn hiding_bot(channel: &'static str) -> FakeIrcBot {
+        let present = AtomicBool::new(false);
+        FakeIrcBot::new("bot").with_handler(move |line| {
+            if line.starts_with("JOIN") && line.split([' ', ',']).any(|c| c == channel) {
+                present.store(true, Ordering::Relaxed);
+            }
+            match line {
+                "WHOIS ghost" => vec![
+                    ":irc.example.net 401 nickname ghost :No such nick/channel".into(),
+                    ":irc.example.net 318 nickname ghost :End of /WHOIS list.".into(),
+                ],
+                "WHOIS bot" => vec![
+                    ":irc.example.net 319 nickname bot :@#files".into(),
+                    ":irc.example.net 318 nickname bot :End of /WHOIS list.".into(),
+                ],
+                _ if line.contains("xdcc send #1") && present.load(Ordering::Relaxed) => {
+                    vec![reply(OFFER)]
+                }
+                _ => Vec::new(),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn should_join_channels_of_bot() {
+        let engine = super::Engine::builder()
+            .connector(hiding_bot("#files"))
+            .timeout(Duration::from_millis(100))
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let err = request.execute().await.unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            crate::Error::Offer(crate::OfferError::Timeout)
+        ));
+
+        let engine = super::Engine::builder()
+            .connector(hiding_bot("#files"))
+            .timeout(Duration::from_millis(100))
+            .channel_discovery(true)
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        assert_eq!(request.execute().await.unwrap().filename, "ubuntu.iso");
+
+        let engine = super::Engine::builder()
+            .connector(hiding_bot("#elsewhere"))
+            .timeout(Duration::from_millis(100))
+            .channel_discovery(true)
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let err = request.execute().await.unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            crate::Error::Offer(crate::OfferError::TimeoutAfterDiscovery { channels }) if channels == &["#files"]
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_check_bot_presence() {
+        let engine = super::Engine::builder()
+            .connector(hiding_bot("#channel"))
+            .check_bot_presence(true)
+            .bot("ghost", crate::Bot::default().with_alias("bot"))
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "ghost", 1);
+        assert_eq!(request.execute().await.unwrap().filename, "ubuntu.iso");
+
+        let engine = super::Engine::builder()
+            .connector(hiding_bot("#channel"))
+            .check_bot_presence(true)
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "ghost", 1);
+        let err = request.execute().await.unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::BotOffline);
+    }
+
+    #[tokio::test]
+    async fn should_apply_settings_at_runtime() {
+        let engine = super::Engine::builder()
+            .connector(slow_bot(Duration::from_millis(300)))
+            .max_requests_per_server(1)
+            .build();
+        engine.set_max_requests_per_server(Some(2));
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let permit = request.acquire().await;
+        // the second slot is available
+        let second = tokio::time::timeout(Duration::from_millis(50), request.acquire())
+            .await
+            .unwrap();
+        drop(second);
+        engine.set_server_max_requests("irc.example.net", Some(1));
+        tokio::time::timeout(Duration::from_millis(50), request.acquire())
+            .await
+            .unwrap_err();
+        engine.set_server_max_requests("irc.example.net", None);
+        drop(permit);
+        engine.set_bot_max_requests("Bot", Some(1));
+        let permit = request.acquire().await;
+        tokio::time::timeout(Duration::from_millis(50), request.acquire())
+            .await
+            .unwrap_err();
+        engine.set_bot_max_requests("bot", None);
+        drop(permit);
+        engine.set_timeout(Duration::from_millis(100));
+        let err = request.execute().await.unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::OfferTimeout);
+    }
+
+    #[tokio::test]
+    async fn should_cancel_request_at_deadline() {
+        let engine = super::Engine::builder()
+            .connector(slow_bot(Duration::from_secs(5)))
+            .max_lifetime(Duration::from_millis(100))
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let err = request.execute().await.unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn should_probe_and_remember_dialect() {
+        let engine = super::Engine::builder()
+            .connector(ctcp_bot())
+            .dialect_probe_timeout(Duration::from_millis(100))
+            .build();
+        let request = engine
+            .create_request("irc.example.net", "#channel", "bot", 1)
+            .with_dialect(crate::Dialect::Auto);
+        assert_eq!(request.execute().await.unwrap().filename, "ubuntu.iso");
+        assert_eq!(
+            engine.0.dialects.get("irc.example.net", "bot"),
+            Some(crate::Dialect::Ctcp)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_wait_before_requesting() {
+        let engine = super::Engine::builder()
+            .connector(ctcp_bot())
+            .request_delay(Duration::from_secs(60))
+            .channel_request_delay("#Channel", Duration::from_millis(200))
+            .build();
+        let request = engine
+            .create_request("irc.example.net", "#channel", "bot", 1)
+            .with_dialect(crate::Dialect::Ctcp);
+        let started_at = std::time::Instant::now();
+        request.execute().await.unwrap();
+        assert!(started_at.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn should_stay_joined_after_offer() {
+        let engine = super::Engine::builder()
+            .connector(ctcp_bot())
+            .bot("bot", crate::Bot::default().max_requests(1))
+            .build();
+        let request = engine
+            .create_request("irc.example.net", "#channel", "bot", 1)
+            .with_dialect(crate::Dialect::Ctcp);
+        let (response, mut presence) = request.execute_and_stay().await.unwrap();
+        assert_eq!(response.filename, "ubuntu.iso");
+        // the slot with the bot is held while staying
+        tokio::time::timeout(Duration::from_millis(50), request.acquire())
+            .await
+            .unwrap_err();
+        presence.reject(&response).await.unwrap();
+        presence.part().await.unwrap();
+        let output = presence.stay(async { 42 }, Duration::from_millis(10)).await;
+        assert_eq!(output, 42);
+        let _permits = request.acquire().await;
+    }
+
+    /// Bot offering the first pack right away, and notifying the pings of the client.
+    fn pinged_bot(pinged: Arc<tokio::sync::Notify>) -> FakeIrcBot {
+        FakeIrcBot::new("bot")
+            .with_pack(1, OFFER)
+            .with_handler(move |line| {
+                if line.contains("\x01PING ") {
+                    pinged.notify_one();
+                }
+                Vec::new()
+            })
+    }
+
+    #[tokio::test]
+    async fn should_stay_active_while_staying() {
+        let pinged = Arc::new(tokio::sync::Notify::new());
+        let engine = super::Engine::builder()
+            .connector(pinged_bot(pinged.clone()))
+            .anti_idle(Duration::from_millis(50))
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (_, presence) = request.execute_and_stay().await.unwrap();
+        // the transfer only ends once the client pinged itself
+        let stay = presence.stay(pinged.notified(), Duration::ZERO);
+        tokio::time::timeout(Duration::from_secs(5), stay)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_keep_transfer_when_connection_drops() {
+        /// Server closing the connection right after offering the first pack.
+        #[derive(Debug)]
+        struct DroppingServer;
+
+        impl super::Connector for DroppingServer {
+            fn connect<'a>(&'a self, _host: &'a str, _port: u16) -> crate::ConnectFuture<'a> {
+                Box::pin(async {
+                    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+                    let (client, server) = tokio::io::duplex(1024);
+                    let (reader, mut writer) = tokio::io::split(server);
+                    writer
+                        .write_all(b":irc.example.net 376 nickname :End of /MOTD command.\r\n")
+                        .await?;
+                    tokio::spawn(async move {
+                        let mut lines = BufReader::new(reader).lines();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line.starts_with("JOIN") {
+                                let welcome = ":bot!bot@example.net PRIVMSG #channel :welcome\r\n";
+                                let _ = writer.write_all(welcome.as_bytes()).await;
+                            } else if line.to_ascii_lowercase().contains("xdcc send #1") {
+                                let offer = format!("{}\r\n", reply(OFFER));
+                                let _ = writer.write_all(offer.as_bytes()).await;
+                                break;
+                            }
+                        }
+                    });
+                    Ok(Box::new(client) as Box<dyn crate::Transport>)
+                })
+            }
+        }
+
+        let engine = super::Engine::with_transport(DroppingServer);
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (_, presence) = request.execute_and_stay().await.unwrap();
+        // the transfer still runs once the server closed the connection
+        let transfer = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            42
+        };
+        let stay = presence.stay(transfer, Duration::MAX);
+        let output = tokio::time::timeout(Duration::from_secs(5), stay)
+            .await
+            .unwrap();
+        assert_eq!(output, 42);
+    }
+
+    /// Bot making a passive offer of the first pack, and sending `hello` to the
+    /// address of the answer.
+    #[cfg(feature = "transfer")]
+    fn passive_bot() -> FakeIrcBot {
+        FakeIrcBot::new("bot")
+            .with_pack(1, "DCC SEND hello.txt 2130706433 0 5 42")
+            .with_handler(|line| {
+                if let Some(answer) = line.strip_prefix("PRIVMSG bot :\x01DCC SEND ")
+                    && answer.ends_with(" 5 42\x01")
+                {
+                    let port: u16 = answer.split(' ').nth(2).unwrap().parse().unwrap();
+                    tokio::spawn(async move {
+                        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+                        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+                            .await
+                            .unwrap();
+                        stream.write_all(b"hello").await.unwrap();
+                        let mut ack = [0; 4];
+                        stream.read_exact(&mut ack).await.unwrap();
+                    });
+                }
+                Vec::new()
+            })
+    }
+
+    #[cfg(feature = "transfer")]
+    #[tokio::test]
+    async fn should_accept_passive_offer() {
+        use std::net::Ipv4Addr;
+
+        let engine = super::Engine::with_transport(passive_bot());
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (response, mut presence) = request.execute_and_stay().await.unwrap();
+        assert_eq!(response.token.as_deref(), Some("42"));
+        let transfer = presence
+            .listen(
+                &response,
+                Ipv4Addr::LOCALHOST.into(),
+                0..=0,
+                Ipv4Addr::LOCALHOST.into(),
+            )
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        let download = transfer.download(&mut received);
+        let size = presence.stay(download, Duration::ZERO).await.unwrap();
+        assert_eq!(size, 5);
+        assert_eq!(received, b"hello");
+    }
+
+    #[cfg(feature = "transfer")]
+    #[tokio::test]
+    async fn should_listen_on_free_port_of_range() {
+        use std::net::Ipv4Addr;
+
+        let taken = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let engine = super::Engine::with_transport(passive_bot());
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (response, mut presence) = request.execute_and_stay().await.unwrap();
+        let result = presence
+            .listen(
+                &response,
+                Ipv4Addr::LOCALHOST.into(),
+                port..=port,
+                Ipv4Addr::LOCALHOST.into(),
+            )
+            .await;
+        assert!(
+            matches!(
+                result,
+                Err(crate::Error::Transfer(crate::TransferError::Listen(_)))
+            ),
+            "{result:?}"
+        );
+        let transfer = presence
+            .listen(
+                &response,
+                Ipv4Addr::LOCALHOST.into(),
+                port..=port.saturating_add(10),
+                Ipv4Addr::LOCALHOST.into(),
+            )
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        let download = transfer.download(&mut received);
+        presence.stay(download, Duration::ZERO).await.unwrap();
+        assert_eq!(received, b"hello");
+    }
+
+    #[cfg(all(feature = "audit", feature = "transfer"))]
+    #[tokio::test]
+    async fn should_audit_transfer_of_presence() {
+        use std::net::Ipv4Addr;
+
+        let log = Arc::new(crate::audit::AuditLog::open_in_memory().unwrap());
+        let engine = super::Engine::builder()
+            .connector(passive_bot())
+            .audit_log(log.clone())
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (response, mut presence) = request.execute_and_stay().await.unwrap();
+        let transfer = presence
+            .listen(
+                &response,
+                Ipv4Addr::LOCALHOST.into(),
+                0..=0,
+                Ipv4Addr::LOCALHOST.into(),
+            )
+            .await
+            .unwrap();
+        let download = transfer.download(Vec::new());
+        presence.stay(download, Duration::ZERO).await.unwrap();
+        let entries = log.entries().unwrap();
+        assert_eq!(entries[0].filename.as_deref(), Some("hello.txt"));
+        assert_eq!(entries[0].transferred, Some(5));
+    }
+
+    #[cfg(feature = "transfer")]
+    #[tokio::test]
+    async fn should_download_matching_packs() {
+        use futures_util::StreamExt;
+
+        let episode = crate::test_util::FakeDccSender::bind(b"episode".to_vec())
+            .await
+            .unwrap();
+        let notes = crate::test_util::FakeDccSender::bind(b"notes".to_vec())
+            .await
+            .unwrap();
+        let bot = FakeIrcBot::new("bot")
+            .with_pack(1, episode.offer("Show.E01.mkv"))
+            .with_pack(2, notes.offer("notes.txt"));
+        let engine = super::Engine::with_transport(bot);
+        let listing = [
+            crate::PackAnnouncement::parse("#1 12x [7B] Show.E01.mkv").unwrap(),
+            crate::PackAnnouncement::parse("#2 3x [5B] notes.txt").unwrap(),
+        ];
+        let directory =
+            std::env::temp_dir().join(format!("xdcc-request-matching-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let selector = crate::PackSelector::Glob("*.mkv".into());
+        let downloads = engine.download_matching(
+            "irc.example.net",
+            "#channel",
+            "bot",
+            selector,
+            &listing,
+            &directory,
+        );
+        let (_, results) = tokio::join!(episode.serve(), downloads.collect::<Vec<_>>());
+        let content = std::fs::read(directory.join("Show.E01.mkv"));
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.pack, crate::PackId::Number(1));
+        assert_eq!(results[0].1.as_ref().unwrap().position, 7);
+        assert_eq!(content.unwrap(), b"episode");
+    }
+
+    /// Bot offering the first pack on the given port, and accepting to resume it.
+    #[cfg(feature = "transfer")]
+    fn resuming_bot(port: u16) -> FakeIrcBot {
+        FakeIrcBot::new("bot")
+            .with_pack(1, format!("DCC SEND hello.txt 2130706433 {port} 5"))
+            .with_handler(move |line| {
+                if line == format!("PRIVMSG bot :\x01DCC RESUME hello.txt {port} 2\x01") {
+                    vec![reply(&format!("\x01DCC ACCEPT hello.txt {port} 2\x01"))]
+                } else {
+                    Vec::new()
+                }
+            })
+    }
+
+    #[cfg(feature = "transfer")]
+    #[tokio::test]
+    async fn should_resume_transfer() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let engine = super::Engine::with_transport(resuming_bot(port));
+        let mut events = engine.events();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (response, mut presence) = request.execute_and_stay().await.unwrap();
+        let transfer = presence.resume(&response, 2).await.unwrap();
+        let mut received = Vec::new();
+        let download = presence.stay(transfer.download_with_stats(&mut received), Duration::ZERO);
+        let (_, result) = tokio::join!(
+            async {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                stream.write_all(b"llo").await.unwrap();
+                let mut ack = [0; 4];
+                stream.read_exact(&mut ack).await.unwrap();
+                assert_eq!(u32::from_be_bytes(ack), 5);
+            },
+            download
+        );
+        let stats = result.unwrap();
+        assert_eq!((stats.position, stats.bytes, stats.resumes), (5, 3, 1));
+        assert_eq!(received, b"llo");
+
+        let transfer_events: Vec<crate::EventKind> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| event.kind)
+            .filter(|kind| {
+                matches!(
+                    kind,
+                    crate::EventKind::TransferStarted(_) | crate::EventKind::TransferCompleted(_)
+                )
+            })
+            .collect();
+        assert!(
+            matches!(
+                transfer_events[..],
+                [
+                    crate::EventKind::TransferStarted(2),
+                    crate::EventKind::TransferCompleted(crate::TransferStats { position: 5, .. })
+                ]
+            ),
+            "{transfer_events:?}"
+        );
+    }
+
+    #[cfg(feature = "transfer")]
+    #[tokio::test]
+    async fn should_cancel_transfer_at_end_of_lifetime() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let engine = super::Engine::builder()
+            .connector(resuming_bot(port))
+            .max_lifetime(Duration::from_millis(300))
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (response, mut presence) = request.execute_and_stay().await.unwrap();
+        assert!(presence.deadline().unwrap() > std::time::Instant::now());
+        let transfer = presence.resume(&response, 2).await.unwrap();
+        // the sender accepts the connection without sending anything
+        let (_, result) = tokio::join!(listener.accept(), transfer.download(Vec::new()));
+        assert!(
+            matches!(result, Err(crate::Error::DeadlineExceeded)),
+            "{result:?}"
+        );
+        assert!(presence.deadline().unwrap() <= std::time::Instant::now());
+    }
+
+    #[cfg(feature = "transfer")]
+    #[tokio::test]
+    async fn should_cancel_offered_transfer_at_end_of_lifetime() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let engine = super::Engine::builder()
+            .connector(resuming_bot(port))
+            .max_lifetime(Duration::from_millis(300))
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let (response, presence) = request.execute_and_stay().await.unwrap();
+        let transfer = presence.transfer(&response);
+        // the sender accepts the connection without sending anything
+        let (_, result) = tokio::join!(listener.accept(), transfer.download(Vec::new()));
+        assert!(
+            matches!(result, Err(crate::Error::DeadlineExceeded)),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_replace_lost_warm_connection() {
+        /// Server closing the first connection once the client identified.
+        #[derive(Debug)]
+        struct ClosingServer(FakeIrcBot, Arc<AtomicUsize>);
+
+        impl super::Connector for ClosingServer {
+            fn connect<'a>(&'a self, host: &'a str, port: u16) -> crate::ConnectFuture<'a> {
+                if self.1.fetch_add(1, Ordering::Relaxed) > 0 {
+                    return self.0.connect(host, port);
+                }
+                Box::pin(async {
+                    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+                    let (client, mut server) = tokio::io::duplex(1024);
+                    server
+                        .write_all(b":irc.example.net 376 nickname :End of /MOTD command.\r\n")
+                        .await?;
+                    tokio::spawn(async move {
+                        let mut lines = BufReader::new(server).lines();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line.starts_with("USER") {
+                                break;
+                            }
+                        }
+                    });
+                    Ok(Box::new(client) as Box<dyn crate::Transport>)
+                })
+            }
+        }
+
+        let connections = Arc::new(AtomicUsize::new(0));
+        let bot = FakeIrcBot::new("bot").with_pack(1, OFFER);
+        let engine = super::Engine::with_transport(ClosingServer(bot, connections.clone()));
+        engine.warm("irc.example.net").await.unwrap();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        assert_eq!(request.execute().await.unwrap().filename, "ubuntu.iso");
+        assert_eq!(connections.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn should_close_idle_connections() {
+        let engine = super::Engine::builder().connector(ctcp_bot()).build();
+        engine.warm("irc.example.net").await.unwrap();
+        engine.close().await;
+        assert!(engine.0.pool.take("irc.example.net").is_none());
+    }
+
+    #[tokio::test]
+    async fn should_receive_offers_of_batch() {
+        use futures_util::StreamExt;
+
+        let engine = super::Engine::builder().connector(ctcp_bot()).build();
+        let batch = engine.create_batch("irc.example.net", "#channel", "bot", 1..=2);
+        assert_eq!(batch.packnums(), 1..=2);
+        let filenames: Vec<String> = batch
+            .execute()
+            .map(|offer| offer.unwrap().filename)
+            .collect()
+            .await;
+        assert_eq!(filenames, ["ubuntu.iso", "debian.iso"]);
+    }
+
+    #[tokio::test]
+    async fn should_keep_offers_of_batch_while_staying() {
+        let engine = super::Engine::builder().connector(ctcp_bot()).build();
+        let batch = engine.create_batch("irc.example.net", "#channel", "bot", 1..=2);
+        let (response, mut presence) = batch.execute_and_stay().await.unwrap();
+        assert_eq!(response.filename, "ubuntu.iso");
+        // the second offer is received while the first file is transferred
+        presence
+            .stay_during(tokio::time::sleep(Duration::from_millis(50)))
+            .await;
+        let response = presence.next_offer().await.unwrap().unwrap();
+        assert_eq!(response.filename, "debian.iso");
+        assert!(presence.next_offer().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_time_out_next_offer_of_batch() {
+        let bot = FakeIrcBot::new("bot").with_handler(|line| {
+            if line.contains("xdcc batch #1-#2") {
+                vec![reply(OFFER)]
+            } else {
+                Vec::new()
+            }
+        });
+        let engine = super::Engine::builder()
+            .connector(bot)
+            .timeout(Duration::from_millis(100))
+            .build();
+        let batch = engine.create_batch("irc.example.net", "#channel", "bot", 1..=2);
+        let (_, mut presence) = batch.execute_and_stay().await.unwrap();
+        let err = presence.next_offer().await.unwrap().unwrap_err();
+        assert!(
+            matches!(err.inner(), crate::Error::Offer(crate::OfferError::Timeout)),
+            "{err:?}"
+        );
+        assert!(presence.next_offer().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_not_connect_for_empty_batch() {
+        use futures_util::StreamExt;
+
+        let engine = super::Engine::default();
+        #[allow(clippy::reversed_empty_ranges)]
+        let batch = engine.create_batch("irc.invalid", "#channel", "bot", 2..=1);
+        assert_eq!(batch.execute().count().await, 0);
+    }
+
+    #[test]
+    fn should_join_default_channels() {
+        let engine = super::Engine::builder()
+            .default_channel("#chat")
+            .network(
+                "example",
+                crate::Network::new("irc.example.net")
+                    .with_channel("#Channel")
+                    .with_channel("#news"),
+            )
+            .build();
+        let request = engine.create_request("example", "#channel", "bot", 1);
+        assert_eq!(request.channels(), "#channel,#chat,#news");
+        let request = engine.create_request("irc.other.net", "#channel", "bot", 1);
+        assert_eq!(request.channels(), "#channel,#chat");
+    }
+
+    #[test]
+    fn should_use_bot_settings() {
+        let engine = super::Engine::builder()
+            .bot(
+                "Bot",
+                crate::Bot::default()
+                    .with_alias("Bot|away")
+                    .with_channel("#bot-chat")
+                    .dialect(crate::Dialect::Ctcp),
+            )
+            .build();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        assert_eq!(request.dialect, crate::Dialect::Ctcp);
+        assert_eq!(request.botnames(), ["bot", "Bot|away"]);
+        assert_eq!(request.channels(), "#channel,#bot-chat");
+        let request = engine.create_request("irc.example.net", "#channel", "other", 1);
+        assert_eq!(request.dialect, crate::Dialect::Xdcc);
+        assert_eq!(request.botnames(), ["other"]);
+    }
+
+    #[tokio::test]
+    async fn should_limit_requests_per_bot() {
+        let engine = super::Engine::builder()
+            .bot("bot", crate::Bot::default().max_requests(1))
+            .build();
+        let first = engine.create_request("irc.example.net", "#channel", "Bot", 1);
+        let second = engine.create_request("irc.other.net", "#channel", "bot", 2);
+        let _permits = first.acquire().await;
+        tokio::time::timeout(Duration::from_millis(50), second.acquire())
+            .await
+            .unwrap_err();
+    }
+
+    #[test]
+    fn should_identify_requests() {
+        let engine = super::Engine::default();
+        let first = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        let second = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        assert_ne!(first.info.id, second.info.id);
+    }
+
+    #[test]
+    fn should_create_requests_from_range() {
+        let engine = super::Engine::default();
+        let requests = engine.create_requests("irc.example.net", "#channel", "bot", 1..=3);
+        let packnums: Vec<u64> = requests
+            .iter()
+            .map(|request| request.info.pack.number().unwrap())
+            .collect();
+        assert_eq!(packnums, [1, 2, 3]);
+        assert!(std::sync::Arc::ptr_eq(
+            &requests[0].info.botname,
+            &requests[2].info.botname
+        ));
+    }
+
+    #[test]
+    fn should_create_requests_from_selector() {
+        let engine = super::Engine::default();
+        let listing = [
+            crate::PackAnnouncement::parse("#1 12x [700M] Café.S01E01.mkv").unwrap(),
+            crate::PackAnnouncement::parse("#2 3x [1K] notes.txt").unwrap(),
+            crate::PackAnnouncement::parse("#3 5x [700M] Café.S01E02.mkv").unwrap(),
+        ];
+        let packnums = |requests: Vec<super::Request>| -> Vec<u64> {
+            requests
+                .iter()
+                .map(|request| request.info.pack.number().unwrap())
+                .collect()
+        };
+        let selector = crate::PackSelector::Glob("caf?.*.mkv".into());
+        let requests = engine.create_selected_requests(
+            "irc.example.net",
+            "#channel",
+            "bot",
+            selector,
+            &listing,
+        );
+        assert_eq!(packnums(requests), [1, 3]);
+        // the numbers don't need any listing
+        let requests =
+            engine.create_selected_requests("irc.example.net", "#channel", "bot", 4..=5, &[]);
+        assert_eq!(packnums(requests), [4, 5]);
+    }
+
+    #[tokio::test]
+    async fn should_broadcast_events() {
+        let engine = super::Engine::default();
+        let mut events = engine.events();
+        let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+        request.emit(super::EventKind::Started);
+        let event = events.recv().await.unwrap();
+        assert_eq!(&*event.request.botname, "bot");
+        assert!(matches!(event.kind, super::EventKind::Started));
+    }
+
+    #[tokio::test]
+    async fn should_probe_offer() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let response =
+            super::Response::decode(&format!("DCC SEND \"foo.txt\" 2130706433 {port} 1024"))
+                .unwrap();
+        response
+            .probe(std::time::Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        // the port is closed once its listener is dropped
+        drop(listener);
+        let err = response
+            .probe(std::time::Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            super::Error::Offer(super::OfferError::Unreachable { .. })
+        ));
+    }
+}
@@ -0,0 +1,314 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::RequestInfo;
+
+/// Errors happening while connecting and registering to the IRC server.
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectError {
+    /// The server address cannot be resolved.
+    #[error("unable to resolve the address of {host}")]
+    Resolve {
+        /// Hostname of the server.
+        host: String,
+        /// Error of the resolution.
+        #[source]
+        source: std::io::Error,
+    },
+    /// The server hostname doesn't exist, or has no address.
+    #[error("unknown host {host}")]
+    UnknownHost {
+        /// Hostname of the server.
+        host: String,
+    },
+    /// No TCP connection could be established with the server.
+    #[error("unable to connect to the server")]
+    Connect(#[source] std::io::Error),
+    /// The server didn't complete the registration in time.
+    #[error("the registration on the server timed out")]
+    RegistrationTimeout,
+    /// The nickname is already used by another client.
+    #[error("the nickname is already in use")]
+    NicknameInUse,
+    /// The server refused the SASL credentials, or doesn't support SASL.
+    #[error("the SASL authentication failed")]
+    Authentication,
+}
+
+/// Errors happening while exchanging IRC messages.
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    /// The server closed the connection.
+    #[error("the connection was closed by the server")]
+    ConnectionClosed,
+    /// The channel didn't become active in time.
+    #[error("no message received in the channel in time")]
+    JoinTimeout,
+    /// The messages couldn't be sent or received.
+    #[error("unable to exchange messages with the server")]
+    Irc(#[source] irc::error::Error),
+}
+
+/// Errors related to the DCC offer of the bot.
+#[derive(Debug, thiserror::Error)]
+pub enum OfferError {
+    /// The bot is not connected to the server, according to `WHOIS`.
+    #[error("the bot {0} is not connected to the server")]
+    BotOffline(String),
+    /// The bot didn't offer the file in time.
+    #[error("the bot didn't offer the file in time")]
+    Timeout,
+    /// The bot didn't offer the file in time, even after joining the channels it's in.
+    #[error(
+        "the bot didn't offer the file in time, even after joining {}",
+        channels.join(", ")
+    )]
+    TimeoutAfterDiscovery {
+        /// Channels of the bot joined after the first timeout.
+        channels: Vec<String>,
+    },
+    /// The bot asked to wait before requesting again, for longer than allowed or too
+    /// many times.
+    #[error("the bot asked to wait {0:?} before requesting again")]
+    Throttled(Duration),
+    /// The bot refused the request, with the given notice.
+    #[error("the bot refused the request: {0}")]
+    Refused(String),
+    /// The endpoint advertised by the DCC offer doesn't accept connections.
+    #[error("the offered endpoint {address} is unreachable")]
+    Unreachable {
+        /// Address advertised by the offer.
+        address: SocketAddr,
+        /// Error of the connection attempt.
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Errors happening while downloading the offered file.
+#[derive(Debug, thiserror::Error)]
+pub enum TransferError {
+    /// No TCP connection could be established with the sender.
+    #[error("unable to connect to the sender")]
+    Connect(#[source] std::io::Error),
+    /// No port could be listened on for the sender of a passive offer.
+    #[error("unable to listen for the sender")]
+    Listen(#[source] std::io::Error),
+    /// The data couldn't be received or written.
+    #[error("unable to transfer the data")]
+    Io(#[source] std::io::Error),
+    /// The sender didn't send any data in time.
+    #[error("no data received from the sender in time")]
+    Timeout,
+    /// The sender didn't accept to resume the transfer in time.
+    #[error("the sender didn't accept to resume the transfer in time")]
+    ResumeTimeout,
+    /// The sender closed the connection before sending the whole file.
+    #[error("the transfer ended after {received} of {expected} bytes")]
+    Incomplete {
+        /// Number of bytes received.
+        received: u64,
+        /// Size of the offered file.
+        expected: u64,
+    },
+    /// The data resent by the sender differs from the partial file, checked with
+    /// [`Transfer::verify`](crate::Transfer::verify).
+    #[error("the data received at {position} differs from the partial file")]
+    Mismatch {
+        /// Position of the first differing byte in the file.
+        position: u64,
+    },
+}
+
+/// Classification of the errors, independent of their details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Error while connecting and registering to the IRC server.
+    Connect,
+    /// Error while exchanging IRC messages.
+    Protocol,
+    /// The bot is not connected to the server.
+    BotOffline,
+    /// The bot didn't offer the file in time.
+    OfferTimeout,
+    /// The bot kept throttling the requests.
+    OfferThrottled,
+    /// The bot refused the request.
+    OfferRefused,
+    /// The endpoint advertised by the DCC offer is unreachable.
+    OfferUnreachable,
+    /// Error while downloading the offered file.
+    Transfer,
+    /// The request or the download exceeded its maximum lifetime.
+    DeadlineExceeded,
+}
+
+impl ErrorKind {
+    /// Name of the kind, like `offer_timeout`, used as metric label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Protocol => "protocol",
+            Self::BotOffline => "bot_offline",
+            Self::OfferTimeout => "offer_timeout",
+            Self::OfferThrottled => "offer_throttled",
+            Self::OfferRefused => "offer_refused",
+            Self::OfferUnreachable => "offer_unreachable",
+            Self::Transfer => "transfer",
+            Self::DeadlineExceeded => "deadline_exceeded",
+        }
+    }
+}
+
+/// Errors returned by the XDCC requests.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Error while connecting to the IRC server.
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    /// Error while exchanging IRC messages.
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    /// Error related to the DCC offer.
+    #[error(transparent)]
+    Offer(#[from] OfferError),
+    /// Error while downloading the offered file.
+    #[error(transparent)]
+    Transfer(#[from] TransferError),
+    /// The request or the download exceeded its maximum lifetime, and was cancelled.
+    #[error("the maximum lifetime was exceeded")]
+    DeadlineExceeded,
+    /// Error of a request, with the information identifying it.
+    #[error(
+        "request of pack {} to {} in {} on {} failed",
+        info.pack,
+        info.botname,
+        info.channel,
+        info.server
+    )]
+    Request {
+        /// Server, channel, bot and pack of the failed request.
+        info: RequestInfo,
+        /// Error of the request.
+        #[source]
+        source: Box<Error>,
+        /// Messages exchanged with the server, when the transcript capture is enabled.
+        transcript: Option<Vec<String>>,
+    },
+}
+
+impl Error {
+    /// Information of the request that failed, if known.
+    pub fn request_info(&self) -> Option<&RequestInfo> {
+        match self {
+            Self::Request { info, .. } => Some(info),
+            _ => None,
+        }
+    }
+
+    /// Messages exchanged with the server before the failure, when the transcript
+    /// capture is enabled on the engine.
+    pub fn transcript(&self) -> Option<&[String]> {
+        match self {
+            Self::Request { transcript, .. } => transcript.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Connect(_) => ErrorKind::Connect,
+            Self::Protocol(_) => ErrorKind::Protocol,
+            Self::Offer(OfferError::BotOffline(_)) => ErrorKind::BotOffline,
+            Self::Offer(OfferError::Timeout | OfferError::TimeoutAfterDiscovery { .. }) => {
+                ErrorKind::OfferTimeout
+            }
+            Self::Offer(OfferError::Throttled(_)) => ErrorKind::OfferThrottled,
+            Self::Offer(OfferError::Refused(_)) => ErrorKind::OfferRefused,
+            Self::Offer(OfferError::Unreachable { .. }) => ErrorKind::OfferUnreachable,
+            Self::Transfer(_) => ErrorKind::Transfer,
+            Self::DeadlineExceeded => ErrorKind::DeadlineExceeded,
+            Self::Request { source, .. } => source.kind(),
+        }
+    }
+
+    /// Whether executing the same request again may succeed.
+    ///
+    /// The network failures and timeouts are transient. An unknown hostname, refused
+    /// credentials, a refused request and an offer advertising an unreachable endpoint
+    /// are not.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self.inner(),
+            Self::Connect(ConnectError::Authentication | ConnectError::UnknownHost { .. })
+                | Self::Offer(OfferError::Refused(_) | OfferError::Unreachable { .. })
+        )
+    }
+
+    /// Error without the request information.
+    pub fn inner(&self) -> &Error {
+        match self {
+            Self::Request { source, .. } => source.inner(),
+            other => other,
+        }
+    }
+}
+
+impl From<irc::error::Error> for Error {
+    fn from(value: irc::error::Error) -> Self {
+        Self::Protocol(ProtocolError::Irc(value))
+    }
+}
+
+impl From<irc::proto::error::ProtocolError> for Error {
+    fn from(value: irc::proto::error::ProtocolError) -> Self {
+        Self::Protocol(ProtocolError::Irc(value.into()))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Protocol(ProtocolError::Irc(value.into()))
+    }
+}
+
+/// Result type of the XDCC requests.
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::sync::Arc;
+
+    use super::{Error, ErrorKind, OfferError};
+    use crate::{RequestId, RequestInfo};
+
+    #[test]
+    fn should_describe_request_context() {
+        let err = Error::Request {
+            info: RequestInfo {
+                id: RequestId::next(),
+                server: Arc::from("irc.example.net"),
+                channel: Arc::from("#channel"),
+                botname: Arc::from("bot"),
+                pack: 42.into(),
+            },
+            source: Box::new(Error::Offer(OfferError::Timeout)),
+            transcript: None,
+        };
+        assert_eq!(
+            err.to_string(),
+            "request of pack #42 to bot in #channel on irc.example.net failed"
+        );
+        assert_eq!(
+            err.source().unwrap().to_string(),
+            "the bot didn't offer the file in time"
+        );
+        assert_eq!(err.request_info().unwrap().pack, 42.into());
+        assert!(err.transcript().is_none());
+        assert!(matches!(err.inner(), Error::Offer(OfferError::Timeout)));
+        assert_eq!(err.kind(), ErrorKind::OfferTimeout);
+        assert!(err.is_retryable());
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use crate::{ChannelRules, QueueStatus, RequestInfo, Response};
+
+/// Step reached by a request.
+#[derive(Clone, Debug)]
+pub enum EventKind {
+    /// The request started its execution.
+    Started,
+    /// The connection to the IRC server is registered.
+    Connected,
+    /// The channel has been joined.
+    Joined,
+    /// Rules were detected in the topic or the entry notices of the channel.
+    ChannelRules(ChannelRules),
+    /// The XDCC command has been sent to the bot.
+    RequestSent,
+    /// The bot asked to wait the given delay, after which the request is sent again.
+    Throttled(Duration),
+    /// The bot queued the request, or announced its updated position in the queue.
+    Queued(QueueStatus),
+    /// The bot offered the file.
+    OfferReceived(Response),
+    /// The request failed, with the description of the error.
+    Failed(String),
+    /// The transfer of the offered file is connected to the sender, and starts at the
+    /// given position.
+    #[cfg(feature = "transfer")]
+    TransferStarted(u64),
+    /// The transfer reached the given position in the file, reported every second.
+    #[cfg(feature = "transfer")]
+    TransferProgress(u64),
+    /// The transfer is complete.
+    #[cfg(feature = "transfer")]
+    TransferCompleted(crate::TransferStats),
+    /// The transfer failed, with the description of the error.
+    #[cfg(feature = "transfer")]
+    TransferFailed(String),
+}
+
+/// Event emitted by the engine during the execution of a request.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Request emitting the event.
+    pub request: RequestInfo,
+    /// Step reached by the request.
+    pub kind: EventKind,
+}
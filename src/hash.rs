@@ -0,0 +1,135 @@
+//! Inline hashing of the received bytes.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use sha2::Digest as _;
+use tokio::io::AsyncWrite;
+
+/// Digest algorithm computed on the received bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Digest {
+    /// CRC32 checksum, as commonly advertised by XDCC bots.
+    Crc32,
+    /// SHA-256 hash.
+    Sha256,
+}
+
+/// Internal state of the digest being computed.
+#[derive(Clone, Debug)]
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    fn new(digest: Digest) -> Self {
+        match digest {
+            Digest::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+            Digest::Sha256 => Self::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Crc32(inner) => inner.update(data),
+            Self::Sha256(inner) => inner.update(data),
+        }
+    }
+
+    fn finalize(self) -> Checksum {
+        match self {
+            Self::Crc32(inner) => Checksum {
+                digest: Digest::Crc32,
+                value: inner.finalize().to_be_bytes().to_vec(),
+            },
+            Self::Sha256(inner) => Checksum {
+                digest: Digest::Sha256,
+                value: inner.finalize().to_vec(),
+            },
+        }
+    }
+}
+
+/// Result of a digest computation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    /// Algorithm used to compute the checksum.
+    pub digest: Digest,
+    /// Raw value of the checksum.
+    pub value: Vec<u8>,
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value
+            .iter()
+            .try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+/// Writer computing the configured digest of the bytes written through it.
+///
+/// Wrapping the destination of a transfer avoids reading the whole file again
+/// once it's complete to verify it.
+#[derive(Debug)]
+pub struct HashingWriter<W> {
+    hasher: Hasher,
+    inner: W,
+}
+
+impl<W> HashingWriter<W> {
+    /// Wraps the given writer, computing the given digest.
+    pub fn new(inner: W, digest: Digest) -> Self {
+        Self {
+            hasher: Hasher::new(digest),
+            inner,
+        }
+    }
+
+    /// Returns the checksum of the written bytes, and the inner writer.
+    pub fn finalize(self) -> (Checksum, W) {
+        (self.hasher.finalize(), self.inner)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let written = std::task::ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        // only the bytes accepted by the inner writer are part of the file
+        this.hasher.update(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::{Digest, HashingWriter};
+
+    #[test_case::test_case(Digest::Crc32, "cbf43926"; "crc32")]
+    #[test_case::test_case(Digest::Sha256, "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225"; "sha256")]
+    #[tokio::test]
+    async fn should_hash_written_bytes(digest: Digest, expected: &str) {
+        let mut writer = HashingWriter::new(Vec::new(), digest);
+        writer.write_all(b"12345").await.unwrap();
+        writer.write_all(b"6789").await.unwrap();
+        let (checksum, inner) = writer.finalize();
+        assert_eq!(inner, b"123456789");
+        assert_eq!(checksum.to_string(), expected);
+    }
+}
@@ -0,0 +1,166 @@
+//! Lightweight identd (RFC 1413) responder, answering the servers that check the
+//! username of the connections during their registration.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Time given to a client to send its query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers the ident queries about the connections being registered.
+///
+/// No background task is involved: the queries are only answered while a registration
+/// is in progress, within [`Identd::serve_while`].
+#[derive(Debug)]
+pub(crate) struct Identd {
+    /// Address the responder listens on, usually on port 113.
+    address: SocketAddr,
+    /// Listener shared by the registrations, bound on first use.
+    listener: tokio::sync::OnceCell<Arc<TcpListener>>,
+    /// Usernames of the connections being registered, indexed by local port.
+    usernames: Mutex<HashMap<u16, String>>,
+}
+
+impl Identd {
+    pub(crate) fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            listener: Default::default(),
+            usernames: Default::default(),
+        }
+    }
+
+    /// Runs the given future, answering meanwhile the queries about the connection
+    /// using the given local port with the given username.
+    ///
+    /// The future runs without responder if the listener cannot be bound.
+    pub(crate) async fn serve_while<T>(
+        &self,
+        local_port: u16,
+        username: &str,
+        future: impl Future<Output = T>,
+    ) -> T {
+        let listener = match self
+            .listener
+            .get_or_try_init(|| async { TcpListener::bind(self.address).await.map(Arc::new) })
+            .await
+        {
+            Ok(listener) => listener.clone(),
+            Err(err) => {
+                warn!(
+                    "unable to listen for ident queries on {}: {err}",
+                    self.address
+                );
+                return future.await;
+            }
+        };
+
+        self.lock().insert(local_port, username.to_owned());
+        let result = {
+            let future = std::pin::pin!(future);
+            let accept = std::pin::pin!(self.accept(&listener));
+            match futures_util::future::select(future, accept).await {
+                futures_util::future::Either::Left((result, _)) => result,
+                futures_util::future::Either::Right((never, _)) => match never {},
+            }
+        };
+        self.lock().remove(&local_port);
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u16, String>> {
+        self.usernames
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Answers the queries, one at a time.
+    async fn accept(&self, listener: &TcpListener) -> std::convert::Infallible {
+        loop {
+            let Ok((stream, peer_addr)) = listener.accept().await else {
+                continue;
+            };
+            if let Err(err) = tokio::time::timeout(QUERY_TIMEOUT, self.answer(stream)).await {
+                debug!("ident query from {peer_addr} failed: {err}");
+            }
+        }
+    }
+
+    /// Reads a query and writes its answer.
+    async fn answer(&self, stream: TcpStream) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut query = String::new();
+        BufReader::new(reader).read_line(&mut query).await?;
+        let reply = reply(&query, |port| self.lock().get(&port).cloned());
+        writer.write_all(reply.as_bytes()).await
+    }
+}
+
+/// Builds the reply to the given query, like `6191, 23`, with the username of the
+/// local port given by `username`.
+fn reply(query: &str, username: impl FnOnce(u16) -> Option<String>) -> String {
+    let ports = query.split_once(',').and_then(|(local, remote)| {
+        Some((
+            local.trim().parse::<u16>().ok()?,
+            remote.trim().parse::<u16>().ok()?,
+        ))
+    });
+    match ports {
+        Some((local, remote)) => match username(local) {
+            Some(username) => format!("{local} , {remote} : USERID : UNIX : {username}\r\n"),
+            None => format!("{local} , {remote} : ERROR : NO-USER\r\n"),
+        },
+        None => format!("{} : ERROR : INVALID-PORT\r\n", query.trim()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{Identd, reply};
+
+    #[test]
+    fn should_reply_username() {
+        let lookup = |port| (port == 6191).then(|| "nickname".to_owned());
+        assert_eq!(
+            reply("6191, 6667\r\n", lookup),
+            "6191 , 6667 : USERID : UNIX : nickname\r\n"
+        );
+        assert_eq!(
+            reply("6192 , 6667\r\n", lookup),
+            "6192 , 6667 : ERROR : NO-USER\r\n"
+        );
+        assert_eq!(
+            reply("hello\r\n", lookup),
+            "hello : ERROR : INVALID-PORT\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_answer_while_registering() {
+        let probe = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = probe.local_addr().unwrap();
+        drop(probe);
+
+        let identd = Identd::new(address);
+        let answer = identd
+            .serve_while(6191, "nickname", async {
+                let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+                stream.write_all(b"6191, 6667\r\n").await.unwrap();
+                let mut answer = String::new();
+                stream.read_to_string(&mut answer).await.unwrap();
+                answer
+            })
+            .await;
+        assert_eq!(answer, "6191 , 6667 : USERID : UNIX : nickname\r\n");
+    }
+}
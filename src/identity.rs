@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Mixes the bits of the given value, using the SplitMix64 finalizer.
+pub(crate) fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
+
+/// Lock-free generator of IRC names, like `adjective-noun`.
+///
+/// Each name is derived from a seed and an atomic counter, so concurrent requests
+/// never wait on each other to get a name.
+#[derive(Debug)]
+pub(crate) struct NameGenerator {
+    counter: AtomicU64,
+    seed: u64,
+}
+
+impl Default for NameGenerator {
+    fn default() -> Self {
+        Self::new(RandomState::new().hash_one(0u64))
+    }
+}
+
+impl NameGenerator {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            counter: AtomicU64::new(0),
+            seed,
+        }
+    }
+
+    /// Generates the next name.
+    pub(crate) fn next_name(&self) -> String {
+        let index = self.counter.fetch_add(1, Ordering::Relaxed);
+        let value = mix(self
+            .seed
+            .wrapping_add(index.wrapping_mul(0x9e3779b97f4a7c15)));
+        let adjective = names::ADJECTIVES[(value % names::ADJECTIVES.len() as u64) as usize];
+        let noun = names::NOUNS[((value >> 32) % names::NOUNS.len() as u64) as usize];
+        format!("{adjective}-{noun}")
+    }
+}
+
+/// Nickname and username used on an IRC server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Identity {
+    pub nickname: String,
+    pub username: String,
+}
+
+/// Provides the identities used to connect to the IRC servers.
+#[derive(Debug, Default)]
+pub(crate) struct IdentityProvider {
+    /// Name generator for IRC nicknames.
+    nicknames: NameGenerator,
+    /// Whether the identities are pinned for the lifetime of the engine.
+    persistent: bool,
+    /// Servers overriding whether their identity is pinned.
+    persistent_overrides: HashMap<String, bool>,
+    /// Identities pinned, indexed by server.
+    pinned: Mutex<HashMap<String, Identity>>,
+    /// Name generator for IRC usernames.
+    usernames: NameGenerator,
+}
+
+impl IdentityProvider {
+    /// Creates a provider, generating reproducible names when a seed is given.
+    pub(crate) fn new(persistent: bool, seed: Option<u64>) -> Self {
+        let (nicknames, usernames) = match seed {
+            // the usernames must not repeat the nicknames
+            Some(seed) => (NameGenerator::new(seed), NameGenerator::new(mix(seed))),
+            None => Default::default(),
+        };
+        Self {
+            nicknames,
+            persistent,
+            persistent_overrides: HashMap::new(),
+            pinned: Default::default(),
+            usernames,
+        }
+    }
+
+    /// Overrides whether the identity of the given server is pinned.
+    pub(crate) fn with_persistent(mut self, server: impl Into<String>, persistent: bool) -> Self {
+        self.persistent_overrides.insert(server.into(), persistent);
+        self
+    }
+
+    fn generate(&self) -> Identity {
+        Identity {
+            nickname: self.nicknames.next_name(),
+            username: self.usernames.next_name(),
+        }
+    }
+
+    /// Returns the identity to use on the given server.
+    ///
+    /// A new identity is generated for each connection, unless the identities are persistent.
+    pub(crate) fn identity(&self, server: &str) -> Identity {
+        let persistent = self
+            .persistent_overrides
+            .get(server)
+            .copied()
+            .unwrap_or(self.persistent);
+        if !persistent {
+            return self.generate();
+        }
+        let mut lock = self.pinned.lock().unwrap_or_else(PoisonError::into_inner);
+        lock.entry(server.to_owned())
+            .or_insert_with(|| self.generate())
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdentityProvider, NameGenerator};
+
+    #[test]
+    fn should_generate_identity_per_connection() {
+        let provider = IdentityProvider::new(false, None);
+        assert_ne!(
+            provider.identity("irc.example.net"),
+            provider.identity("irc.example.net")
+        );
+    }
+
+    #[test]
+    fn should_pin_identity_per_server() {
+        let provider = IdentityProvider::new(true, None);
+        let first = provider.identity("irc.example.net");
+        assert_eq!(first, provider.identity("irc.example.net"));
+        assert_ne!(first, provider.identity("irc.other.net"));
+    }
+
+    #[test]
+    fn should_override_persistence_per_server() {
+        let provider = IdentityProvider::new(false, None).with_persistent("irc.example.net", true);
+        assert_eq!(
+            provider.identity("irc.example.net"),
+            provider.identity("irc.example.net")
+        );
+        assert_ne!(
+            provider.identity("irc.other.net"),
+            provider.identity("irc.other.net")
+        );
+    }
+
+    #[test]
+    fn should_reproduce_identities_from_seed() {
+        let first = IdentityProvider::new(false, Some(42));
+        let second = IdentityProvider::new(false, Some(42));
+        for _ in 0..3 {
+            assert_eq!(
+                first.identity("irc.example.net"),
+                second.identity("irc.example.net")
+            );
+        }
+    }
+
+    #[test]
+    fn should_generate_names() {
+        let generator = NameGenerator::default();
+        let name = generator.next_name();
+        // some words contain dashes
+        assert!(names::ADJECTIVES.iter().any(|adjective| {
+            name.strip_prefix(adjective)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .is_some_and(|noun| names::NOUNS.contains(&noun))
+        }));
+    }
+
+    #[test]
+    fn should_generate_different_names() {
+        let generator = NameGenerator::new(42);
+        let first = generator.next_name();
+        let second = generator.next_name();
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use tokio::sync::watch;
+
+use crate::{PackId, Response};
+
+/// Server and bot of a request in lowercase, and its pack.
+type Key = (String, String, PackId);
+
+/// Requests being executed, indexed by server, bot and pack, so the identical requests
+/// wait for their offer instead of asking the bot again.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    /// Receives the offer of each request, once received.
+    requests: Mutex<HashMap<Key, watch::Receiver<Option<Response>>>>,
+}
+
+/// Outcome of joining the identical requests.
+pub(crate) enum Joined<'a> {
+    /// An identical request received the offer.
+    Done(Response),
+    /// No identical request is running, the caller executes the request and shares its
+    /// offer with the given leader.
+    Leader(Leader<'a>),
+}
+
+impl InFlight {
+    /// Waits for the identical request being executed, if any.
+    ///
+    /// When it fails or is cancelled, one of the waiting requests becomes the leader
+    /// and executes the request in turn.
+    pub(crate) async fn join(&self, server: &str, botname: &str, pack: &PackId) -> Joined<'_> {
+        let key = (
+            server.to_ascii_lowercase(),
+            botname.to_ascii_lowercase(),
+            pack.clone(),
+        );
+        loop {
+            let mut receiver = {
+                let mut requests = self.requests.lock().unwrap_or_else(PoisonError::into_inner);
+                match requests.get(&key) {
+                    Some(receiver) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        requests.insert(key.clone(), receiver);
+                        return Joined::Leader(Leader {
+                            in_flight: self,
+                            key,
+                            sender,
+                        });
+                    }
+                }
+            };
+            // the sender is dropped without offer when the leader fails
+            if let Ok(response) = receiver.wait_for(Option::is_some).await
+                && let Some(ref response) = *response
+            {
+                return Joined::Done(response.clone());
+            }
+        }
+    }
+}
+
+/// Request executed on behalf of the identical ones, removed from the running
+/// requests when dropped.
+pub(crate) struct Leader<'a> {
+    in_flight: &'a InFlight,
+    key: Key,
+    sender: watch::Sender<Option<Response>>,
+}
+
+impl Leader<'_> {
+    /// Shares the offer with the identical requests.
+    pub(crate) fn complete(&self, response: Response) {
+        self.sender.send_replace(Some(response));
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.in_flight
+            .requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{InFlight, Joined};
+    use crate::test_util::local_response;
+    use crate::{PackId, Response};
+
+    fn response() -> Response {
+        local_response("ubuntu.iso", 5000, 1024)
+    }
+
+    #[tokio::test]
+    async fn should_share_offer_with_identical_requests() {
+        let in_flight = InFlight::default();
+        let pack = PackId::Number(1);
+        let Joined::Leader(leader) = in_flight.join("irc.example.net", "bot", &pack).await else {
+            panic!("the first request should lead");
+        };
+        // another pack is not affected
+        assert!(matches!(
+            in_flight
+                .join("irc.example.net", "bot", &PackId::Number(2))
+                .await,
+            Joined::Leader(_)
+        ));
+        let follower = in_flight.join("IRC.example.net", "Bot", &pack);
+        tokio::pin!(follower);
+        tokio::time::timeout(Duration::from_millis(50), &mut follower)
+            .await
+            .err()
+            .unwrap();
+        leader.complete(response());
+        assert!(matches!(follower.await, Joined::Done(res) if res == response()));
+    }
+
+    #[tokio::test]
+    async fn should_lead_when_identical_request_fails() {
+        let in_flight = InFlight::default();
+        let pack = PackId::Number(1);
+        let leader = in_flight.join("irc.example.net", "bot", &pack).await;
+        let follower = in_flight.join("irc.example.net", "bot", &pack);
+        tokio::pin!(follower);
+        tokio::time::timeout(Duration::from_millis(50), &mut follower)
+            .await
+            .err()
+            .unwrap();
+        drop(leader);
+        assert!(matches!(follower.await, Joined::Leader(_)));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Step of a request for which the latency is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Phase {
+    /// From the identification to the end of the registration.
+    Registration,
+    /// From the channel join to the first private message.
+    Join,
+    /// From the XDCC command to the DCC offer.
+    Offer,
+}
+
+impl Phase {
+    /// Error returned when the phase doesn't complete in time.
+    pub(crate) fn timeout_error(self) -> crate::Error {
+        match self {
+            Self::Registration => crate::ConnectError::RegistrationTimeout.into(),
+            Self::Join => crate::ProtocolError::JoinTimeout.into(),
+            Self::Offer => crate::OfferError::Timeout.into(),
+        }
+    }
+
+    /// Span covering the phase.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn span(self) -> tracing::Span {
+        match self {
+            Self::Registration => tracing::info_span!("register"),
+            Self::Join => tracing::info_span!("join"),
+            Self::Offer => tracing::info_span!("offer_wait"),
+        }
+    }
+}
+
+/// Ratio between the measured latency and the computed timeout.
+const LATENCY_FACTOR: u32 = 4;
+
+/// Tracks the latencies of the servers to compute the timeout of each phase.
+#[derive(Debug)]
+pub(crate) struct LatencyTracker {
+    /// Bounds of the adaptive timeouts, disabled when `None`.
+    bounds: Option<(Duration, Duration)>,
+    /// Timeout used when no latency has been measured.
+    fallback: Mutex<Duration>,
+    /// Smoothed latencies, indexed by server and phase.
+    latencies: Mutex<HashMap<(String, Phase), Duration>>,
+}
+
+impl LatencyTracker {
+    pub(crate) fn new(fallback: Duration, bounds: Option<(Duration, Duration)>) -> Self {
+        Self {
+            bounds,
+            fallback: Mutex::new(fallback),
+            latencies: Default::default(),
+        }
+    }
+
+    /// Replaces the timeout used when no latency has been measured.
+    pub(crate) fn set_fallback(&self, value: Duration) {
+        *self.fallback.lock().unwrap_or_else(PoisonError::into_inner) = value;
+    }
+
+    /// Timeout used when no latency has been measured.
+    fn fallback(&self) -> Duration {
+        *self.fallback.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records a measured latency, using an exponentially weighted moving average.
+    pub(crate) fn record(&self, server: &str, phase: Phase, value: Duration) {
+        if self.bounds.is_none() {
+            return;
+        }
+        self.latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((server.to_owned(), phase))
+            .and_modify(|current| *current = (*current * 3 + value) / 4)
+            .or_insert(value);
+    }
+
+    /// Computes the timeout of the given phase on the given server.
+    pub(crate) fn timeout(&self, server: &str, phase: Phase) -> Duration {
+        let Some((min, max)) = self.bounds else {
+            return self.fallback();
+        };
+        let Some(latency) = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&(server.to_owned(), phase))
+            .copied()
+        else {
+            return self.fallback();
+        };
+        (latency * LATENCY_FACTOR).clamp(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LatencyTracker, Phase};
+
+    #[test]
+    fn should_use_fallback_when_disabled() {
+        let tracker = LatencyTracker::new(Duration::from_secs(30), None);
+        tracker.record("irc.example.net", Phase::Offer, Duration::from_secs(1));
+        assert_eq!(
+            tracker.timeout("irc.example.net", Phase::Offer),
+            Duration::from_secs(30)
+        );
+        tracker.set_fallback(Duration::from_secs(10));
+        assert_eq!(
+            tracker.timeout("irc.example.net", Phase::Offer),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn should_adapt_to_measured_latency() {
+        let tracker = LatencyTracker::new(
+            Duration::from_secs(30),
+            Some((Duration::from_secs(5), Duration::from_secs(120))),
+        );
+        assert_eq!(
+            tracker.timeout("irc.example.net", Phase::Offer),
+            Duration::from_secs(30)
+        );
+        tracker.record("irc.example.net", Phase::Offer, Duration::from_secs(20));
+        assert_eq!(
+            tracker.timeout("irc.example.net", Phase::Offer),
+            Duration::from_secs(80)
+        );
+        tracker.record("irc.example.net", Phase::Offer, Duration::from_secs(60));
+        assert_eq!(
+            tracker.timeout("irc.example.net", Phase::Offer),
+            Duration::from_secs(120)
+        );
+        tracker.record(
+            "irc.example.net",
+            Phase::Registration,
+            Duration::from_millis(100),
+        );
+        assert_eq!(
+            tracker.timeout("irc.example.net", Phase::Registration),
+            Duration::from_secs(5)
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Limits the number of concurrent requests on a same server, or with a same bot.
+#[derive(Debug)]
+pub(crate) struct ServerLimiter {
+    /// Maximum number of concurrent requests per server, unlimited when `None`.
+    limit: Mutex<Option<usize>>,
+    /// Limits of the servers overriding the default one.
+    limits: Mutex<HashMap<String, usize>>,
+    /// Slots, indexed by server.
+    slots: Mutex<HashMap<String, Arc<Slots>>>,
+}
+
+/// Slots of a server, resized in place when its limit changes.
+#[derive(Debug)]
+struct Slots {
+    semaphore: Arc<Semaphore>,
+    /// Number of slots, and the acquired ones exceeding it since it decreased.
+    capacity: Mutex<Capacity>,
+}
+
+#[derive(Debug)]
+struct Capacity {
+    size: usize,
+    /// Acquired slots to forget once released, instead of making them available.
+    excess: usize,
+}
+
+impl Slots {
+    fn new(size: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(size)),
+            capacity: Mutex::new(Capacity { size, excess: 0 }),
+        }
+    }
+
+    /// Changes the number of slots, the acquired ones counting against the new size.
+    fn resize(&self, size: usize) {
+        let mut capacity = self.capacity.lock().unwrap_or_else(PoisonError::into_inner);
+        if size >= capacity.size {
+            // the excess slots are kept rather than forgotten then added again
+            let added = size - capacity.size;
+            let kept = added.min(capacity.excess);
+            capacity.excess -= kept;
+            self.semaphore.add_permits(added - kept);
+        } else {
+            let removed = capacity.size - size;
+            capacity.excess += removed - self.semaphore.forget_permits(removed);
+        }
+        capacity.size = size;
+    }
+}
+
+/// Slot acquired on a server, released when dropped.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    permit: Option<OwnedSemaphorePermit>,
+    slots: Arc<Slots>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        // released under the lock, so a concurrent resize sees a consistent excess
+        let mut capacity = self
+            .slots
+            .capacity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+        if capacity.excess > 0 {
+            capacity.excess -= 1;
+            permit.forget();
+        }
+    }
+}
+
+impl ServerLimiter {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit: Mutex::new(limit),
+            limits: Default::default(),
+            slots: Default::default(),
+        }
+    }
+
+    /// Overrides the limit of the given server.
+    pub(crate) fn with_limit(self, server: impl Into<String>, limit: usize) -> Self {
+        self.set_server_limit(server.into(), Some(limit));
+        self
+    }
+
+    /// Replaces the default limit, resizing the slots of the servers without their own.
+    ///
+    /// The slots already acquired count against the new limit, while the requests
+    /// started when the server was unlimited hold none, so they don't. The servers
+    /// becoming unlimited forget their slots, and let their waiting requests through.
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        *self.limit.lock().unwrap_or_else(PoisonError::into_inner) = limit;
+        let limits = self.limits.lock().unwrap_or_else(PoisonError::into_inner);
+        self.slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|server, slots| {
+                let Some(limit) = limits.get(server).copied().or(limit) else {
+                    slots.semaphore.close();
+                    return false;
+                };
+                slots.resize(limit);
+                true
+            });
+    }
+
+    /// Overrides the limit of the given server, or falls back to the default one when
+    /// `None`, resizing its slots like [`ServerLimiter::set_limit`].
+    pub(crate) fn set_server_limit(&self, server: String, limit: Option<usize>) {
+        let default = *self.limit.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut limits = self.limits.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        let effective = limit.or(default);
+        match (slots.get(&server), effective) {
+            (Some(existing), Some(effective)) => existing.resize(effective),
+            (Some(existing), None) => {
+                existing.semaphore.close();
+                slots.remove(&server);
+            }
+            (None, _) => {}
+        }
+        match limit {
+            Some(limit) => limits.insert(server, limit),
+            None => limits.remove(&server),
+        };
+    }
+
+    /// Waits for a slot on the given server to be available.
+    ///
+    /// The slot is released when the returned permit is dropped. No slot is returned
+    /// when the server is unlimited, or becomes so while waiting.
+    pub(crate) async fn acquire(&self, server: &str) -> Option<Permit> {
+        let default = *self.limit.lock().unwrap_or_else(PoisonError::into_inner);
+        // the slots are created under the lock of the limits, so they can't be changed
+        // meanwhile, and the maps stay consistent even if a thread panicked holding them
+        let slots = {
+            let limits = self.limits.lock().unwrap_or_else(PoisonError::into_inner);
+            let limit = limits.get(server).copied().or(default)?;
+            self.slots
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(server.to_owned())
+                .or_insert_with(|| Arc::new(Slots::new(limit)))
+                .clone()
+        };
+        let permit = slots.semaphore.clone().acquire_owned().await.ok()?;
+        Some(Permit {
+            permit: Some(permit),
+            slots,
+        })
+    }
+}
+
+/// Spaces the requests sent on a same server by a minimum interval.
+#[derive(Debug, Default)]
+pub(crate) struct RequestPacer {
+    /// Minimum intervals, indexed by server.
+    intervals: Mutex<HashMap<String, Duration>>,
+    /// Instants of the next available turns, indexed by server.
+    turns: Mutex<HashMap<String, Instant>>,
+}
+
+impl RequestPacer {
+    /// Spaces the requests sent on the given server by the given interval.
+    pub(crate) fn with_interval(self, server: impl Into<String>, interval: Duration) -> Self {
+        self.set_interval(server.into(), Some(interval));
+        self
+    }
+
+    /// Changes the interval of the given server, `None` removing it.
+    ///
+    /// The turns already given are kept, the following ones are spaced by the new
+    /// interval.
+    pub(crate) fn set_interval(&self, server: String, interval: Option<Duration>) {
+        let mut intervals = self
+            .intervals
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match interval {
+            Some(interval) => intervals.insert(server, interval),
+            None => intervals.remove(&server),
+        };
+    }
+
+    /// Waits for the turn of a request on the given server.
+    ///
+    /// The turns are given in the order of the calls, the waiting ones keep theirs.
+    pub(crate) async fn wait(&self, server: &str) {
+        let interval = self
+            .intervals
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(server)
+            .copied();
+        let Some(interval) = interval else {
+            return;
+        };
+        let turn = {
+            let mut turns = self.turns.lock().unwrap_or_else(PoisonError::into_inner);
+            let next = turns.entry(server.to_owned()).or_insert_with(Instant::now);
+            let turn = (*next).max(Instant::now());
+            *next = turn + interval;
+            turn
+        };
+        tokio::time::sleep_until(turn).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RequestPacer, ServerLimiter};
+
+    #[tokio::test]
+    async fn should_not_limit_by_default() {
+        let limiter = ServerLimiter::new(None);
+        assert!(limiter.acquire("irc.example.net").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_available_slot() {
+        let limiter = ServerLimiter::new(Some(1));
+        let permit = limiter.acquire("irc.example.net").await.unwrap();
+        // another server is not affected
+        let _other = limiter.acquire("irc.other.net").await.unwrap();
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("irc.example.net"),
+        )
+        .await
+        .unwrap_err();
+        drop(permit);
+        let _permit = limiter.acquire("irc.example.net").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_override_limit_per_server() {
+        let limiter = ServerLimiter::new(None).with_limit("irc.example.net", 1);
+        assert!(limiter.acquire("irc.other.net").await.is_none());
+        let _permit = limiter.acquire("irc.example.net").await.unwrap();
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("irc.example.net"),
+        )
+        .await
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn should_apply_new_limit() {
+        let limiter = ServerLimiter::new(Some(1)).with_limit("irc.other.net", 1);
+        let first = limiter.acquire("irc.example.net").await.unwrap();
+        let _other = limiter.acquire("irc.other.net").await.unwrap();
+        // the waiting request gets the added slot
+        let (second, ()) = tokio::join!(limiter.acquire("irc.example.net"), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            limiter.set_limit(Some(2));
+        });
+        let second = second.unwrap();
+        // the acquired slots count against the new limit
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("irc.example.net"),
+        )
+        .await
+        .unwrap_err();
+        // the overridden limit is kept
+        tokio::time::timeout(Duration::from_millis(50), limiter.acquire("irc.other.net"))
+            .await
+            .unwrap_err();
+        limiter.set_limit(Some(1));
+        drop(first);
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("irc.example.net"),
+        )
+        .await
+        .unwrap_err();
+        drop(second);
+        let _permit = limiter.acquire("irc.example.net").await.unwrap();
+        limiter.set_limit(None);
+        assert!(limiter.acquire("irc.example.net").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_apply_new_server_limit() {
+        let limiter = ServerLimiter::new(Some(1));
+        let _permit = limiter.acquire("irc.example.net").await.unwrap();
+        limiter.set_server_limit("irc.example.net".into(), Some(2));
+        let second = limiter.acquire("irc.example.net").await.unwrap();
+        limiter.set_server_limit("irc.example.net".into(), None);
+        drop(second);
+        // back to the default limit, with a slot still acquired
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("irc.example.net"),
+        )
+        .await
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn should_release_waiting_requests_when_unlimited() {
+        let limiter = ServerLimiter::new(Some(1)).with_limit("irc.other.net", 1);
+        let _permit = limiter.acquire("irc.example.net").await.unwrap();
+        let _other = limiter.acquire("irc.other.net").await.unwrap();
+        let (waiting, ()) = tokio::join!(limiter.acquire("irc.example.net"), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            limiter.set_limit(None);
+        });
+        assert!(waiting.is_none());
+        let (waiting, ()) = tokio::join!(limiter.acquire("irc.other.net"), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            limiter.set_server_limit("irc.other.net".into(), None);
+        });
+        assert!(waiting.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_not_count_unlimited_requests_against_new_limit() {
+        let limiter = ServerLimiter::new(None);
+        // the running request holds no slot
+        assert!(limiter.acquire("irc.example.net").await.is_none());
+        limiter.set_limit(Some(1));
+        let _permit = limiter.acquire("irc.example.net").await.unwrap();
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("irc.example.net"),
+        )
+        .await
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn should_space_requests() {
+        let pacer =
+            RequestPacer::default().with_interval("irc.example.net", Duration::from_millis(100));
+        let started_at = std::time::Instant::now();
+        pacer.wait("irc.example.net").await;
+        // another server is not affected
+        pacer.wait("irc.other.net").await;
+        assert!(started_at.elapsed() < Duration::from_millis(100));
+        pacer.wait("irc.example.net").await;
+        pacer.wait("irc.example.net").await;
+        assert!(started_at.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn should_keep_limiting_after_panic() {
+        let limiter = ServerLimiter::new(Some(1));
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _lock = limiter.slots.lock().unwrap();
+                    panic!("poisoning the lock");
+                })
+                .join()
+                .unwrap_err();
+        });
+        let _permit = limiter.acquire("irc.example.net").await.unwrap();
+        tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("irc.example.net"),
+        )
+        .await
+        .unwrap_err();
+    }
+}
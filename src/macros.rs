@@ -0,0 +1,49 @@
+//! Diagnostics macros, forwarding to the `log` facade when the `log` feature is enabled,
+//! and to the `tracing` spans when the `tracing` feature is enabled.
+
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::debug!($($arg)+);
+        // keeps the arguments used, without evaluating them
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::info!($($arg)+);
+        // keeps the arguments used, without evaluating them
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::warn!($($arg)+);
+        // keeps the arguments used, without evaluating them
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+macro_rules! instrument {
+    ($future:expr, $span:expr) => {{
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument($future, $span);
+        // the span is not even built
+        #[cfg(not(feature = "tracing"))]
+        let future = $future;
+        future
+    }};
+}
@@ -0,0 +1,250 @@
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::Connector;
+
+/// Profile of an IRC network, registered on the engine with
+/// [`EngineBuilder::network`](crate::EngineBuilder::network).
+///
+/// The requests created with the name of the network as server connect to the servers
+/// of the network, and use its settings instead of the ones of the engine.
+///
+/// Like the round-robin hosts published by the IRC networks, the connections rotate
+/// over the servers, and fail over to the next one when a server cannot be reached.
+///
+/// ```
+/// use std::time::Duration;
+/// use xdcc_request::{Engine, Network};
+///
+/// let engine = Engine::builder()
+///     .network(
+///         "example",
+///         Network::new("irc.example.net")
+///             .with_server("irc2.example.net")
+///             .max_requests(1)
+///             .request_delay(Duration::from_secs(10))
+///             .request_interval(Duration::from_secs(30))
+///             .sasl("account", "password"),
+///     )
+///     .build();
+/// let request = engine.create_request("example", "#channel", "bot", 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Network {
+    /// Channels joined on every connection along with the channel of the request.
+    pub(crate) channels: Vec<String>,
+    pub(crate) max_requests: Option<usize>,
+    /// Index of the server the next connection starts with.
+    next: Arc<AtomicUsize>,
+    pub(crate) persistent_identity: Option<bool>,
+    pub(crate) request_delay: Option<Duration>,
+    pub(crate) request_interval: Option<Duration>,
+    pub(crate) sasl: Option<SaslCredentials>,
+    /// Addresses of the servers, never empty.
+    servers: Vec<String>,
+    /// Domain whose service records list the servers, when discovered.
+    pub(crate) srv_domain: Option<String>,
+    /// Connector handling TLS, when the network is connected to over TLS.
+    pub(crate) tls: Option<Arc<dyn Connector>>,
+}
+
+impl Network {
+    /// Creates the profile of the network reachable at the given server address.
+    pub fn new(server: impl Into<String>) -> Self {
+        Self {
+            channels: Vec::new(),
+            max_requests: None,
+            next: Default::default(),
+            persistent_identity: None,
+            request_delay: None,
+            request_interval: None,
+            sasl: None,
+            servers: vec![server.into()],
+            srv_domain: None,
+            tls: None,
+        }
+    }
+
+    /// Creates the profile of the network whose servers are discovered from the
+    /// `_irc._tcp` service records of the given domain, or the `_ircs._tcp` ones
+    /// advertising the TLS ports when connected to with [`Network::tls`].
+    ///
+    /// The domain itself is connected to on the default port when it has no record,
+    /// or none of them is reachable.
+    /// The records are resolved by the [`Resolver`](crate::Resolver) of the engine.
+    pub fn srv(domain: impl Into<String>) -> Self {
+        let domain = domain.into();
+        Self {
+            srv_domain: Some(domain.clone()),
+            ..Self::new(domain)
+        }
+    }
+
+    /// Adds the address of another server of the network.
+    pub fn with_server(mut self, server: impl Into<String>) -> Self {
+        self.servers.push(server.into());
+        self
+    }
+
+    /// Joins the given channel on every connection to the network along with the channel
+    /// of the request, like the chat channels some networks require their users to stay in.
+    pub fn with_channel(mut self, channel: impl Into<String>) -> Self {
+        self.channels.push(channel.into());
+        self
+    }
+
+    /// Addresses of the servers of the network.
+    pub fn servers(&self) -> &[String] {
+        &self.servers
+    }
+
+    /// Addresses of the servers to try in order for the next connection, starting
+    /// with the server following the one of the previous connection.
+    pub(crate) fn rotation(&self) -> impl Iterator<Item = &str> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % self.servers.len();
+        let (before, after) = self.servers.split_at(start);
+        after.iter().chain(before).map(String::as_str)
+    }
+
+    /// Maximum number of requests executed concurrently on the network, instead of the
+    /// [`EngineBuilder::max_requests_per_server`](crate::EngineBuilder::max_requests_per_server).
+    pub fn max_requests(mut self, value: usize) -> Self {
+        self.max_requests = Some(value);
+        self
+    }
+
+    /// Whether the same nickname and username are kept on the network, instead of the
+    /// [`EngineBuilder::persistent_identity`](crate::EngineBuilder::persistent_identity).
+    pub fn persistent_identity(mut self, value: bool) -> Self {
+        self.persistent_identity = Some(value);
+        self
+    }
+
+    /// Delay waited after joining a channel of the network before sending the command,
+    /// instead of the [`EngineBuilder::request_delay`](crate::EngineBuilder::request_delay).
+    pub fn request_delay(mut self, value: Duration) -> Self {
+        self.request_delay = Some(value);
+        self
+    }
+
+    /// Minimum interval between two commands sent to the bots of the network, for the
+    /// networks banning the clients requesting too often.
+    ///
+    /// The requests ready sooner wait for their turn, in order.
+    pub fn request_interval(mut self, value: Duration) -> Self {
+        self.request_interval = Some(value);
+        self
+    }
+
+    /// Authenticates to the services of the network with the `PLAIN` SASL mechanism
+    /// during the registration, for the networks requiring a registered account.
+    ///
+    /// The registration fails with
+    /// [`ConnectError::Authentication`](crate::ConnectError::Authentication) if the
+    /// server refuses the credentials or doesn't support SASL.
+    pub fn sasl(mut self, account: impl Into<String>, password: impl Into<String>) -> Self {
+        self.sasl = Some(SaslCredentials {
+            account: account.into(),
+            password: password.into(),
+        });
+        self
+    }
+
+    /// Connects to the servers of the network over TLS, with the given connector
+    /// performing the handshake instead of the
+    /// [`EngineBuilder::connector`](crate::EngineBuilder::connector).
+    ///
+    /// The servers are connected to on the TLS port 6697, and not upgraded with
+    /// `STARTTLS`.
+    pub fn tls(mut self, connector: impl Connector + 'static) -> Self {
+        self.tls = Some(Arc::new(connector));
+        self
+    }
+}
+
+/// Account and password authenticating to the services of a network.
+#[derive(Clone)]
+pub(crate) struct SaslCredentials {
+    account: String,
+    password: String,
+}
+
+impl SaslCredentials {
+    /// Payload of the `PLAIN` mechanism, encoded in base64.
+    pub(crate) fn plain(&self) -> String {
+        let payload = format!("{0}\0{0}\0{1}", self.account, self.password);
+        base64(payload.as_bytes())
+    }
+}
+
+impl Debug for SaslCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct(stringify!(SaslCredentials))
+            .field("account", &self.account)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encodes the given bytes in padded base64, as defined by RFC 4648.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Network, base64};
+
+    #[test]
+    fn should_encode_base64() {
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(base64(input.as_bytes()), expected, "{input}");
+        }
+        let credentials = Network::new("irc.example.net")
+            .sasl("jilles", "sesame")
+            .sasl
+            .unwrap();
+        assert_eq!(credentials.plain(), "amlsbGVzAGppbGxlcwBzZXNhbWU=");
+        assert!(!format!("{credentials:?}").contains("sesame"));
+    }
+
+    #[test]
+    fn should_rotate_servers() {
+        let network = Network::new("irc1.example.net")
+            .with_server("irc2.example.net")
+            .with_server("irc3.example.net");
+        let first: Vec<&str> = network.rotation().collect();
+        assert_eq!(
+            first,
+            ["irc1.example.net", "irc2.example.net", "irc3.example.net"]
+        );
+        let second: Vec<&str> = network.rotation().collect();
+        assert_eq!(
+            second,
+            ["irc2.example.net", "irc3.example.net", "irc1.example.net"]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use crate::QueueStatus;
+use crate::parser;
+
+/// Meaning of a line of the bot while waiting for its offer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Notice {
+    /// The request is queued at the given position.
+    Queued(QueueStatus),
+    /// The bot asks to wait the given delay before requesting again.
+    Throttled(Duration),
+    /// The bot refuses the request.
+    #[cfg(feature = "regex")]
+    Refused,
+}
+
+/// Additional patterns classifying the notices of the bots, for the bots not speaking
+/// English, registered on the engine with
+/// [`EngineBuilder::notice_patterns`](crate::EngineBuilder::notice_patterns).
+///
+/// The patterns are matched against the lines of the bot without their formatting,
+/// before the built-in English detection.
+#[derive(Clone, Debug, Default)]
+pub struct NoticePatterns {
+    #[cfg(feature = "regex")]
+    errors: Vec<regex::Regex>,
+    #[cfg(feature = "regex")]
+    queues: Vec<regex::Regex>,
+    #[cfg(feature = "regex")]
+    throttles: Vec<regex::Regex>,
+}
+
+#[cfg(feature = "regex")]
+impl NoticePatterns {
+    /// Adds a pattern of the notices refusing the request, like an invalid pack number.
+    ///
+    /// The requests fail with [`OfferError::Refused`](crate::OfferError::Refused) on
+    /// these notices.
+    pub fn with_error(mut self, pattern: regex::Regex) -> Self {
+        self.errors.push(pattern);
+        self
+    }
+
+    /// Adds a pattern of the notices announcing the position in the queue, captured by
+    /// the `position` group, along with the optional `total` and `eta` groups.
+    ///
+    /// The `eta` is a duration like `1h30m`, or a number of seconds.
+    pub fn with_queue(mut self, pattern: regex::Regex) -> Self {
+        self.queues.push(pattern);
+        self
+    }
+
+    /// Adds a pattern of the notices asking to wait before requesting again, with the
+    /// delay captured by the `delay` group, as a duration like `1h30m`, or a number of
+    /// seconds.
+    pub fn with_throttle(mut self, pattern: regex::Regex) -> Self {
+        self.throttles.push(pattern);
+        self
+    }
+
+    /// Classifies the given line with the additional patterns only.
+    fn classify_custom(&self, line: &str) -> Option<Notice> {
+        let line = parser::strip_formatting(line);
+        if self.errors.iter().any(|pattern| pattern.is_match(&line)) {
+            return Some(Notice::Refused);
+        }
+        for captures in self
+            .queues
+            .iter()
+            .filter_map(|pattern| pattern.captures(&line))
+        {
+            let group = |name| captures.name(name).map(|group| group.as_str());
+            if let Some(position) = group("position").and_then(|value| value.parse().ok()) {
+                return Some(Notice::Queued(QueueStatus {
+                    position,
+                    total: group("total").and_then(|value| value.parse().ok()),
+                    eta: group("eta").and_then(parse_delay),
+                }));
+            }
+        }
+        self.throttles
+            .iter()
+            .filter_map(|pattern| pattern.captures(&line))
+            .find_map(|captures| parse_delay(captures.name("delay")?.as_str()))
+            .map(Notice::Throttled)
+    }
+}
+
+impl NoticePatterns {
+    /// Classifies the given line of the bot, with the additional patterns first.
+    pub(crate) fn classify(&self, line: &str) -> Option<Notice> {
+        #[cfg(feature = "regex")]
+        if let Some(notice) = self.classify_custom(line) {
+            return Some(notice);
+        }
+        if let Some(status) = QueueStatus::parse(line) {
+            Some(Notice::Queued(status))
+        } else {
+            parser::throttle_delay(line).map(Notice::Throttled)
+        }
+    }
+}
+
+/// Parses a delay like `1h30m`, or a number of seconds.
+#[cfg(feature = "regex")]
+fn parse_delay(text: &str) -> Option<Duration> {
+    match text.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => parser::parse_duration(text),
+    }
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod tests {
+    use std::time::Duration;
+
+    use regex::Regex;
+
+    use super::{Notice, NoticePatterns};
+    use crate::QueueStatus;
+
+    #[test]
+    fn should_classify_with_patterns() {
+        let patterns = NoticePatterns::default()
+            .with_queue(Regex::new(r"en position (?<position>\d+) sur (?<total>\d+)").unwrap())
+            .with_throttle(Regex::new(r"(?i)attendez (?<delay>\w+)").unwrap())
+            .with_error(Regex::new(r"(?i)pack invalide").unwrap());
+        assert_eq!(
+            patterns.classify("Vous êtes en position 2 sur 5"),
+            Some(Notice::Queued(QueueStatus {
+                position: 2,
+                total: Some(5),
+                eta: None,
+            }))
+        );
+        assert_eq!(
+            patterns.classify("\x02Attendez\x02 1m30s"),
+            Some(Notice::Throttled(Duration::from_secs(90)))
+        );
+        assert_eq!(
+            patterns.classify("Numéro de pack invalide"),
+            Some(Notice::Refused)
+        );
+        // the built-in detection still applies
+        assert_eq!(
+            patterns.classify("Please wait 30 seconds before requesting again"),
+            Some(Notice::Throttled(Duration::from_secs(30)))
+        );
+        assert_eq!(patterns.classify("Tous les slots sont pleins"), None);
+    }
+}
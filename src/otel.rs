@@ -0,0 +1,37 @@
+//! Export of the traces of the requests to OpenTelemetry, through
+//! `tracing-opentelemetry`, when the `opentelemetry` feature is enabled.
+//!
+//! Each request becomes a trace, with the spans of the connection, the registration,
+//! the join and the wait for the offer, as well as the downloads with the `transfer`
+//! feature. The spans of the failed requests and downloads have the error status.
+
+use tracing::Subscriber;
+use tracing_subscriber::Layer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Layer exporting the spans of this crate with the given tracer, like the one of a
+/// tracer provider with an OTLP exporter.
+///
+/// The spans of the other crates are left to the other layers of the application.
+///
+/// ```no_run
+/// # fn run(tracer: opentelemetry::global::BoxedTracer) {
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let subscriber = tracing_subscriber::registry().with(xdcc_request::otel::layer(tracer));
+/// tracing::subscriber::set_global_default(subscriber).unwrap();
+/// # }
+/// ```
+pub fn layer<S, T>(tracer: T) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    T: opentelemetry::trace::Tracer + Send + Sync + 'static,
+    T::Span: Send + Sync,
+{
+    let own_spans = tracing_subscriber::filter::filter_fn(|metadata| {
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+    });
+    tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(own_spans)
+}
@@ -0,0 +1,894 @@
+//! Protocol knowledge, without any async or networking dependency.
+//!
+//! Log analyzers and bots written on other stacks can decode the XDCC messages
+//! by disabling the default `engine` feature. The module only requires `alloc` when
+//! the `std` feature is disabled as well.
+
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, SocketAddr};
+use core::ops::RangeInclusive;
+use core::time::Duration;
+
+/// Represents a parsed DCC SEND response from the IRC bot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    /// The name of the file being sent.
+    pub filename: String,
+    /// IP address of the sender.
+    pub address: IpAddr,
+    /// Port number used for the DCC transfer.
+    pub port: u16,
+    /// Size of the file in bytes.
+    pub filesize: u64,
+    /// Full prefix of the user who sent the offer, like `nick!user@host`, when known.
+    ///
+    /// Only the offers received by the engine have a sender, as it's not part of
+    /// the `DCC SEND` command message.
+    pub sender: Option<String>,
+    /// Token of a passive offer, made with a zero port by a sender unable to accept
+    /// connections, that expects the receiver to listen and answer with its own
+    /// address and this token.
+    pub token: Option<String>,
+}
+
+impl Response {
+    /// Address of the endpoint to connect to for the transfer.
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    /// Whether the offer is passive, the sender expecting the receiver to listen.
+    pub fn is_passive(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Encodes the response into its canonical `DCC SEND` command message.
+    ///
+    /// The filename is always quoted, escaping the quotes and backslashes it contains.
+    /// IPv4 addresses are written as integers, and IPv6 addresses in their textual form.
+    /// The token of a passive offer follows the file size.
+    pub fn encode(&self) -> String {
+        let mut filename = String::with_capacity(self.filename.len());
+        for c in self.filename.chars() {
+            if matches!(c, '"' | '\\') {
+                filename.push('\\');
+            }
+            filename.push(c);
+        }
+        let address = match self.address {
+            IpAddr::V4(address) => u32::from(address).to_string(),
+            IpAddr::V6(address) => address.to_string(),
+        };
+        let mut msg = format!(
+            "DCC SEND \"{filename}\" {address} {} {}",
+            self.port, self.filesize
+        );
+        if let Some(ref token) = self.token {
+            msg.push(' ');
+            msg.push_str(token);
+        }
+        msg
+    }
+
+    /// Encodes the `DCC REJECT` message declining the offer, sent back to the bot as a
+    /// CTCP reply so it frees the slot right away.
+    pub fn reject(&self) -> String {
+        format!("DCC REJECT SEND {}", self.ctcp_filename())
+    }
+
+    /// Encodes the `DCC RESUME` message asking the sender to resume the transfer at the
+    /// given position, which the sender confirms with a `DCC ACCEPT` message.
+    pub fn resume(&self, position: u64) -> String {
+        let mut msg = format!(
+            "DCC RESUME {} {} {position}",
+            self.ctcp_filename(),
+            self.port
+        );
+        if let Some(ref token) = self.token {
+            msg.push(' ');
+            msg.push_str(token);
+        }
+        msg
+    }
+
+    /// Filename as written in the CTCP replies, quoted only when it contains spaces.
+    fn ctcp_filename(&self) -> String {
+        let filename = &self.filename;
+        if filename.contains(' ') {
+            format!("\"{filename}\"")
+        } else {
+            filename.clone()
+        }
+    }
+
+    /// Decodes a `DCC SEND` command message into a `Response`, including the passive
+    /// offers ending with a token after a zero port.
+    ///
+    /// Returns `Some(Response)` if decoding is successful, or `None` if parsing fails.
+    pub fn decode(msg: &str) -> Option<Self> {
+        let msg = msg.trim().strip_prefix("DCC SEND ")?;
+
+        if let Some((rest, token)) = msg.rsplit_once(' ')
+            && let Some(response) = Self::decode_endpoint(rest, Some(token))
+            && response.port == 0
+        {
+            return Some(response);
+        }
+        Self::decode_endpoint(msg, None)
+    }
+
+    /// Decodes the filename, address, port and size of a `DCC SEND` command message.
+    fn decode_endpoint(msg: &str, token: Option<&str>) -> Option<Self> {
+        let (msg, filesize) = msg.rsplit_once(" ")?;
+        let filesize = filesize.parse::<u64>().ok()?;
+
+        let (msg, port) = msg.rsplit_once(" ")?;
+        let port = port.parse::<u16>().ok()?;
+
+        let (msg, ip) = msg.rsplit_once(" ")?;
+        let address = match ip.parse::<u32>() {
+            Ok(ip) => IpAddr::V4(Ipv4Addr::from(ip)),
+            Err(_) => ip.parse::<IpAddr>().ok()?,
+        };
+
+        let filename = match msg.strip_prefix('"').and_then(|msg| msg.strip_suffix('"')) {
+            Some(quoted) => unescape(quoted),
+            None => msg.to_owned(),
+        };
+        if filename.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            filename,
+            address,
+            port,
+            filesize,
+            sender: None,
+            token: token.map(ToOwned::to_owned),
+        })
+    }
+}
+
+/// Confirmation of the sender to resume a transfer, answering a `DCC RESUME` message
+/// encoded with [`Response::resume`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeAccept {
+    /// Name of the file, as written by the sender.
+    pub filename: String,
+    /// Port of the offer of the transfer, zero for a passive offer.
+    pub port: u16,
+    /// Position the transfer resumes at.
+    pub position: u64,
+}
+
+impl ResumeAccept {
+    /// Decodes a `DCC ACCEPT` message, with or without its CTCP delimiters, and with
+    /// the trailing token of a passive offer.
+    ///
+    /// Returns `None` if the message is not a valid `DCC ACCEPT` message.
+    pub fn decode(msg: &str) -> Option<Self> {
+        let msg = msg
+            .trim()
+            .trim_matches('\x01')
+            .strip_prefix("DCC ACCEPT ")?;
+        let (rest, last) = msg.rsplit_once(' ')?;
+        let (filename, port, position) = match rest.rsplit_once(' ') {
+            // the passive offers are resumed on port zero, followed by their token
+            Some((before, position)) if before.ends_with(" 0") => {
+                (before.strip_suffix(" 0")?, "0", position)
+            }
+            _ => {
+                let (filename, port) = rest.rsplit_once(' ')?;
+                (filename, port, last)
+            }
+        };
+        let filename = match filename
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+        {
+            Some(quoted) => unescape(quoted),
+            None => filename.to_owned(),
+        };
+        Some(Self {
+            filename,
+            port: port.parse().ok()?,
+            position: position.parse().ok()?,
+        })
+    }
+}
+
+/// Removes the backslashes escaping the quotes and backslashes of a quoted filename.
+fn unescape(quoted: &str) -> String {
+    let mut filename = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next @ ('"' | '\\')) => filename.push(next),
+                Some(next) => {
+                    filename.push(c);
+                    filename.push(next);
+                }
+                None => filename.push(c),
+            },
+            c => filename.push(c),
+        }
+    }
+    filename
+}
+
+/// Outcome of a dry-run of a request, returned by [`Request::validate`](crate::Request::validate).
+#[derive(Clone, Debug)]
+pub struct Validation {
+    /// Whether the pack appears to exist and be requestable.
+    pub exists: bool,
+    /// Lines of the reply of the bot to the `xdcc info` command.
+    pub info: Vec<String>,
+}
+
+impl Validation {
+    /// Interprets the reply of the bot to the `xdcc info` command.
+    ///
+    /// The bots answer with the details of the pack, or with a message like
+    /// `Invalid Pack Number, Try Again` when it doesn't exist.
+    pub fn from_reply(info: Vec<String>) -> Self {
+        let exists = !info.is_empty()
+            && !info
+                .iter()
+                .any(|line| line.to_ascii_lowercase().contains("invalid pack"));
+        Self { exists, info }
+    }
+}
+
+/// Position of a request in the queue of a bot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueStatus {
+    /// Position in the queue, starting at 1.
+    pub position: u32,
+    /// Number of requests in the queue, when announced.
+    pub total: Option<u32>,
+    /// Estimated time before the transfer starts, when announced.
+    pub eta: Option<Duration>,
+}
+
+impl QueueStatus {
+    /// Parses a queue notice of the bot, like
+    /// `Queued 0h12m for "file.mkv", in position 2 of 5. 1h30m or more remaining.`
+    /// or `Added you to the main queue for pack 1 ("file.mkv") in position 2.`
+    ///
+    /// Returns `None` if the message doesn't announce a position.
+    pub fn parse(msg: &str) -> Option<Self> {
+        let lower = msg.to_ascii_lowercase();
+        let (_, rest) = lower.split_once("position ")?;
+        let (position, rest) = leading_number(rest)?;
+        let total = rest
+            .trim_start()
+            .strip_prefix("of ")
+            .and_then(leading_number)
+            .map(|(total, _)| total);
+        let eta = lower
+            .split_once(" remaining")
+            .and_then(|(before, _)| before.trim_end_matches(" or more").rsplit(' ').next())
+            .and_then(parse_duration);
+        Some(Self {
+            position,
+            total,
+            eta,
+        })
+    }
+}
+
+/// Splits the number at the start of the given text.
+fn leading_number(text: &str) -> Option<(u32, &str)> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+/// Parses a duration like `1h30m`, `12m` or `45s`.
+pub(crate) fn parse_duration(text: &str) -> Option<Duration> {
+    let mut seconds = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (value, after) = leading_number(rest)?;
+        let mut chars = after.chars();
+        let unit = match chars.next()? {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        seconds += u64::from(value) * unit;
+        rest = chars.as_str();
+    }
+    (!text.is_empty()).then(|| Duration::from_secs(seconds))
+}
+
+/// Parses the delay asked by a throttle notice of the bot, like
+/// `Please wait 30 seconds before requesting again`.
+///
+/// Returns `None` if the message doesn't ask to wait, including the queue notices.
+pub fn throttle_delay(msg: &str) -> Option<Duration> {
+    let msg = strip_formatting(msg).to_ascii_lowercase();
+    if msg.contains("position ") {
+        return None;
+    }
+    // the delay follows the request to wait, other durations may precede it
+    let start = msg.find("wait").or_else(|| msg.find("try again"))?;
+    clause_duration(&msg[start..])
+}
+
+/// Rules of a channel detected in its topic and entry notices.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelRules {
+    /// Delay to wait after joining the channel before requesting a pack.
+    pub request_delay: Option<Duration>,
+    /// Maximum number of downloads per user.
+    pub max_downloads: Option<u32>,
+}
+
+impl ChannelRules {
+    /// Detects the rules stated in the given topic or notice, like
+    /// `no requests for 60s after join` or `max 2 downloads`.
+    ///
+    /// The detection is a best effort, the rules not understood are ignored.
+    pub fn parse(text: &str) -> Self {
+        let text = strip_formatting(text).to_ascii_lowercase();
+        let mut rules = Self::default();
+        for clause in text.split(['.', '!', '|', ';', ',']) {
+            if clause.contains("join") || clause.contains("wait") {
+                rules = rules.merge(Self {
+                    request_delay: clause_duration(clause),
+                    max_downloads: None,
+                });
+            }
+            if clause.contains("max")
+                && ["download", "dl", "slot", "get", "send"]
+                    .iter()
+                    .any(|word| clause.contains(word))
+            {
+                rules = rules.merge(Self {
+                    request_delay: None,
+                    max_downloads: clause.split_whitespace().find_map(|word| word.parse().ok()),
+                });
+            }
+        }
+        rules
+    }
+
+    /// Combines the rules detected in several messages, keeping the strictest ones.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            request_delay: self.request_delay.max(other.request_delay),
+            max_downloads: match (self.max_downloads, other.max_downloads) {
+                (Some(first), Some(second)) => Some(first.min(second)),
+                (first, second) => first.or(second),
+            },
+        }
+    }
+
+    /// Whether no rule has been detected.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Finds the first duration of a clause, like `60s`, `1h30m` or `2 minutes`.
+fn clause_duration(clause: &str) -> Option<Duration> {
+    let mut words = clause
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_ascii_alphanumeric()));
+    while let Some(word) = words.next() {
+        if let Some(duration) = parse_duration(word) {
+            return Some(duration);
+        }
+        let Some((value, "")) = leading_number(word) else {
+            continue;
+        };
+        let unit = match words.clone().next()? {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+            _ => continue,
+        };
+        return Some(Duration::from_secs(u64::from(value) * unit));
+    }
+    None
+}
+
+/// Pack advertised by a bot in a channel or in its listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackAnnouncement {
+    /// XDCC pack number.
+    pub packnum: u64,
+    /// Number of times the pack has been downloaded.
+    pub gets: u64,
+    /// Approximate size of the file, in bytes.
+    pub size: u64,
+    /// Name of the file.
+    pub filename: String,
+}
+
+impl PackAnnouncement {
+    /// Parses an announce line, like `#12 99x [1.4G] Some.File.mkv`.
+    ///
+    /// The IRC formatting codes are ignored. The sizes like `<1K` are rounded up.
+    /// Returns `None` if the line doesn't advertise a pack.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = strip_formatting(line);
+        let rest = line.trim_start().strip_prefix('#')?;
+        let (packnum, rest) = rest.split_once(char::is_whitespace)?;
+        let (gets, rest) = rest.trim_start().split_once(char::is_whitespace)?;
+        let (size, filename) = rest.trim_start().strip_prefix('[')?.split_once(']')?;
+        let filename = filename.trim();
+        if filename.is_empty() {
+            return None;
+        }
+        Some(Self {
+            packnum: packnum.parse().ok()?,
+            gets: gets.strip_suffix(['x', 'X'])?.parse().ok()?,
+            size: parse_size(size.trim())?,
+            filename: filename.to_owned(),
+        })
+    }
+}
+
+/// Removes the bold, color, italic, reverse, underline and reset codes of an IRC message.
+pub(crate) fn strip_formatting(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x02' | '\x0f' | '\x16' | '\x1d' | '\x1f' => {}
+            '\x03' => {
+                // foreground and optional background, of up to two digits each
+                for _ in 0..2 {
+                    chars.next_if(char::is_ascii_digit);
+                }
+                if chars.peek() == Some(&',') {
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    if lookahead.peek().is_some_and(char::is_ascii_digit) {
+                        chars.next();
+                        for _ in 0..2 {
+                            chars.next_if(char::is_ascii_digit);
+                        }
+                    }
+                }
+            }
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Parses a size like `1.4G`, `700M` or `<1K`, in bytes.
+fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim_start_matches('<');
+    let (number, shift) = match text.as_bytes().last()?.to_ascii_uppercase() {
+        b'B' => (&text[..text.len() - 1], 0),
+        b'K' => (&text[..text.len() - 1], 10),
+        b'M' => (&text[..text.len() - 1], 20),
+        b'G' => (&text[..text.len() - 1], 30),
+        b'T' => (&text[..text.len() - 1], 40),
+        _ => (text, 0),
+    };
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let unit = 1u64 << shift;
+    let integer = integer.parse::<u64>().ok()?.checked_mul(unit)?;
+    if fraction.is_empty() {
+        return Some(integer);
+    }
+    if fraction.len() > 3 || !fraction.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let scale = 10u64.pow(fraction.len() as u32);
+    let fraction = fraction.parse::<u64>().ok()? * unit / scale;
+    integer.checked_add(fraction)
+}
+
+/// Identifier of the pack requested from a bot.
+///
+/// Most bots number their packs, while some address them by name or keyword.
+/// It's displayed the way the commands address it, like `#42` or `ubuntu.iso`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PackId {
+    /// Pack number, like `#42`.
+    Number(u64),
+    /// Name or keyword of the pack.
+    Name(String),
+}
+
+impl PackId {
+    /// Number of the pack, if addressed by number.
+    pub fn number(&self) -> Option<u64> {
+        match self {
+            Self::Number(value) => Some(*value),
+            Self::Name(_) => None,
+        }
+    }
+}
+
+impl From<u64> for PackId {
+    fn from(value: u64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<String> for PackId {
+    fn from(value: String) -> Self {
+        Self::Name(value)
+    }
+}
+
+impl From<&str> for PackId {
+    fn from(value: &str) -> Self {
+        Self::Name(value.to_owned())
+    }
+}
+
+impl core::fmt::Display for PackId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Number(value) => write!(f, "#{value}"),
+            Self::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Selection of the packs of a bot.
+///
+/// The selectors other than numbers are resolved against the listing of the bot,
+/// with [`PackSelector::select`], or when creating the requests with
+/// `Engine::create_selected_requests`.
+#[derive(Clone, Debug)]
+pub enum PackSelector {
+    /// Single pack number.
+    Number(u64),
+    /// Inclusive range of pack numbers.
+    Range(RangeInclusive<u64>),
+    /// Glob pattern matching the filenames, where `*` matches any sequence of characters
+    /// and `?` any single character, ignoring the ASCII case.
+    Glob(String),
+    /// Regular expression matching the filenames.
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+impl From<u64> for PackSelector {
+    fn from(value: u64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<RangeInclusive<u64>> for PackSelector {
+    fn from(value: RangeInclusive<u64>) -> Self {
+        Self::Range(value)
+    }
+}
+
+#[cfg(feature = "regex")]
+impl From<regex::Regex> for PackSelector {
+    fn from(value: regex::Regex) -> Self {
+        Self::Regex(value)
+    }
+}
+
+impl PackSelector {
+    /// Pack numbers selected without any listing, `None` for the filename patterns.
+    pub fn packnums(&self) -> Option<RangeInclusive<u64>> {
+        match self {
+            Self::Number(packnum) => Some(*packnum..=*packnum),
+            Self::Range(range) => Some(range.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether the given pack of the listing is selected.
+    pub fn matches(&self, packnum: u64, filename: &str) -> bool {
+        match self {
+            Self::Number(value) => *value == packnum,
+            Self::Range(range) => range.contains(&packnum),
+            Self::Glob(pattern) => glob_matches(pattern, filename),
+            #[cfg(feature = "regex")]
+            Self::Regex(regex) => regex.is_match(filename),
+        }
+    }
+
+    /// Selects the pack numbers of the given listing, made of pack numbers and filenames.
+    pub fn select<'a>(&self, listing: impl IntoIterator<Item = (u64, &'a str)>) -> Vec<u64> {
+        listing
+            .into_iter()
+            .filter(|(packnum, filename)| self.matches(*packnum, filename))
+            .map(|(packnum, _)| packnum)
+            .collect()
+    }
+}
+
+/// Matches the given glob pattern character by character, backtracking to the last `*`
+/// on mismatch.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut star = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, v));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                v += 1;
+            }
+            Some(c) if c.eq_ignore_ascii_case(&value[v]) => {
+                p += 1;
+                v += 1;
+            }
+            _ => match star {
+                Some((star_p, star_v)) => {
+                    p = star_p + 1;
+                    v = star_v + 1;
+                    star = Some((star_p, star_v + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::{
+        ChannelRules, PackAnnouncement, PackId, PackSelector, QueueStatus, Validation,
+        throttle_delay,
+    };
+
+    #[test_case::test_case("DCC SEND \"foo.txt\" 3232235777 5000 1048576", "foo.txt", 5000, 1048576; "simple")]
+    #[test_case::test_case("DCC SEND \"hello\\\"world.txt\" 3232235777 5000 1048576", "hello\"world.txt", 5000, 1048576; "with quotes")]
+    #[test_case::test_case("DCC SEND \"foo bar baz.txt\" 3232235777 5000 1048576", "foo bar baz.txt", 5000, 1048576; "filename with spaces")]
+    fn should_decode_dcc_msg(msg: &str, fname: &str, port: u16, size: u64) {
+        let res = super::Response::decode(msg).unwrap();
+        assert_eq!(res.filename, fname);
+        assert_eq!(res.port, port);
+        assert_eq!(res.filesize, size);
+    }
+
+    #[test_case::test_case("DCC SEND \"\" 3232235777 5000 1048576"; "empty filename")]
+    #[test_case::test_case("DCC SEND 3232235777 5000 1048576"; "missing filename")]
+    #[test_case::test_case("DCC SEND \"foo.txt\" 3232235777 70000 1048576"; "invalid port")]
+    fn should_reject_invalid_dcc_msg(msg: &str) {
+        assert!(super::Response::decode(msg).is_none());
+    }
+
+    #[test_case::test_case("DCC SEND \"foo bar.txt\" 3232235777 0 1048576 42", "foo bar.txt", Some("42"); "passive")]
+    #[test_case::test_case("DCC SEND foo.txt 3232235777 0 1048576", "foo.txt", None; "zero port")]
+    #[test_case::test_case("DCC SEND foo 0 0 1048576", "foo", None; "zero address")]
+    fn should_decode_passive_dcc_msg(msg: &str, filename: &str, token: Option<&str>) {
+        let res = super::Response::decode(msg).unwrap();
+        assert_eq!(res.filename, filename);
+        assert_eq!(res.port, 0);
+        assert_eq!(res.filesize, 1048576);
+        assert_eq!(res.token.as_deref(), token);
+        assert_eq!(res.is_passive(), token.is_some());
+        if token.is_some() {
+            assert_eq!(res.encode(), msg);
+        }
+    }
+
+    #[test]
+    fn should_encode_dcc_msg() {
+        let response = super::Response {
+            filename: "hello\"world.txt".into(),
+            address: std::net::Ipv4Addr::new(192, 168, 1, 1).into(),
+            port: 5000,
+            filesize: 1048576,
+            sender: None,
+            token: None,
+        };
+        assert_eq!(
+            response.encode(),
+            "DCC SEND \"hello\\\"world.txt\" 3232235777 5000 1048576"
+        );
+    }
+
+    #[test_case::test_case("ubuntu.iso", "DCC REJECT SEND ubuntu.iso"; "simple")]
+    #[test_case::test_case("foo bar.txt", "DCC REJECT SEND \"foo bar.txt\""; "filename with spaces")]
+    fn should_encode_reject_msg(filename: &str, expected: &str) {
+        let response = super::Response {
+            filename: filename.into(),
+            address: std::net::Ipv4Addr::new(192, 168, 1, 1).into(),
+            port: 5000,
+            filesize: 1048576,
+            sender: None,
+            token: None,
+        };
+        assert_eq!(response.reject(), expected);
+    }
+
+    #[test]
+    fn should_encode_resume_msg() {
+        let mut response = super::Response {
+            filename: "foo bar.txt".into(),
+            address: std::net::Ipv4Addr::new(192, 168, 1, 1).into(),
+            port: 5000,
+            filesize: 1048576,
+            sender: None,
+            token: None,
+        };
+        assert_eq!(
+            response.resume(1024),
+            "DCC RESUME \"foo bar.txt\" 5000 1024"
+        );
+        response.port = 0;
+        response.token = Some("42".into());
+        assert_eq!(
+            response.resume(1024),
+            "DCC RESUME \"foo bar.txt\" 0 1024 42"
+        );
+    }
+
+    #[test_case::test_case("\x01DCC ACCEPT \"foo bar.txt\" 5000 1024\x01", "foo bar.txt", 5000; "quoted")]
+    #[test_case::test_case("DCC ACCEPT file.ext 5000 1024", "file.ext", 5000; "placeholder")]
+    #[test_case::test_case("DCC ACCEPT foo.txt 0 1024 42", "foo.txt", 0; "passive")]
+    fn should_decode_accept_msg(msg: &str, filename: &str, port: u16) {
+        assert_eq!(
+            super::ResumeAccept::decode(msg),
+            Some(super::ResumeAccept {
+                filename: filename.into(),
+                port,
+                position: 1024,
+            })
+        );
+    }
+
+    #[test_case::test_case("DCC ACCEPT foo.txt 5000"; "missing position")]
+    #[test_case::test_case("DCC SEND foo.txt 3232235777 5000 1024"; "offer")]
+    fn should_reject_invalid_accept_msg(msg: &str) {
+        assert_eq!(super::ResumeAccept::decode(msg), None);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn should_decode_encoded_dcc_msg(
+            filename in "[^\\x00-\\x1f\\x7f]{1,64}",
+            address: std::net::IpAddr,
+            port: u16,
+            filesize: u64,
+        ) {
+            let response = super::Response {
+                filename,
+                address,
+                port,
+                filesize,
+                sender: None,
+                token: None,
+            };
+            let decoded = super::Response::decode(&response.encode()).unwrap();
+            proptest::prop_assert_eq!(decoded, response);
+        }
+    }
+
+    #[test]
+    fn should_detect_invalid_pack() {
+        let validation = Validation::from_reply(vec!["** Invalid Pack Number, Try Again".into()]);
+        assert!(!validation.exists);
+        let validation = Validation::from_reply(vec!["Pack Info for Pack #1:".into()]);
+        assert!(validation.exists);
+    }
+
+    #[test_case::test_case("*.mkv", "Show.S01E01.MKV" => true; "extension")]
+    #[test_case::test_case("show.s01e0?.*", "Show.S01E02.mkv" => true; "single character")]
+    #[test_case::test_case("*s01*1080p*", "Show.S01E01.720p.mkv" => false; "missing part")]
+    #[test_case::test_case("show", "Show.S01E01.mkv" => false; "prefix only")]
+    #[test_case::test_case("caf?.mkv", "café.mkv" => true; "non-ascii character")]
+    fn should_match_glob(pattern: &str, filename: &str) -> bool {
+        PackSelector::Glob(pattern.into()).matches(1, filename)
+    }
+
+    #[test]
+    fn should_display_pack_id() {
+        assert_eq!(PackId::from(42).to_string(), "#42");
+        assert_eq!(PackId::from("ubuntu").to_string(), "ubuntu");
+        assert_eq!(PackId::from(42).number(), Some(42));
+        assert_eq!(PackId::from("ubuntu").number(), None);
+    }
+
+    #[test]
+    fn should_select_packs_from_listing() {
+        let listing = [(1, "a.mkv"), (2, "b.txt"), (3, "c.mkv")];
+        assert_eq!(PackSelector::from(2..=3).select(listing), [2, 3]);
+        assert_eq!(PackSelector::Glob("*.mkv".into()).select(listing), [1, 3]);
+        assert_eq!(PackSelector::from(2).packnums(), Some(2..=2));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn should_select_packs_by_regex() {
+        let selector = PackSelector::from(regex::Regex::new(r"E0[12]\.mkv$").unwrap());
+        let listing = [(1, "S01E01.mkv"), (2, "S01E02.mkv"), (3, "S01E03.mkv")];
+        assert_eq!(selector.select(listing), [1, 2]);
+    }
+
+    #[test]
+    fn should_parse_queue_status() {
+        assert_eq!(
+            QueueStatus::parse(
+                "Queued 0h12m for \"file.mkv\", in position 2 of 5. 1h30m or more remaining."
+            ),
+            Some(QueueStatus {
+                position: 2,
+                total: Some(5),
+                eta: Some(Duration::from_secs(5400)),
+            })
+        );
+        assert_eq!(
+            QueueStatus::parse(
+                "** All Slots Full, Added you to the main queue for pack 1 (\"file.mkv\") in position 3."
+            ),
+            Some(QueueStatus {
+                position: 3,
+                total: None,
+                eta: None,
+            })
+        );
+        assert_eq!(QueueStatus::parse("** Sending you pack #1"), None);
+    }
+
+    #[test_case::test_case("#12 99x [1.4G] Some.File.mkv", 12, 99, 1503238553, "Some.File.mkv"; "gigabytes")]
+    #[test_case::test_case("#1   3x [700M] ubuntu.iso", 1, 3, 734003200, "ubuntu.iso"; "aligned")]
+    #[test_case::test_case("#7 0x [<1K] notes.txt", 7, 0, 1024, "notes.txt"; "below kilobyte")]
+    #[test_case::test_case("\x0304#5\x03 \x02 10x\x02 [\x0312,01 12K\x03] file name.zip", 5, 10, 12288, "file name.zip"; "colors")]
+    fn should_parse_announcement(line: &str, packnum: u64, gets: u64, size: u64, filename: &str) {
+        assert_eq!(
+            PackAnnouncement::parse(line),
+            Some(PackAnnouncement {
+                packnum,
+                gets,
+                size,
+                filename: filename.into(),
+            })
+        );
+    }
+
+    #[test_case::test_case("Total offered: 1.4G"; "total")]
+    #[test_case::test_case("#12 99 [1.4G] file.mkv"; "no gets")]
+    #[test_case::test_case("#12 99x [1.4Q] file.mkv"; "unit")]
+    #[test_case::test_case("#12 99x [1.4G]"; "no filename")]
+    #[test_case::test_case("#12 99x [16777216T] file.mkv"; "size overflow")]
+    fn should_reject_announcement(line: &str) {
+        assert_eq!(PackAnnouncement::parse(line), None);
+    }
+
+    #[test_case::test_case("Welcome! No requests for 60s after join | max 2 downloads", Some(60), Some(2); "topic")]
+    #[test_case::test_case("\x02Please wait 2 minutes before requesting\x02", Some(120), None; "notice")]
+    #[test_case::test_case("Max. 3 dl per user, max 1 slot per bot", None, Some(1); "strictest")]
+    #[test_case::test_case("Welcome to #channel, enjoy your stay", None, None; "no rules")]
+    fn should_parse_channel_rules(text: &str, delay: Option<u64>, max_downloads: Option<u32>) {
+        assert_eq!(
+            ChannelRules::parse(text),
+            ChannelRules {
+                request_delay: delay.map(Duration::from_secs),
+                max_downloads,
+            }
+        );
+    }
+
+    #[test_case::test_case("** Please wait 30 seconds before requesting again", Some(30); "seconds")]
+    #[test_case::test_case("You can only request once per 2 minutes, try again in 90s", Some(90); "try again")]
+    #[test_case::test_case("Queued 0h1m, in position 2 of 3. 0h5m or more remaining. Please wait.", None; "queue")]
+    #[test_case::test_case("** Sending you pack #1", None; "offer")]
+    fn should_parse_throttle_delay(msg: &str, delay: Option<u64>) {
+        assert_eq!(throttle_delay(msg), delay.map(Duration::from_secs));
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Keeps idle connections ready to be used, indexed by server.
+#[derive(Debug)]
+pub(crate) struct Pool<T> {
+    /// Idle connections with the date they were put in the pool.
+    entries: Mutex<HashMap<String, Vec<(Instant, T)>>>,
+    /// Duration a connection can stay idle before being discarded.
+    ttl: Duration,
+}
+
+impl<T> Pool<T> {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Default::default(),
+            ttl,
+        }
+    }
+
+    /// Puts an idle connection to the given server in the pool.
+    pub(crate) fn put(&self, server: &str, item: T) {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(server.to_owned())
+            .or_default()
+            .push((Instant::now(), item));
+    }
+
+    /// Takes the most recent idle connection to the given server, discarding the expired ones.
+    pub(crate) fn take(&self, server: &str) -> Option<T> {
+        let mut lock = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let items = lock.get_mut(server)?;
+        items.retain(|(created_at, _)| created_at.elapsed() < self.ttl);
+        let found = items.pop().map(|(_, item)| item);
+        if items.is_empty() {
+            lock.remove(server);
+        }
+        found
+    }
+
+    /// Takes all the idle connections, including the expired ones.
+    pub(crate) fn drain(&self) -> Vec<T> {
+        let mut lock = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        lock.drain()
+            .flat_map(|(_, items)| items)
+            .map(|(_, item)| item)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Pool;
+
+    #[test]
+    fn should_take_idle_item() {
+        let pool = Pool::new(Duration::from_secs(60));
+        pool.put("irc.example.net", 1);
+        pool.put("irc.example.net", 2);
+        assert_eq!(pool.take("irc.example.net"), Some(2));
+        assert_eq!(pool.take("irc.example.net"), Some(1));
+        assert_eq!(pool.take("irc.example.net"), None);
+        assert_eq!(pool.take("irc.other.net"), None);
+    }
+
+    #[test]
+    fn should_drain_all_items() {
+        let pool = Pool::new(Duration::ZERO);
+        pool.put("irc.example.net", 1);
+        pool.put("irc.other.net", 2);
+        let mut items = pool.drain();
+        items.sort();
+        assert_eq!(items, [1, 2]);
+        assert_eq!(pool.drain(), []);
+    }
+
+    #[test]
+    fn should_discard_expired_item() {
+        let pool = Pool::new(Duration::ZERO);
+        pool.put("irc.example.net", 1);
+        assert_eq!(pool.take("irc.example.net"), None);
+    }
+}
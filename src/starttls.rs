@@ -0,0 +1,136 @@
+//! In-band TLS upgrade of the plaintext IRC connections, with the `STARTTLS` command.
+
+use std::fmt::Debug;
+use std::io;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{ConnectFuture, Transport};
+
+/// Numeric reply accepting the upgrade.
+const RPL_STARTTLS: &str = "670";
+
+/// Numeric replies refusing the upgrade, when it failed, isn't supported, or must
+/// happen before the registration.
+const REFUSALS: [&str; 3] = ["691", "421", "451"];
+
+/// Maximum length of an IRC line, including its tags.
+const MAX_LINE_LENGTH: usize = 8703;
+
+/// Performs the TLS handshake of the connections upgraded with the `STARTTLS` command.
+///
+/// Implementing this trait allows using any TLS stack, like `rustls` or `native-tls`.
+pub trait TlsUpgrader: Debug + Send + Sync {
+    /// Performs the TLS handshake with the given server over the given plaintext stream.
+    fn upgrade<'a>(&'a self, host: &'a str, stream: Box<dyn Transport>) -> ConnectFuture<'a>;
+}
+
+/// Reads a line from the stream, one byte at a time so nothing following it is consumed.
+async fn read_line(stream: &mut Box<dyn Transport>) -> io::Result<String> {
+    let mut line = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            byte if line.len() < MAX_LINE_LENGTH => line.push(byte),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long")),
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_owned())
+}
+
+/// Command of the given IRC line, after its tags and prefix.
+fn command(line: &str) -> Option<&str> {
+    line.split_whitespace()
+        .find(|word| !word.starts_with('@') && !word.starts_with(':'))
+}
+
+/// Asks the server to upgrade the connection to TLS before the registration, and
+/// performs the handshake with the given upgrader when accepted.
+///
+/// Returns the stream with whether it was upgraded, the plaintext stream being returned
+/// when the server refuses the upgrade.
+pub(crate) async fn negotiate(
+    mut stream: Box<dyn Transport>,
+    host: &str,
+    upgrader: &dyn TlsUpgrader,
+) -> io::Result<(Box<dyn Transport>, bool)> {
+    stream.write_all(b"STARTTLS\r\n").await?;
+    loop {
+        let line = read_line(&mut stream).await?;
+        match command(&line) {
+            Some(RPL_STARTTLS) => return Ok((upgrader.upgrade(host, stream).await?, true)),
+            Some(code) if REFUSALS.contains(&code) => {
+                warn!("{host} refused the TLS upgrade, staying in plaintext: {line}");
+                return Ok((stream, false));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::{TlsUpgrader, command, negotiate};
+    use crate::{ConnectFuture, Transport};
+
+    /// Marks the upgraded streams by writing `TLS` on them.
+    #[derive(Debug)]
+    struct MarkingUpgrader;
+
+    impl TlsUpgrader for MarkingUpgrader {
+        fn upgrade<'a>(
+            &'a self,
+            _host: &'a str,
+            mut stream: Box<dyn Transport>,
+        ) -> ConnectFuture<'a> {
+            Box::pin(async move {
+                stream.write_all(b"TLS\r\n").await?;
+                Ok(stream)
+            })
+        }
+    }
+
+    async fn exchange(reply: &'static [u8]) -> (Vec<String>, bool) {
+        let (client, server) = tokio::io::duplex(1024);
+        let (reader, mut writer) = tokio::io::split(server);
+        writer.write_all(reply).await.unwrap();
+        let client = Box::new(client) as Box<dyn Transport>;
+        let (mut stream, upgraded) = negotiate(client, "irc.example.net", &MarkingUpgrader)
+            .await
+            .unwrap();
+        stream.write_all(b"NICK nickname\r\n").await.unwrap();
+        drop(stream);
+        let mut lines = Vec::new();
+        let mut reader = BufReader::new(reader).lines();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            lines.push(line);
+        }
+        (lines, upgraded)
+    }
+
+    #[tokio::test]
+    async fn should_upgrade_when_accepted() {
+        let (lines, upgraded) = exchange(
+            b":irc.example.net NOTICE * :Looking up your hostname\r\n:irc.example.net 670 * :STARTTLS successful, go ahead with TLS handshake\r\n",
+        )
+        .await;
+        assert_eq!(lines, ["STARTTLS", "TLS", "NICK nickname"]);
+        assert!(upgraded);
+    }
+
+    #[tokio::test]
+    async fn should_stay_in_plaintext_when_refused() {
+        let (lines, upgraded) =
+            exchange(b":irc.example.net 421 * STARTTLS :Unknown command\r\n").await;
+        assert_eq!(lines, ["STARTTLS", "NICK nickname"]);
+        assert!(!upgraded);
+    }
+
+    #[test]
+    fn should_find_command() {
+        assert_eq!(command("@time=now :irc.example.net 670 * :ok"), Some("670"));
+        assert_eq!(command("PING :irc.example.net"), Some("PING"));
+    }
+}
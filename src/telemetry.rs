@@ -0,0 +1,61 @@
+//! Instrumentation through the `metrics` facade, when the `metrics` feature is enabled,
+//! and of the outcome of the `tracing` spans, when the `tracing` feature is enabled.
+
+use std::time::Duration;
+
+use crate::Error;
+
+/// Records the duration and the outcome of a request.
+pub(crate) fn record_request<T>(duration: Duration, result: &Result<T, Error>) {
+    #[cfg(feature = "metrics")]
+    {
+        metrics::histogram!("xdcc_request_duration_seconds").record(duration.as_secs_f64());
+        if let Err(err) = result {
+            metrics::counter!("xdcc_request_failures_total", "kind" => err.kind().as_str())
+                .increment(1);
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (duration, result);
+}
+
+/// Marks the given span as failed with the error of the result, as the OpenTelemetry
+/// exporters expect it.
+///
+/// The span declares the empty `otel.status_code` and `otel.status_description` fields.
+#[cfg(feature = "tracing")]
+pub(crate) fn record_span_outcome<T>(span: &tracing::Span, result: &Result<T, Error>) {
+    if let Err(err) = result {
+        span.record("otel.status_code", "ERROR");
+        span.record("otel.status_description", tracing::field::display(err));
+    }
+}
+
+/// Records the bytes received by a transfer.
+#[cfg(feature = "transfer")]
+pub(crate) fn record_transferred(bytes: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("xdcc_bytes_transferred_total").increment(bytes as u64);
+    #[cfg(not(feature = "metrics"))]
+    let _ = bytes;
+}
+
+/// Records the outcome of a transfer.
+#[cfg(feature = "transfer")]
+pub(crate) fn record_transfer<T>(result: &Result<T, Error>) {
+    #[cfg(feature = "metrics")]
+    if let Err(err) = result {
+        metrics::counter!("xdcc_transfer_failures_total", "kind" => err.kind().as_str())
+            .increment(1);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = result;
+}
+
+/// Records the time between the XDCC command and the DCC offer.
+pub(crate) fn record_offer_wait(duration: Duration) {
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("xdcc_request_ttfb_dcc_offer_seconds").record(duration.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = duration;
+}
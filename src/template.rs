@@ -0,0 +1,382 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::{PackId, RequestInfo, Response};
+
+/// Error returned when a filename template cannot be parsed.
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    /// The placeholder is not one of the supported ones.
+    #[error("unknown placeholder {{{0}}}")]
+    UnknownPlaceholder(String),
+    /// A `{` is not closed by a `}`.
+    #[error("unclosed placeholder")]
+    Unclosed,
+}
+
+/// Value substituted in a template.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    Bot,
+    Channel,
+    Filename,
+    Id,
+    Packnum,
+    Server,
+}
+
+/// Part of a parsed template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Template of the path a received file is saved to, like `{bot}/{packnum}-{filename}`.
+///
+/// The supported placeholders are `{server}`, `{channel}`, `{bot}`, `{packnum}`, replaced
+/// by the name of the packs addressed by name, `{filename}` and `{id}`, and `{{` and `}}` produce literal braces. The slashes of the
+/// template separate directories, while the ones of the substituted values are replaced,
+/// so a bot cannot choose where its file lands.
+///
+/// ```
+/// use xdcc_request::FilenameTemplate;
+///
+/// let template: FilenameTemplate = "{bot}/{packnum}-{filename}".parse().unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilenameTemplate {
+    /// Maximum length of the rendered paths in UTF-16 code units, unlimited when `None`.
+    max_path_len: Option<usize>,
+    segments: Vec<Segment>,
+    /// Whether the substituted values follow the restrictions of Windows.
+    windows_safe: bool,
+}
+
+impl Default for FilenameTemplate {
+    /// Template keeping the offered filename.
+    fn default() -> Self {
+        Self {
+            max_path_len: None,
+            segments: vec![Segment::Placeholder(Placeholder::Filename)],
+            windows_safe: cfg!(windows),
+        }
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = TemplateError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = value.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::Unclosed),
+                        }
+                    }
+                    let placeholder = match name.as_str() {
+                        "bot" => Placeholder::Bot,
+                        "channel" => Placeholder::Channel,
+                        "filename" => Placeholder::Filename,
+                        "id" => Placeholder::Id,
+                        "packnum" => Placeholder::Packnum,
+                        "server" => Placeholder::Server,
+                        _ => return Err(TemplateError::UnknownPlaceholder(name)),
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self {
+            max_path_len: None,
+            segments,
+            windows_safe: cfg!(windows),
+        })
+    }
+}
+
+/// Names of the devices reserved by Windows, with or without extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "AUX", "CON", "NUL", "PRN", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Maximum length of a path component on Windows, in UTF-16 code units.
+const MAX_COMPONENT_LENGTH: usize = 255;
+
+/// Replaces the characters of a value that would change the directory of the file.
+///
+/// When `windows_safe` is set, the characters forbidden by Windows are replaced too,
+/// the trailing dots and spaces are removed, the reserved device names are prefixed
+/// with `_`, and the value is shortened to fit a path component, keeping its extension.
+fn sanitize(value: &str, windows_safe: bool) -> String {
+    if value.is_empty() || value.chars().all(|c| c == '.') {
+        return "_".into();
+    }
+    if !windows_safe {
+        return value.replace(['/', '\\'], "_");
+    }
+
+    let mut value: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    value.truncate(value.trim_end_matches(['.', ' ']).len());
+    if value.is_empty() {
+        return "_".into();
+    }
+    let stem = value.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem))
+    {
+        value.insert(0, '_');
+    }
+    shorten(&value, MAX_COMPONENT_LENGTH)
+}
+
+/// Shortens the given name to the given number of UTF-16 code units, keeping its extension.
+fn shorten(name: &str, max: usize) -> String {
+    if name.encode_utf16().count() <= max {
+        return name.to_owned();
+    }
+    let extension = name
+        .rfind('.')
+        .map(|index| &name[index..])
+        .filter(|extension| extension.encode_utf16().count() < max / 2)
+        .unwrap_or_default();
+    let mut remaining = max - extension.encode_utf16().count();
+    let mut shortened: String = name[..name.len() - extension.len()]
+        .chars()
+        .take_while(|c| {
+            let fits = c.len_utf16() <= remaining;
+            remaining = remaining.saturating_sub(c.len_utf16());
+            fits
+        })
+        .collect();
+    shortened.push_str(extension);
+    shortened
+}
+
+impl FilenameTemplate {
+    /// Applies the restrictions of Windows to the substituted values, like the forbidden
+    /// characters, the reserved device names such as `CON` or `NUL`, the trailing dots
+    /// and spaces, and the length of the path components.
+    ///
+    /// Enabled by default on Windows only, it makes the rendered paths portable.
+    pub fn windows_safe(mut self, value: bool) -> Self {
+        self.windows_safe = value;
+        self
+    }
+
+    /// Shortens the rendered paths to the given number of UTF-16 code units, like the
+    /// 259 of the Windows paths without the `\\?\` prefix, minus the length of the
+    /// directory the files are saved in.
+    ///
+    /// The `{filename}` values are shortened first, keeping their extension, then the
+    /// other substituted values, from the last one. The literal parts of the template
+    /// are kept, so a template longer than the budget on its own renders longer paths.
+    pub fn max_path_len(mut self, value: usize) -> Self {
+        self.max_path_len = Some(value);
+        self
+    }
+
+    /// Path of the file offered by the given response to the given request.
+    pub fn render(&self, info: &RequestInfo, response: &Response) -> PathBuf {
+        let mut parts = Vec::with_capacity(self.segments.len());
+        for segment in &self.segments {
+            let placeholder = match segment {
+                Segment::Literal(literal) => {
+                    parts.push((literal.clone(), None));
+                    continue;
+                }
+                Segment::Placeholder(placeholder) => *placeholder,
+            };
+            let value = match placeholder {
+                Placeholder::Bot => info.botname.to_string(),
+                Placeholder::Channel => info.channel.to_string(),
+                Placeholder::Filename => response.filename.clone(),
+                Placeholder::Id => info.id.to_string(),
+                Placeholder::Packnum => match info.pack {
+                    PackId::Number(packnum) => packnum.to_string(),
+                    PackId::Name(ref name) => name.clone(),
+                },
+                Placeholder::Server => info.server.to_string(),
+            };
+            parts.push((sanitize(&value, self.windows_safe), Some(placeholder)));
+        }
+        if let Some(max) = self.max_path_len {
+            self.fit(&mut parts, max);
+        }
+        PathBuf::from(parts.into_iter().map(|(part, _)| part).collect::<String>())
+    }
+
+    /// Shortens the substituted values of the given rendered parts, until the path fits
+    /// in the given number of UTF-16 code units.
+    fn fit(&self, parts: &mut [(String, Option<Placeholder>)], max: usize) {
+        let length: usize = parts
+            .iter()
+            .map(|(part, _)| part.encode_utf16().count())
+            .sum();
+        let mut excess = length.saturating_sub(max);
+        let placeholders: Vec<(usize, Placeholder)> = parts
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (_, placeholder))| Some((index, (*placeholder)?)))
+            .collect();
+        let filenames = placeholders
+            .iter()
+            .filter(|(_, placeholder)| *placeholder == Placeholder::Filename);
+        let others = placeholders
+            .iter()
+            .rev()
+            .filter(|(_, placeholder)| *placeholder != Placeholder::Filename);
+        let order: Vec<usize> = filenames.chain(others).map(|(index, _)| *index).collect();
+        for index in order {
+            if excess == 0 {
+                break;
+            }
+            let part = &mut parts[index].0;
+            let before = part.encode_utf16().count();
+            let mut shortened = shorten(part, before.saturating_sub(excess).max(1));
+            if self.windows_safe {
+                shortened.truncate(shortened.trim_end_matches(['.', ' ']).len());
+            }
+            if shortened.is_empty() {
+                shortened.push('_');
+            }
+            excess = excess.saturating_sub(before.saturating_sub(shortened.encode_utf16().count()));
+            *part = shortened;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{FilenameTemplate, TemplateError};
+    use crate::test_util::local_response;
+    use crate::{RequestId, RequestInfo, Response};
+
+    fn info(botname: &str) -> RequestInfo {
+        RequestInfo {
+            id: RequestId::next(),
+            server: "irc.example.net".into(),
+            channel: "#channel".into(),
+            botname: botname.into(),
+            pack: 42.into(),
+        }
+    }
+
+    fn response(filename: &str) -> Response {
+        local_response(filename, 5000, 1024)
+    }
+
+    #[test]
+    fn should_render_template() {
+        let info = info("bot");
+        let template: FilenameTemplate = "{bot}/{packnum}-{filename} {{x}}".parse().unwrap();
+        assert_eq!(
+            template.render(&info, &response("ubuntu.iso")),
+            Path::new("bot/42-ubuntu.iso {x}")
+        );
+    }
+
+    #[test]
+    fn should_not_escape_directory() {
+        let info = info("..");
+        let template: FilenameTemplate = "{bot}/{filename}".parse().unwrap();
+        assert_eq!(
+            template.render(&info, &response("../../etc/passwd")),
+            Path::new("_/.._.._etc_passwd")
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_template() {
+        assert!(matches!(
+            "{size}".parse::<FilenameTemplate>(),
+            Err(TemplateError::UnknownPlaceholder(name)) if name == "size"
+        ));
+        assert!(matches!(
+            "{bot".parse::<FilenameTemplate>(),
+            Err(TemplateError::Unclosed)
+        ));
+    }
+
+    #[test_case::test_case("CON", "_CON"; "reserved name")]
+    #[test_case::test_case("nul.tar.gz", "_nul.tar.gz"; "reserved name with extension")]
+    #[test_case::test_case("console.txt", "console.txt"; "not reserved")]
+    #[test_case::test_case("what? <yes>: \"a|b\"*.mkv", "what_ _yes__ _a_b__.mkv"; "forbidden characters")]
+    #[test_case::test_case("file. . ", "file"; "trailing dots and spaces")]
+    #[test_case::test_case(" .", "_"; "only dots and spaces")]
+    fn should_render_windows_safe_filename(filename: &str, expected: &str) {
+        let info = info("bot");
+        let template = FilenameTemplate::default().windows_safe(true);
+        assert_eq!(
+            template.render(&info, &response(filename)),
+            Path::new(expected)
+        );
+    }
+
+    #[test]
+    fn should_shorten_long_filename() {
+        let info = info("bot");
+        let template = FilenameTemplate::default().windows_safe(true);
+        let filename = format!("{}.mkv", "é".repeat(300));
+        let path = template.render(&info, &response(&filename));
+        let rendered = path.to_str().unwrap();
+        assert_eq!(rendered.encode_utf16().count(), 255);
+        assert!(rendered.ends_with("é.mkv"));
+    }
+
+    #[test]
+    fn should_fit_path_in_budget() {
+        let info = info("bot");
+        let filename = format!("{}.mkv", "a".repeat(50));
+        let template: FilenameTemplate = "{server}/{bot}/{filename}".parse().unwrap();
+        // the filename is shortened first, keeping its extension
+        assert_eq!(
+            template
+                .clone()
+                .max_path_len(30)
+                .render(&info, &response(&filename)),
+            Path::new("irc.example.net/bot/aaaaaa.mkv")
+        );
+        // then the other values, from the last one
+        assert_eq!(
+            template
+                .max_path_len(10)
+                .render(&info, &response(&filename)),
+            Path::new("irc.ex/b/a")
+        );
+    }
+}
@@ -0,0 +1,614 @@
+//! Utilities to test the XDCC transfers without any real IRC bot.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker, ready};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::time::Sleep;
+
+use crate::{ConnectFuture, Connector, Response, Transport};
+
+/// Size of the chunks written to the receiver.
+const CHUNK_SIZE: usize = 4096;
+
+/// DCC offer of the given file on the given local port, without sender nor token.
+pub(crate) fn local_response(filename: impl Into<String>, port: u16, filesize: u64) -> Response {
+    Response {
+        filename: filename.into(),
+        address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        port,
+        filesize,
+        sender: None,
+        token: None,
+    }
+}
+
+/// Fake DCC sender, serving a buffer to a single receiver on a local port.
+///
+/// The speed of the transfer can be limited and the connection dropped in the
+/// middle of the stream, to reproduce slow or unreliable bots.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use xdcc_request::test_util::FakeDccSender;
+///
+/// let sender = FakeDccSender::bind(vec![0; 1024]).await?.disconnect_after(512);
+/// let offer = sender.offer("file.bin");
+/// // send the offer to the code under test, then serve the transfer
+/// let sent = sender.serve().await?;
+/// assert_eq!(sent, 512);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FakeDccSender {
+    data: Vec<u8>,
+    disconnect_after: Option<u64>,
+    listener: TcpListener,
+    rate: Option<u64>,
+}
+
+impl FakeDccSender {
+    /// Listens on a local port to serve the given data.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no local port can be bound.
+    pub async fn bind(data: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(Self {
+            data: data.into(),
+            disconnect_after: None,
+            listener: TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?,
+            rate: None,
+        })
+    }
+
+    /// Drops the connection once the given number of bytes has been sent.
+    pub fn disconnect_after(mut self, bytes: u64) -> Self {
+        self.disconnect_after = Some(bytes);
+        self
+    }
+
+    /// Limits the speed of the transfer to the given number of bytes per second.
+    pub fn rate(mut self, bytes_per_second: u64) -> Self {
+        self.rate = Some(bytes_per_second.max(1));
+        self
+    }
+
+    /// Address the sender listens on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address of the socket cannot be read.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// DCC offer of the data, as the bot would decode it.
+    pub fn response(&self, filename: impl Into<String>) -> Response {
+        let port = self.local_addr().map_or(0, |address| address.port());
+        local_response(filename, port, self.data.len() as u64)
+    }
+
+    /// DCC offer of the data, as the bot would send it in a private message.
+    pub fn offer(&self, filename: &str) -> String {
+        self.response(filename).encode()
+    }
+
+    /// Accepts a single receiver and sends it the data.
+    ///
+    /// Returns the number of bytes sent before closing the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no receiver can be accepted, or if the receiver closes the
+    /// connection early.
+    pub async fn serve(self) -> io::Result<u64> {
+        let (mut stream, _address) = self.listener.accept().await?;
+        let limit = self
+            .disconnect_after
+            .map_or(self.data.len(), |bytes| {
+                usize::try_from(bytes).unwrap_or(usize::MAX)
+            })
+            .min(self.data.len());
+        let started_at = tokio::time::Instant::now();
+        let mut sent = 0;
+        for chunk in self.data[..limit].chunks(CHUNK_SIZE) {
+            stream.write_all(chunk).await?;
+            sent += chunk.len() as u64;
+            if let Some(rate) = self.rate {
+                let elapsed = Duration::from_secs_f64(sent as f64 / rate as f64);
+                tokio::time::sleep_until(started_at + elapsed).await;
+            }
+        }
+        stream.shutdown().await?;
+        Ok(sent)
+    }
+}
+
+/// Fake IRC server where a single XDCC bot answers the requests with the offers of its
+/// packs, to execute the requests end-to-end without any network.
+///
+/// The server completes the registration right away, and the bot welcomes the client in
+/// the first channel it joins, then answers `xdcc send #N` with the offer of the pack.
+/// The other commands are answered by the handlers of [`FakeIrcBot::with_handler`].
+///
+/// ```no_run
+/// # async fn run() -> xdcc_request::Result<()> {
+/// use xdcc_request::Engine;
+/// use xdcc_request::test_util::{FakeDccSender, FakeIrcBot};
+///
+/// let sender = FakeDccSender::bind(vec![0; 1024]).await?;
+/// let bot = FakeIrcBot::new("bot").with_pack(1, sender.offer("file.bin"));
+/// let engine = Engine::with_transport(bot);
+/// let response = engine
+///     .create_request("irc.example.net", "#channel", "bot", 1)
+///     .execute()
+///     .await?;
+/// // connect to the offered address while the sender serves the transfer
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FakeIrcBot {
+    /// Answer the lines of the client, in addition to the built-in answers.
+    handlers: Vec<Arc<Handler>>,
+    nickname: String,
+    /// Delay before sending the offers.
+    offer_delay: Duration,
+    /// Offers of the packs, indexed by number.
+    offers: HashMap<u64, String>,
+}
+
+/// Answers a line of the client with the lines to send back.
+type Handler = dyn Fn(&str) -> Vec<String> + Send + Sync;
+
+impl std::fmt::Debug for FakeIrcBot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(stringify!(FakeIrcBot))
+            .field("nickname", &self.nickname)
+            .field("offer_delay", &self.offer_delay)
+            .field("offers", &self.offers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FakeIrcBot {
+    /// Creates the bot of the given nickname, without any pack.
+    pub fn new(nickname: impl Into<String>) -> Self {
+        Self {
+            handlers: Vec::new(),
+            nickname: nickname.into(),
+            offer_delay: Duration::ZERO,
+            offers: HashMap::new(),
+        }
+    }
+
+    /// Sends the offers of the packs after the given delay, like the slow bots.
+    pub fn offer_delay(mut self, value: Duration) -> Self {
+        self.offer_delay = value;
+        self
+    }
+
+    /// Answers each line of the client, without its line ending, with the lines returned
+    /// by the given handler, like the replies of the server or the commands of other
+    /// dialects.
+    ///
+    /// ```
+    /// use xdcc_request::test_util::FakeIrcBot;
+    ///
+    /// let bot = FakeIrcBot::new("bot").with_handler(|line| {
+    ///     if line == "WHOIS bot" {
+    ///         vec![":irc.example.net 318 me bot :End of /WHOIS list.".into()]
+    ///     } else {
+    ///         Vec::new()
+    ///     }
+    /// });
+    /// ```
+    pub fn with_handler(
+        mut self,
+        handler: impl Fn(&str) -> Vec<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Answers the requests of the given pack with the given DCC offer, like the one of
+    /// [`FakeDccSender::offer`].
+    pub fn with_pack(mut self, number: u64, offer: impl Into<String>) -> Self {
+        self.offers.insert(number, offer.into());
+        self
+    }
+}
+
+impl Connector for FakeIrcBot {
+    fn connect<'a>(&'a self, _host: &'a str, _port: u16) -> ConnectFuture<'a> {
+        let stream = FakeIrcStream::new(self.clone());
+        Box::pin(std::future::ready(Ok(
+            Box::new(stream) as Box<dyn Transport>
+        )))
+    }
+}
+
+/// Connection to a [`FakeIrcBot`], answering each line as soon as it's written.
+#[derive(Debug)]
+struct FakeIrcStream {
+    bot: FakeIrcBot,
+    /// Nickname of the client, once identified.
+    client: String,
+    /// Set once the client quit.
+    closed: bool,
+    /// Answers sent once their instant is reached.
+    delayed: VecDeque<(tokio::time::Instant, String)>,
+    /// Partial line written by the client.
+    input: Vec<u8>,
+    /// Answers waiting to be read by the client.
+    output: VecDeque<u8>,
+    /// Waker of the read waiting for an answer.
+    reader: Option<Waker>,
+    /// Wait for the first delayed answer.
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl FakeIrcStream {
+    fn new(bot: FakeIrcBot) -> Self {
+        let mut stream = Self {
+            bot,
+            client: "*".into(),
+            closed: false,
+            delayed: VecDeque::new(),
+            input: Vec::new(),
+            output: VecDeque::new(),
+            reader: None,
+            sleep: None,
+        };
+        stream.send(":irc.example.net 376 * :End of /MOTD command.");
+        stream
+    }
+
+    /// Queues a line to be read by the client.
+    fn send(&mut self, line: &str) {
+        self.output.extend(line.as_bytes());
+        self.output.extend(b"\r\n");
+    }
+
+    /// Answers a line of the client.
+    fn answer(&mut self, line: &str) {
+        for handler in self.bot.handlers.clone() {
+            for reply in handler(line) {
+                self.send(&reply);
+            }
+        }
+        let prefix = format!(":{0}!{0}@irc.example.net", self.bot.nickname);
+        let (command, params) = line.split_once(' ').unwrap_or((line, ""));
+        match &*command.to_ascii_uppercase() {
+            "NICK" => self.client = params.trim_start_matches(':').to_owned(),
+            "JOIN" => {
+                let channel = params.split([',', ' ']).next().unwrap_or_default();
+                self.send(&format!("{prefix} PRIVMSG {channel} :welcome"));
+            }
+            "PRIVMSG" => {
+                let Some((target, text)) = params.split_once(" :") else {
+                    return;
+                };
+                let pack = text
+                    .to_ascii_lowercase()
+                    .strip_prefix("xdcc send #")
+                    .and_then(|number| number.trim().parse().ok());
+                if target.eq_ignore_ascii_case(&self.bot.nickname)
+                    && let Some(offer) = pack.and_then(|pack| self.bot.offers.get(&pack))
+                {
+                    let offer = format!("{prefix} PRIVMSG {} :{offer}", self.client);
+                    if self.bot.offer_delay.is_zero() {
+                        self.send(&offer);
+                    } else {
+                        let due = tokio::time::Instant::now() + self.bot.offer_delay;
+                        self.delayed.push_back((due, offer));
+                    }
+                }
+            }
+            "QUIT" => self.closed = true,
+            _ => {}
+        }
+    }
+}
+
+impl AsyncRead for FakeIrcStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.output.is_empty() && !this.closed {
+            let Some(&(due, _)) = this.delayed.front() else {
+                this.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            };
+            let sleep = this
+                .sleep
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(due)));
+            if sleep.as_mut().poll(cx).is_pending() {
+                this.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            this.sleep = None;
+            if let Some((_, line)) = this.delayed.pop_front() {
+                this.send(&line);
+            }
+        }
+        let size = buf.remaining().min(this.output.len());
+        let chunk: Vec<u8> = this.output.drain(..size).collect();
+        buf.put_slice(&chunk);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for FakeIrcStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        this.input.extend_from_slice(buf);
+        while let Some(end) = this.input.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = this.input.drain(..=end).collect();
+            this.answer(String::from_utf8_lossy(&line).trim_end());
+        }
+        if let Some(reader) = this.reader.take() {
+            reader.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.closed = true;
+        if let Some(reader) = this.reader.take() {
+            reader.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Transport wrapper injecting faults into an IRC or DCC stream.
+///
+/// The faults are deterministic for a given seed, so that the reconnection and
+/// resume logic can be exercised reliably in CI.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use std::time::Duration;
+/// use xdcc_request::test_util::FaultyStream;
+///
+/// let stream = tokio::net::TcpStream::connect("127.0.0.1:6667").await?;
+/// let stream = FaultyStream::new(stream)
+///     .latency(Duration::from_millis(50))
+///     .partial_reads(42)
+///     .disconnect_after(1024);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FaultyStream<S> {
+    inner: S,
+    disconnect_after: Option<u64>,
+    latency: Option<Duration>,
+    /// State of the generator of the read sizes, when the reads are partial.
+    partial_reads: Option<u64>,
+    /// Pending delay of the current read.
+    sleep: Option<Pin<Box<Sleep>>>,
+    transferred: u64,
+    waited: bool,
+}
+
+impl<S> FaultyStream<S> {
+    /// Wraps the given stream, without any fault until configured.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            disconnect_after: None,
+            latency: None,
+            partial_reads: None,
+            sleep: None,
+            transferred: 0,
+            waited: false,
+        }
+    }
+
+    /// Fails every read and write with [`io::ErrorKind::ConnectionReset`] once the given
+    /// number of bytes has been read and written.
+    pub fn disconnect_after(mut self, bytes: u64) -> Self {
+        self.disconnect_after = Some(bytes);
+        self
+    }
+
+    /// Delays every read by the given duration.
+    pub fn latency(mut self, value: Duration) -> Self {
+        self.latency = Some(value);
+        self
+    }
+
+    /// Returns only part of the available bytes on each read, with sizes derived from
+    /// the given seed.
+    pub fn partial_reads(mut self, seed: u64) -> Self {
+        self.partial_reads = Some(seed);
+        self
+    }
+
+    /// Returns the wrapped stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Number of bytes that can still be transferred before the disconnection.
+    fn remaining(&self) -> io::Result<usize> {
+        match self.disconnect_after {
+            Some(limit) if self.transferred >= limit => Err(io::ErrorKind::ConnectionReset.into()),
+            Some(limit) => Ok(usize::try_from(limit - self.transferred).unwrap_or(usize::MAX)),
+            None => Ok(usize::MAX),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FaultyStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Some(latency) = this.latency.filter(|_| !this.waited) {
+            let sleep = this
+                .sleep
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(latency)));
+            ready!(sleep.as_mut().poll(cx));
+            this.sleep = None;
+            this.waited = true;
+        }
+
+        let mut size = buf.remaining().min(this.remaining()?);
+        if let Some(ref mut state) = this.partial_reads {
+            *state = crate::identity::mix(state.wrapping_add(0x9e3779b97f4a7c15));
+            size = size.min(1 + (*state % 1024) as usize);
+        }
+        let mut chunk = vec![0; size];
+        let mut chunk_buf = ReadBuf::new(&mut chunk);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+        let read = chunk_buf.filled();
+        buf.put_slice(read);
+        this.transferred += read.len() as u64;
+        this.waited = false;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FaultyStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let size = buf.len().min(this.remaining()?);
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..size]))?;
+        this.transferred += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::{FakeDccSender, FakeIrcBot, FaultyStream};
+    use crate::{Engine, Response};
+
+    async fn receive(sender: FakeDccSender) -> (u64, Vec<u8>) {
+        let address = sender.response("file.bin").socket_addr();
+        let (sent, received) = tokio::join!(sender.serve(), async {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+        (sent.unwrap(), received)
+    }
+
+    #[tokio::test]
+    async fn should_serve_data() {
+        let data: Vec<u8> = (0..10_000u32).map(|value| value as u8).collect();
+        let sender = FakeDccSender::bind(data.clone()).await.unwrap();
+        let response = Response::decode(&sender.offer("file.bin")).unwrap();
+        assert_eq!(response.socket_addr(), sender.local_addr().unwrap());
+        assert_eq!(response.filesize, 10_000);
+        assert_eq!(receive(sender).await, (10_000, data));
+    }
+
+    #[tokio::test]
+    async fn should_execute_against_fake_bot() {
+        let data: Vec<u8> = (0..10_000u32).map(|value| value as u8).collect();
+        let sender = FakeDccSender::bind(data.clone()).await.unwrap();
+        let bot = FakeIrcBot::new("bot").with_pack(1, sender.offer("file.bin"));
+        let engine = Engine::with_transport(bot);
+        let response = engine
+            .create_request("irc.example.net", "#channel", "bot", 1)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(response.filename, "file.bin");
+        assert_eq!(receive(sender).await, (10_000, data));
+    }
+
+    #[tokio::test]
+    async fn should_disconnect_mid_stream() {
+        let sender = FakeDccSender::bind(vec![1; 10_000])
+            .await
+            .unwrap()
+            .disconnect_after(5_000);
+        assert_eq!(receive(sender).await, (5_000, vec![1; 5_000]));
+    }
+
+    #[tokio::test]
+    async fn should_read_partially() {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let mut stream = FaultyStream::new(client).partial_reads(42);
+        server.write_all(&[1; 10_000]).await.unwrap();
+        drop(server);
+        let mut buf = vec![0; 10_000];
+        let read = stream.read(&mut buf).await.unwrap();
+        assert!(read <= 1024);
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(read + rest.len(), 10_000);
+    }
+
+    #[tokio::test]
+    async fn should_disconnect_after_limit() {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let mut stream = FaultyStream::new(client).disconnect_after(100);
+        server.write_all(&[1; 1_000]).await.unwrap();
+        let mut buf = vec![0; 1_000];
+        stream.read_exact(&mut buf[..100]).await.unwrap();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+        assert!(stream.write_all(b"hello").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_delay_reads() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut stream = FaultyStream::new(client).latency(Duration::from_millis(50));
+        server.write_all(b"hello").await.unwrap();
+        let started_at = tokio::time::Instant::now();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert!(started_at.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,43 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Time spent in each step of a request.
+///
+/// The steps that didn't happen, like the connection when reusing a warm one, are zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Resolution of the server address and TCP connection.
+    pub connect: Duration,
+    /// Registration on the IRC server.
+    pub register: Duration,
+    /// Channel join, until the first private message.
+    pub join: Duration,
+    /// Wait for the DCC offer after sending the XDCC command.
+    pub offer_wait: Duration,
+    /// Download of the offered file, measured by `Transfer::download_with_timings` with
+    /// the `transfer` feature.
+    pub transfer: Duration,
+}
+
+impl Timings {
+    /// Total time spent in the request, and in the download when measured.
+    pub fn total(&self) -> Duration {
+        self.connect + self.register + self.join + self.offer_wait + self.transfer
+    }
+}
+
+/// Health report of an IRC server, returned by [`Engine::probe`](crate::Engine::probe).
+#[derive(Clone, Debug)]
+pub struct ServerProbe {
+    /// Address the connection was established with, unknown when connected by a custom
+    /// [`Connector`](crate::Connector).
+    pub address: Option<SocketAddr>,
+    /// Resolution of the server address and TCP connection.
+    pub connect: Duration,
+    /// Registration on the IRC server.
+    pub register: Duration,
+    /// Whether the connection is encrypted, with the TLS connector of the network or
+    /// the `STARTTLS` command, `false` when the server refused the upgrade, and `None`
+    /// when TLS wasn't attempted. A failed TLS handshake fails the probe instead.
+    pub tls: Option<bool>,
+}
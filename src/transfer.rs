@@ -0,0 +1,742 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error::{Error, Result, TransferError};
+use crate::{Event, EventKind, RequestInfo, Response, SocketOptions, Timings};
+
+/// Size of the buffer the data is received in.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Duration the peak speed is measured over.
+const PEAK_WINDOW: Duration = Duration::from_secs(1);
+
+/// Download of the file offered by a bot, from the endpoint advertised by its offer.
+///
+/// The passive offers are downloaded from the transfers returned by
+/// [`Presence::listen`](crate::Presence::listen) instead, as the bot connects to
+/// the receiver.
+///
+/// ```no_run
+/// # async fn run() -> xdcc_request::Result<()> {
+/// use std::time::Duration;
+/// use xdcc_request::{Engine, Transfer};
+///
+/// let engine = Engine::default();
+/// let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+/// let response = request.execute().await?;
+/// let mut data = Vec::new();
+/// Transfer::new(&response)
+///     .idle_timeout(Duration::from_secs(30))
+///     .download(&mut data)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Transfer {
+    /// Audit log the outcome of the transfer is stored in, when created by a presence.
+    #[cfg(feature = "audit")]
+    pub(crate) audit_log: Option<std::sync::Arc<crate::audit::AuditLog>>,
+    deadline: Option<Instant>,
+    /// Bus the events of the transfer are sent to, with the request of the offer, when
+    /// created by a presence.
+    pub(crate) events: Option<(tokio::sync::broadcast::Sender<Event>, RequestInfo)>,
+    filesize: u64,
+    idle_timeout: Duration,
+    /// Position the download starts at.
+    offset: u64,
+    /// Number of times the sender accepted to resume the file.
+    pub(crate) resumes: u32,
+    socket_options: SocketOptions,
+    source: Source,
+    /// Data of the partial file at the given position, compared with the received one.
+    verified: Option<(u64, Vec<u8>)>,
+}
+
+/// Statistics of a complete download, returned by [`Transfer::download_with_stats`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransferStats {
+    /// Position reached in the file, its size once the download is complete.
+    pub position: u64,
+    /// Number of bytes received, after the position the download started at.
+    pub bytes: u64,
+    /// Time spent receiving the data, once connected to the sender.
+    pub duration: Duration,
+    /// Average speed, in bytes per second.
+    pub average_speed: f64,
+    /// Highest speed over a second, in bytes per second.
+    pub peak_speed: f64,
+    /// Number of times the sender accepted to resume the file, with
+    /// [`Presence::resume`](crate::Presence::resume).
+    pub resumes: u32,
+}
+
+/// State of an interrupted download, persisted by the caller to continue it in another
+/// process, like after a restart.
+///
+/// The offer of the previous process is lost with its IRC connection, so the pack is
+/// requested again, and the new offer resumed at the position of the token when it
+/// [matches](ResumeToken::matches) the same file.
+///
+/// ```no_run
+/// # async fn run(saved: &str) -> xdcc_request::Result<()> {
+/// use xdcc_request::{Engine, ResumeToken};
+///
+/// let engine = Engine::default();
+/// let request = engine.create_request("irc.example.net", "#channel", "bot", 1);
+/// let (response, mut presence) = request.execute_and_stay().await?;
+/// let transfer = match ResumeToken::decode(saved) {
+///     Some(token) if token.matches(&response) => {
+///         presence.resume(&response, token.position).await?
+///     }
+///     _ => presence.transfer(&response),
+/// };
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeToken {
+    /// Offer of the interrupted download.
+    pub response: Response,
+    /// Position reached in the file, the size of the partial file.
+    pub position: u64,
+}
+
+/// Origin of the connection of a transfer.
+#[derive(Debug)]
+enum Source {
+    /// Connects to the endpoint advertised by the sender.
+    Connect(SocketAddr),
+    /// Accepts the connection of the sender of a passive offer, from one of the given
+    /// addresses.
+    Accept(TcpListener, Vec<IpAddr>),
+}
+
+impl Transfer {
+    /// Prepares the download of the file of the given offer.
+    pub fn new(response: &Response) -> Self {
+        Self {
+            #[cfg(feature = "audit")]
+            audit_log: None,
+            deadline: None,
+            events: None,
+            filesize: response.filesize,
+            idle_timeout: Duration::from_secs(60),
+            offset: 0,
+            resumes: 0,
+            socket_options: SocketOptions::default(),
+            source: Source::Connect(response.socket_addr()),
+            verified: None,
+        }
+    }
+
+    /// Prepares the download of the file of the given passive offer, accepting the
+    /// connection of the sender with the given listener.
+    ///
+    /// Only the address of the offer and the host of its sender, when it's an address,
+    /// may connect, so no other peer can inject its own data.
+    pub(crate) fn accept(response: &Response, listener: TcpListener) -> Self {
+        let host = response
+            .sender
+            .as_deref()
+            .and_then(|sender| sender.rsplit_once('@'))
+            .and_then(|(_, host)| host.parse().ok());
+        let senders = std::iter::once(response.address)
+            .chain(host)
+            .map(|address: IpAddr| address.to_canonical())
+            .collect();
+        Self {
+            source: Source::Accept(listener, senders),
+            ..Self::new(response)
+        }
+    }
+
+    /// Cancels the download still running at the given instant, failing with
+    /// [`Error::DeadlineExceeded`], like at the end of the lifetime of the request.
+    pub fn deadline(mut self, value: Instant) -> Self {
+        self.deadline = Some(value);
+        self
+    }
+
+    /// Starts the download at the given position, once the sender accepted to resume the
+    /// transfer there, like with [`Presence::resume`](crate::Presence::resume).
+    ///
+    /// Only the data following the position is written, to append to the partial file.
+    pub fn offset(mut self, position: u64) -> Self {
+        self.offset = position;
+        self
+    }
+
+    /// Compares the data received at the given position with the given data of the
+    /// partial file, failing with [`TransferError::Mismatch`] if they differ, before
+    /// appending to the partial file.
+    ///
+    /// Resuming a few kilobytes before the end of the partial file, with the last ones
+    /// as data, detects a corrupted partial file instead of completing it with the rest
+    /// of the file. The data up to the end of the given one is not written, as the
+    /// partial file already has it.
+    ///
+    /// ```no_run
+    /// # use xdcc_request::{Presence, Response};
+    /// # async fn run(mut presence: Presence, response: Response, partial: &[u8]) -> xdcc_request::Result<()> {
+    /// // the last 4 KiB of the partial file are sent again and compared
+    /// let position = partial.len().saturating_sub(4096);
+    /// let transfer = presence.resume(&response, position as u64).await?;
+    /// let transfer = transfer.verify(position as u64, partial[position..].to_vec());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn verify(mut self, position: u64, data: Vec<u8>) -> Self {
+        self.verified = Some((position, data));
+        self
+    }
+
+    /// TCP options applied to the connection with the sender, like the
+    /// [`EngineBuilder::socket_options`](crate::EngineBuilder::socket_options) of the
+    /// IRC connections.
+    pub fn socket_options(mut self, value: SocketOptions) -> Self {
+        self.socket_options = value;
+        self
+    }
+
+    /// Maximum duration to connect to the sender or to be connected to, and without receiving any data once
+    /// connected, 60 seconds by default.
+    pub fn idle_timeout(mut self, value: Duration) -> Self {
+        self.idle_timeout = value;
+        self
+    }
+
+    /// Connects to the sender and writes the file to the given writer, acknowledging
+    /// the received bytes as the DCC protocol requires.
+    ///
+    /// Returns the position reached in the file, its size once the download is complete.
+    /// The data sent past the size of the file is not read. An offer of size zero doesn't
+    /// tell the size, the file then ends when the sender closes the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransferError::Incomplete`] if the sender closes the connection before
+    /// sending the whole file, or another [`TransferError`] if the connection fails,
+    /// stays idle for too long, or the writer fails.
+    pub async fn download(&self, writer: impl AsyncWrite + Unpin) -> Result<u64> {
+        self.download_with_stats(writer)
+            .await
+            .map(|stats| stats.position)
+    }
+
+    /// Downloads the file like [`Transfer::download`], storing the time spent in the
+    /// given timings, like the ones returned by
+    /// [`Request::execute_with_timings`](crate::Request::execute_with_timings).
+    ///
+    /// The time is stored even when the download fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails, see [`Transfer::download`].
+    pub async fn download_with_timings(
+        &self,
+        writer: impl AsyncWrite + Unpin,
+        timings: &mut Timings,
+    ) -> Result<u64> {
+        let started_at = Instant::now();
+        let result = self.download(writer).await;
+        timings.transfer = started_at.elapsed();
+        result
+    }
+
+    /// Downloads the file like [`Transfer::download`], returning the statistics of the
+    /// download, like its speed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails, see [`Transfer::download`].
+    pub async fn download_with_stats(
+        &self,
+        writer: impl AsyncWrite + Unpin,
+    ) -> Result<TransferStats> {
+        let receive = self.receive(writer);
+        let download = async {
+            match self.deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), receive)
+                    .await
+                    .unwrap_or(Err(Error::DeadlineExceeded)),
+                None => receive.await,
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "transfer",
+            size = self.filesize,
+            offset = self.offset,
+            otel.status_code = tracing::field::Empty,
+            otel.status_description = tracing::field::Empty,
+        );
+        let result = instrument!(download, span.clone()).await;
+        #[cfg(feature = "tracing")]
+        crate::telemetry::record_span_outcome(&span, &result);
+        crate::telemetry::record_transfer(&result);
+        match result {
+            Ok(stats) => self.emit(EventKind::TransferCompleted(stats)),
+            Err(ref err) => self.emit(EventKind::TransferFailed(err.to_string())),
+        }
+        #[cfg(feature = "audit")]
+        if let (Some(audit_log), Some((_, request))) = (&self.audit_log, &self.events)
+            && let Err(err) = audit_log.record_transfer(request, result.as_ref()).await
+        {
+            warn!(
+                "unable to store the transfer of request {} in the audit log: {err}",
+                request.id
+            );
+        }
+        result
+    }
+
+    /// Sends an event about this transfer to the subscribers of the engine, if created
+    /// by a presence.
+    fn emit(&self, kind: EventKind) {
+        let Some((ref events, ref request)) = self.events else {
+            return;
+        };
+        if let EventKind::TransferFailed(ref reason) = kind {
+            warn!("transfer of request {} failed: {reason}", request.id);
+        }
+        // no subscriber is not an error
+        let _ = events.send(Event {
+            request: request.clone(),
+            kind,
+        });
+    }
+
+    /// Compares the given data, received at the given position, with the data of the
+    /// partial file to verify, and returns the part to write, following the end of the
+    /// partial file.
+    fn check<'a>(&self, position: u64, data: &'a [u8]) -> Result<&'a [u8]> {
+        let Some((start, ref expected)) = self.verified else {
+            return Ok(data);
+        };
+        let end = start + expected.len() as u64;
+        let from = start.max(position);
+        let to = end.min(position + data.len() as u64);
+        if from < to {
+            let received = &data[(from - position) as usize..(to - position) as usize];
+            let expected = &expected[(from - start) as usize..(to - start) as usize];
+            if let Some(index) = received.iter().zip(expected).position(|(a, b)| a != b) {
+                return Err(TransferError::Mismatch {
+                    position: from + index as u64,
+                }
+                .into());
+            }
+        }
+        let stored = end.saturating_sub(position).min(data.len() as u64);
+        Ok(&data[stored as usize..])
+    }
+
+    /// Connects to the sender and receives the file, without deadline.
+    async fn receive(&self, mut writer: impl AsyncWrite + Unpin) -> Result<TransferStats> {
+        let connect = async {
+            match self.source {
+                Source::Connect(address) => TcpStream::connect(address).await,
+                Source::Accept(ref listener, ref senders) => loop {
+                    let (stream, peer) = listener.accept().await?;
+                    if senders.contains(&peer.ip().to_canonical()) {
+                        break Ok(stream);
+                    }
+                    warn!("closing the connection of {peer}, not the sender of the offer");
+                },
+            }
+        };
+        let mut stream = tokio::time::timeout(self.idle_timeout, connect)
+            .await
+            .map_err(|_| TransferError::Timeout)?
+            .map_err(TransferError::Connect)?;
+        self.socket_options
+            .apply(&stream)
+            .map_err(TransferError::Connect)?;
+        self.emit(EventKind::TransferStarted(self.offset));
+        let started_at = Instant::now();
+        let mut window = (started_at, 0);
+        let mut peak_speed = 0f64;
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut received = self.offset;
+        let known_size = self.filesize > 0;
+        loop {
+            let remaining = if known_size {
+                self.filesize.saturating_sub(received)
+            } else {
+                BUFFER_SIZE as u64
+            };
+            if remaining == 0 {
+                break;
+            }
+            // the last read stops at the end of the file, ignoring the data sent past it
+            let chunk = &mut buffer[..remaining.min(BUFFER_SIZE as u64) as usize];
+            let read = tokio::time::timeout(self.idle_timeout, stream.read(chunk))
+                .await
+                .map_err(|_| TransferError::Timeout)?
+                .map_err(TransferError::Io)?;
+            if read == 0 {
+                break;
+            }
+            let data = self.check(received, &buffer[..read])?;
+            writer.write_all(data).await.map_err(TransferError::Io)?;
+            received += read as u64;
+            crate::telemetry::record_transferred(read);
+            window.1 += read;
+            let elapsed = window.0.elapsed();
+            if elapsed >= PEAK_WINDOW {
+                peak_speed = peak_speed.max(window.1 as f64 / elapsed.as_secs_f64());
+                window = (Instant::now(), 0);
+                self.emit(EventKind::TransferProgress(received));
+            }
+            // the acknowledgement is the total received, truncated to 32 bits past 4 GiB,
+            // and the sender may already be gone after the last one, which is unknown
+            // without the size
+            let ack = (received as u32).to_be_bytes();
+            if let Err(err) = stream.write_all(&ack).await
+                && known_size
+                && received < self.filesize
+            {
+                return Err(TransferError::Io(err).into());
+            }
+        }
+        writer.flush().await.map_err(TransferError::Io)?;
+
+        if known_size && received < self.filesize {
+            return Err(TransferError::Incomplete {
+                received,
+                expected: self.filesize,
+            }
+            .into());
+        }
+        let duration = started_at.elapsed();
+        let bytes = received - self.offset;
+        let average_speed = if duration.is_zero() {
+            0.0
+        } else {
+            bytes as f64 / duration.as_secs_f64()
+        };
+        Ok(TransferStats {
+            position: received,
+            bytes,
+            duration,
+            average_speed,
+            // a download shorter than the window is as fast as its average
+            peak_speed: peak_speed.max(average_speed),
+            resumes: self.resumes,
+        })
+    }
+}
+
+impl ResumeToken {
+    /// Describes the download of the given offer, interrupted at the given position,
+    /// like the one of [`TransferError::Incomplete`].
+    pub fn new(response: &Response, position: u64) -> Self {
+        Self {
+            response: response.clone(),
+            position,
+        }
+    }
+
+    /// Whether the given offer is the one of the same file, with the same name and size,
+    /// so the download can continue at the position of the token.
+    pub fn matches(&self, response: &Response) -> bool {
+        response.filename == self.response.filename && response.filesize == self.response.filesize
+    }
+
+    /// Encodes the token as a single line, the position followed by the `DCC SEND`
+    /// command message of the offer.
+    ///
+    /// The sender of the offer is not part of the token.
+    pub fn encode(&self) -> String {
+        format!("{} {}", self.position, self.response.encode())
+    }
+
+    /// Decodes a token encoded with [`ResumeToken::encode`].
+    ///
+    /// Returns `None` if the token is not valid.
+    pub fn decode(token: &str) -> Option<Self> {
+        let (position, response) = token.trim().split_once(' ')?;
+        Some(Self {
+            response: Response::decode(response)?,
+            position: position.parse().ok()?,
+        })
+    }
+}
+
+impl Response {
+    /// Downloads the offered file to the given writer, with the default settings of
+    /// [`Transfer`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails, see [`Transfer::download`].
+    pub async fn download(&self, writer: impl AsyncWrite + Unpin) -> Result<u64> {
+        Transfer::new(self).download(writer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+    use crate::test_util::local_response;
+    use crate::{Error, Response, ResumeToken, Timings, Transfer, TransferError};
+
+    /// Sends the given data, then closes the connection once acknowledged, and returns
+    /// the last acknowledgement.
+    async fn serve(listener: TcpListener, data: &[u8]) -> u32 {
+        let (stream, _) = listener.accept().await.unwrap();
+        serve_stream(stream, data).await
+    }
+
+    /// Sends the given data like [`serve`], on an accepted connection.
+    async fn serve_stream(mut stream: TcpStream, data: &[u8]) -> u32 {
+        stream.write_all(data).await.unwrap();
+        let mut ack = [0; 4];
+        while stream.read_exact(&mut ack).await.is_ok()
+            && u32::from_be_bytes(ack) as usize != data.len()
+        {}
+        u32::from_be_bytes(ack)
+    }
+
+    async fn offer(data: &[u8]) -> (TcpListener, Response) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let response = local_response("file.bin", port, data.len() as u64);
+        (listener, response)
+    }
+
+    #[tokio::test]
+    async fn should_download_and_acknowledge() {
+        let data: Vec<u8> = (0..100_000u32).map(|value| value as u8).collect();
+        let (listener, response) = offer(&data).await;
+        let mut received = Vec::new();
+        let (ack, result) = tokio::join!(serve(listener, &data), response.download(&mut received));
+        assert_eq!(result.unwrap(), 100_000);
+        assert_eq!(ack, 100_000);
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn should_measure_download() {
+        let data = vec![1; 100_000];
+        let (listener, response) = offer(&data).await;
+        let transfer = Transfer::new(&response).offset(40_000);
+        let (_, result) = tokio::join!(
+            serve(listener, &data[40_000..]),
+            transfer.download_with_stats(tokio::io::sink())
+        );
+        let stats = result.unwrap();
+        assert_eq!(stats.position, 100_000);
+        assert_eq!(stats.bytes, 60_000);
+        assert!(stats.average_speed > 0.0);
+        assert!(stats.peak_speed >= stats.average_speed);
+        assert_eq!(stats.resumes, 0);
+    }
+
+    #[tokio::test]
+    async fn should_time_download() {
+        let data = vec![1; 10_000];
+        let (listener, response) = offer(&data).await;
+        let mut timings = Timings {
+            offer_wait: Duration::from_secs(1),
+            ..Timings::default()
+        };
+        let transfer = Transfer::new(&response);
+        let (_, result) = tokio::join!(
+            async {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                serve_stream(stream, &data).await
+            },
+            transfer.download_with_timings(tokio::io::sink(), &mut timings)
+        );
+        assert_eq!(result.unwrap(), 10_000);
+        assert!(timings.transfer >= Duration::from_millis(50));
+        assert_eq!(timings.total(), timings.offer_wait + timings.transfer);
+    }
+
+    #[tokio::test]
+    async fn should_detect_incomplete_transfer() {
+        let data = vec![1; 10_000];
+        let (listener, response) = offer(&data).await;
+        let mut received = Vec::new();
+        let (_, result) = tokio::join!(
+            serve(listener, &data[..4_000]),
+            response.download(&mut received)
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::Transfer(TransferError::Incomplete {
+                received: 4_000,
+                expected: 10_000
+            })
+        ));
+        assert_eq!(received.len(), 4_000);
+    }
+
+    #[tokio::test]
+    async fn should_download_until_closed_without_size() {
+        let data = vec![1; 100_000];
+        let (listener, mut response) = offer(&data).await;
+        response.filesize = 0;
+        let mut received = Vec::new();
+        let (ack, result) = tokio::join!(serve(listener, &data), response.download(&mut received));
+        assert_eq!(result.unwrap(), 100_000);
+        assert_eq!(ack, 100_000);
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn should_ignore_data_past_size() {
+        let data: Vec<u8> = (0..10_000u32).map(|value| value as u8).collect();
+        let (listener, mut response) = offer(&data).await;
+        response.filesize = 4_000;
+        let mut received = Vec::new();
+        let (_, result) = tokio::join!(serve(listener, &data), response.download(&mut received));
+        assert_eq!(result.unwrap(), 4_000);
+        assert_eq!(received, &data[..4_000]);
+    }
+
+    #[tokio::test]
+    async fn should_resume_at_offset() {
+        let data: Vec<u8> = (0..10_000u32).map(|value| value as u8).collect();
+        let (listener, response) = offer(&data).await;
+        let mut received = Vec::new();
+        let transfer = Transfer::new(&response).offset(4_000);
+        let (_, result) = tokio::join!(
+            async {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                stream.write_all(&data[4_000..]).await.unwrap();
+                let mut ack = [0; 4];
+                // the acknowledgements count the resumed part
+                while stream.read_exact(&mut ack).await.is_ok() && u32::from_be_bytes(ack) != 10_000
+                {
+                }
+            },
+            transfer.download(&mut received)
+        );
+        assert_eq!(result.unwrap(), 10_000);
+        assert_eq!(received, &data[4_000..]);
+    }
+
+    #[tokio::test]
+    async fn should_only_accept_sender() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sender = Ipv4Addr::new(127, 0, 0, 2);
+        let response = Response {
+            filename: "file.bin".into(),
+            address: sender.into(),
+            port: 0,
+            filesize: 5,
+            sender: Some("bot!bot@irc.example.net".into()),
+            token: Some("42".into()),
+        };
+        let transfer = Transfer::accept(&response, listener);
+        let mut received = Vec::new();
+        let (_, result) = tokio::join!(
+            async {
+                // another peer connects first, from a different address
+                let mut foreign = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+                    .await
+                    .unwrap();
+                foreign.write_all(b"evil!").await.unwrap();
+                let socket = TcpSocket::new_v4().unwrap();
+                socket.bind((sender, 0).into()).unwrap();
+                let mut stream = socket
+                    .connect((Ipv4Addr::LOCALHOST, port).into())
+                    .await
+                    .unwrap();
+                stream.write_all(b"hello").await.unwrap();
+                let mut ack = [0; 4];
+                stream.read_exact(&mut ack).await.unwrap();
+            },
+            transfer.download(&mut received)
+        );
+        assert_eq!(result.unwrap(), 5);
+        assert_eq!(received, b"hello");
+    }
+
+    #[tokio::test]
+    async fn should_cancel_at_deadline() {
+        let (listener, response) = offer(&[1; 10_000]).await;
+        let transfer =
+            Transfer::new(&response).deadline(Instant::now() + Duration::from_millis(100));
+        let mut received = Vec::new();
+        // the sender accepts the connection without sending anything
+        let (_accepted, result) = tokio::join!(listener.accept(), transfer.download(&mut received));
+        assert!(matches!(result.unwrap_err(), Error::DeadlineExceeded));
+    }
+
+    #[test]
+    fn should_encode_resume_token() {
+        let mut response = local_response("my file \"1\".bin", 0, 10_000);
+        response.token = Some("42".into());
+        let token = ResumeToken::new(&response, 4_000);
+        let encoded = token.encode();
+        assert_eq!(
+            encoded,
+            "4000 DCC SEND \"my file \\\"1\\\".bin\" 2130706433 0 10000 42"
+        );
+        assert_eq!(ResumeToken::decode(&encoded), Some(token));
+        assert_eq!(ResumeToken::decode("4000"), None);
+        assert_eq!(
+            ResumeToken::decode("start DCC SEND file.bin 2130706433 5000 10"),
+            None
+        );
+    }
+
+    #[test]
+    fn should_match_offer_of_same_file() {
+        let token = ResumeToken::new(&local_response("file.bin", 5000, 10_000), 4_000);
+        assert!(token.matches(&local_response("file.bin", 6000, 10_000)));
+        assert!(!token.matches(&local_response("file.bin", 5000, 20_000)));
+        assert!(!token.matches(&local_response("other.bin", 5000, 10_000)));
+    }
+
+    #[tokio::test]
+    async fn should_verify_resumed_data() {
+        let data: Vec<u8> = (0..10_000u32).map(|value| value as u8).collect();
+        let (listener, response) = offer(&data).await;
+        let mut received = Vec::new();
+        // the partial file has the first 6000 bytes, the last 2000 of them sent again
+        let transfer = Transfer::new(&response)
+            .offset(4_000)
+            .verify(4_000, data[4_000..6_000].to_vec());
+        let (_, result) = tokio::join!(
+            serve(listener, &data[4_000..]),
+            transfer.download(&mut received)
+        );
+        assert_eq!(result.unwrap(), 10_000);
+        assert_eq!(received, &data[6_000..]);
+    }
+
+    #[tokio::test]
+    async fn should_detect_corrupted_partial_file() {
+        let data: Vec<u8> = (0..10_000u32).map(|value| value as u8).collect();
+        let (listener, response) = offer(&data).await;
+        let mut partial = data[4_000..6_000].to_vec();
+        partial[1_500] ^= 0xff;
+        let mut received = Vec::new();
+        let transfer = Transfer::new(&response)
+            .offset(4_000)
+            .verify(4_000, partial);
+        let (_, result) = tokio::join!(
+            serve(listener, &data[4_000..]),
+            transfer.download(&mut received)
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::Transfer(TransferError::Mismatch { position: 5_500 })
+        ));
+        assert!(received.is_empty());
+    }
+}
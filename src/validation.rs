@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use irc::proto::{Command, Message};
+
+use crate::error::{ProtocolError, Result};
+
+/// Delay without any new line after which the reply of the bot is considered complete.
+pub(crate) const REPLY_QUIET_PERIOD: Duration = Duration::from_secs(1);
+
+/// Extracts the text of the given message when it's sent by the bot, known by the
+/// given nicknames.
+pub(crate) fn bot_line(message: Message, botnames: &[&str]) -> Option<String> {
+    if !message.source_nickname().is_some_and(|nickname| {
+        botnames
+            .iter()
+            .any(|botname| nickname.eq_ignore_ascii_case(botname))
+    }) {
+        return None;
+    }
+    match message.command {
+        Command::NOTICE(_, text) | Command::PRIVMSG(_, text) => Some(text),
+        _ => None,
+    }
+}
+
+/// Waits for the first line sent by the bot.
+///
+/// Returns an error if the stream ends or fails.
+pub(crate) async fn wait_for_reply(
+    mut stream: impl Stream<Item = Result<Message>> + Unpin,
+    botnames: &[&str],
+) -> Result<String> {
+    while let Some(message) = stream.next().await.transpose()? {
+        if let Some(line) = bot_line(message, botnames) {
+            return Ok(line);
+        }
+    }
+
+    Err(ProtocolError::ConnectionClosed.into())
+}
+
+/// Collects the lines sent by the bot until it stays quiet for the given duration,
+/// or the stream ends.
+pub(crate) async fn collect_reply(
+    mut stream: impl Stream<Item = Result<Message>> + Unpin,
+    botnames: &[&str],
+    quiet: Duration,
+) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    while let Ok(next) = tokio::time::timeout(quiet, stream.next()).await {
+        let Some(message) = next.transpose()? else {
+            break;
+        };
+        lines.extend(bot_line(message, botnames));
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+    use irc::proto::{Command, Message, Prefix};
+
+    use super::*;
+
+    fn notice(source: &str, text: &str) -> Result<Message> {
+        Ok(Message {
+            tags: None,
+            prefix: Some(Prefix::new_from_str(source)),
+            command: Command::NOTICE("nickname".into(), text.into()),
+        })
+    }
+
+    #[tokio::test]
+    async fn should_collect_reply_of_bot() {
+        let mut stream = stream::iter(vec![
+            notice("someone", "hello world"),
+            notice("Bot", "Pack Info for Pack #1:"),
+            notice("bot", " Filename       ubuntu.iso"),
+        ]);
+        let first = wait_for_reply(&mut stream, &["bot"]).await.unwrap();
+        let rest = collect_reply(&mut stream, &["bot"], REPLY_QUIET_PERIOD)
+            .await
+            .unwrap();
+        assert_eq!(first, "Pack Info for Pack #1:");
+        assert_eq!(rest, [" Filename       ubuntu.iso"]);
+    }
+}
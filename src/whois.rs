@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use irc::proto::{Command, Message, Response};
+
+use crate::error::{ProtocolError, Result};
+
+/// Details of a user, as answered by the server to `WHOIS`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Whois {
+    /// Channels the user is in, without the membership prefixes like `@`.
+    pub(crate) channels: Vec<String>,
+    /// Duration since the last message of the user, when shared by the server.
+    pub(crate) idle: Option<Duration>,
+}
+
+/// Waits for the answer of the server to `WHOIS` on the given nickname.
+///
+/// Returns `None` when no user has this nickname, or an error if the stream ends or fails.
+pub(crate) async fn wait_for_whois(
+    mut stream: impl Stream<Item = Result<Message>> + Unpin,
+    nickname: &str,
+) -> Result<Option<Whois>> {
+    let mut whois = Whois::default();
+    while let Some(message) = stream.next().await.transpose()? {
+        let Command::Response(response, args) = message.command else {
+            continue;
+        };
+        // the replies are made of the client nickname, the target nickname, then the details
+        if !args
+            .get(1)
+            .is_some_and(|target| target.eq_ignore_ascii_case(nickname))
+        {
+            continue;
+        }
+        match response {
+            Response::ERR_NOSUCHNICK => return Ok(None),
+            Response::RPL_ENDOFWHOIS => return Ok(Some(whois)),
+            Response::RPL_WHOISCHANNELS => {
+                let channels = args.last().into_iter().flat_map(|text| text.split(' '));
+                whois.channels.extend(
+                    channels
+                        .map(|channel| channel.trim_start_matches(['~', '&', '@', '%', '+']))
+                        .filter(|channel| !channel.is_empty())
+                        .map(str::to_owned),
+                );
+            }
+            Response::RPL_WHOISIDLE => {
+                whois.idle = args
+                    .get(2)
+                    .and_then(|idle| idle.parse().ok())
+                    .map(Duration::from_secs);
+            }
+            _ => {}
+        }
+    }
+
+    Err(ProtocolError::ConnectionClosed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::stream;
+    use irc::proto::{Command, Message, Response};
+
+    use super::{Whois, wait_for_whois};
+
+    fn reply(response: Response, args: &[&str]) -> crate::Result<Message> {
+        Ok(Message {
+            tags: None,
+            prefix: Some("irc.example.net".into()),
+            command: Command::Response(response, args.iter().map(|arg| arg.to_string()).collect()),
+        })
+    }
+
+    #[tokio::test]
+    async fn should_collect_whois_details() {
+        let mut stream = stream::iter(vec![
+            reply(
+                Response::RPL_WHOISUSER,
+                &["me", "Bot", "bot", "example.net", "*", "Bot"],
+            ),
+            reply(
+                Response::RPL_WHOISCHANNELS,
+                &["me", "Bot", "@#files +#chat"],
+            ),
+            reply(
+                Response::RPL_WHOISIDLE,
+                &["me", "Bot", "42", "1700000000", "seconds idle"],
+            ),
+            reply(
+                Response::RPL_ENDOFWHOIS,
+                &["me", "Bot", "End of /WHOIS list."],
+            ),
+        ]);
+        assert_eq!(
+            wait_for_whois(&mut stream, "bot").await.unwrap(),
+            Some(Whois {
+                channels: vec!["#files".into(), "#chat".into()],
+                idle: Some(Duration::from_secs(42)),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn should_detect_missing_user() {
+        let mut stream = stream::iter(vec![
+            reply(
+                Response::ERR_NOSUCHNICK,
+                &["me", "bot", "No such nick/channel"],
+            ),
+            reply(
+                Response::RPL_ENDOFWHOIS,
+                &["me", "bot", "End of /WHOIS list."],
+            ),
+        ]);
+        assert_eq!(wait_for_whois(&mut stream, "bot").await.unwrap(), None);
+    }
+}